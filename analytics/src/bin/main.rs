@@ -61,26 +61,35 @@ fn sample_request() -> ReqItem<'static> {
         time: 1_786_123_989,
         body: None,
         headers: vec![
-            ("host".into(), "qdat.net".into()),
-            ("connection".into(), "keep-alive".into()),
+            ("host".into(), "qdat.net".as_bytes()),
+            ("connection".into(), "keep-alive".as_bytes()),
             (
                 "user-agent".into(),
-                "Mozilla/5.0 (compatible; AntibotDetector/1.0; +https://proxybase.xyz)".into(),
+                "Mozilla/5.0 (compatible; AntibotDetector/1.0; +https://proxybase.xyz)".as_bytes(),
             ),
             (
                 "accept".into(),
-                "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8".into(),
+                "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8".as_bytes(),
             ),
-            ("accept-language".into(), "*".into()),
-            ("sec-fetch-mode".into(), "cors".into()),
-            ("pragma".into(), "no-cache".into()),
-            ("cache-control".into(), "no-cache".into()),
-            ("accept-encoding".into(), "br, gzip, deflate".into()),
+            ("accept-language".into(), "*".as_bytes()),
+            ("sec-fetch-mode".into(), "cors".as_bytes()),
+            ("pragma".into(), "no-cache".as_bytes()),
+            ("cache-control".into(), "no-cache".as_bytes()),
+            ("accept-encoding".into(), "br, gzip, deflate".as_bytes()),
         ],
         host: "qdat.net".into(),
         proto: Protocol::Https as u16,
         location: "FR".into(),
         tls_fp: [0u8; 16],
+        time_us: 0,
+        ttfb: 0,
+        duration: 0,
+        bytes_in: 0,
+        bytes_out: 0,
+        body_len: 0,
+        body_hash: [0; 32],
+        source: "",
+        seq: 0,
     }
 }
 
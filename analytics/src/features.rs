@@ -199,7 +199,7 @@ impl FeatureExtractor {
         let headers_lower: Vec<(String, String)> = item
             .headers
             .iter()
-            .map(|&(k, v)| (k.to_ascii_lowercase(), v.into()))
+            .map(|&(k, v)| (k.to_ascii_lowercase(), String::from_utf8_lossy(v).into_owned()))
             .collect();
         let header_map: HashMap<&str, &str> = headers_lower
             .iter()
@@ -337,8 +337,7 @@ impl FeatureExtractor {
                     .iter()
                     .find(|(k, _)| k.eq_ignore_ascii_case("user-agent"))
                     .map(|&(_, v)| v)
-                    .unwrap_or("")
-                    .as_bytes(),
+                    .unwrap_or(b""),
             ),
             is_static_asset: is_static,
         });
@@ -1,4 +1,4 @@
-export function make_entry(status, method, uri, ua, referer, ip, port, time, body, headers, host, proto, location, tls_fp) {
+export function make_entry(status, method, uri, ua, referer, ip, port, time, body, headers, host, proto, location, tls_fp, ttfb, duration, bytes_in, bytes_out, body_len, body_hash, source, seq) {
     return {
         status,
         method,
@@ -13,6 +13,14 @@ export function make_entry(status, method, uri, ua, referer, ip, port, time, bod
         host,
         proto,
         location,
-        tls_fp
+        tls_fp,
+        ttfb,
+        duration,
+        bytes_in,
+        bytes_out,
+        body_len,
+        body_hash,
+        source,
+        seq
     };
 }
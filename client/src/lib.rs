@@ -1,17 +1,18 @@
 use std::{
     collections::{BTreeMap, HashMap, VecDeque},
     net::Ipv6Addr,
-    ops::Range
+    ops::{Deref, DerefMut, Range}
 };
 
 use clog_core::{
     BatchHeader, PacketType, SyncHeader,
-    filter::{Filter, FilterCtx},
+    aggregate::Aggregation,
+    filter::{BlockFilter, Filter, FilterCtx},
     headers_string,
     shema::{self, Shema},
 };
 use clog_ws_api::{ClientMessage, ServerMessage};
-use js_sys::{BigInt, Function, Uint8Array};
+use js_sys::{Array, BigInt, Function, Uint8Array};
 use time::OffsetDateTime;
 use wasm_bindgen::{JsCast, JsValue, prelude::wasm_bindgen};
 use web_sys::{BinaryType, Event, MessageEvent, WebSocket};
@@ -29,17 +30,25 @@ pub fn start() {
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
 }
 
-#[wasm_bindgen]
-pub struct Client {
+/// The blocks and rows received over one websocket, for any shema `S`.
+pub struct Connection<S> {
     websocket: WebSocket,
 
-    entries: BTreeMap<u64, Builder>,
-    current: Builder,
+    entries: BTreeMap<u64, S>,
+    current: S,
     current_start: u64,
 
     requested_start: u64,
+    /// rows before this were deleted on the server
+    first_available: u64,
 
     reconnecting: bool,
+    partition: Option<String>,
+}
+
+#[wasm_bindgen]
+pub struct Client {
+    conn: Connection<Builder>,
 }
 
 #[wasm_bindgen]
@@ -51,16 +60,92 @@ pub struct PacketRange {
 #[wasm_bindgen]
 impl Client {
     #[wasm_bindgen(constructor)]
+    pub fn new(websocket: WebSocket) -> Self {
+        Client {
+            conn: Connection::new(websocket),
+        }
+    }
+    pub fn reconnect(&mut self, websocket: WebSocket) {
+        self.conn.reconnect(websocket);
+    }
+    /// the partition to subscribe to, for servers that partition their logs
+    pub fn set_partition(&mut self, partition: Option<String>) {
+        self.conn.partition = partition;
+    }
+    pub fn on_open(&mut self, e: Event) {
+        self.conn.on_open(e);
+    }
+    pub fn on_message(&mut self, event: MessageEvent) -> Option<PacketRange> {
+        self.conn.on_message(event)
+    }
+    pub fn get(&self, n: u64) -> JsValue {
+        match self.conn.get_entry(n) {
+            None => JsValue::null(),
+            Some(e) => wrap(e),
+        }
+    }
+    pub fn end(&self) -> u64 {
+        self.conn.end()
+    }
+    /// rows before this were deleted on the server and won't be loaded
+    pub fn first_available(&self) -> u64 {
+        self.conn.first_available
+    }
+    /// the `n` groups with the largest sum of `metric` as `[key, count, sum]`,
+    /// e.g. `top("ip", "bytes_out", "uri /assets/ *", 10)`
+    pub fn top(&self, group_by: &str, metric: &str, filter: Option<String>, n: usize) -> Result<Array, JsValue> {
+        let group_by = group_by.parse().map_err(|_| JsValue::from_str("unknown column"))?;
+        let metric = metric.parse().map_err(|_| JsValue::from_str("unknown metric"))?;
+        let filter = filter
+            .map(|s| Filter::parse(&s).map_err(|e| JsValue::from_str(&e.to_string())))
+            .transpose()?;
+
+        let ctx = FilterCtx::new();
+        let mut agg = Aggregation::new(group_by, metric);
+        for block in self.conn.blocks() {
+            match filter {
+                Some(ref f) => {
+                    let f = BlockFilter::new(f, block);
+                    for e in f.rows(&ctx).filter_map(|idx| block.get(idx)) {
+                        agg.add(&e);
+                    }
+                }
+                None => for e in block.iter().flatten() {
+                    agg.add(&e);
+                }
+            }
+        }
+
+        Ok(agg.top(n).into_iter().map(|(key, stats)| {
+            Array::of3(&key.into(), &(stats.count as f64).into(), &(stats.sum as f64).into())
+        }).collect())
+    }
+}
+impl Deref for Client {
+    type Target = Connection<Builder>;
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+impl DerefMut for Client {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.conn
+    }
+}
+
+impl<S: Shema + Default> Connection<S> {
     pub fn new(websocket: WebSocket) -> Self {
         websocket.set_binary_type(BinaryType::Arraybuffer);
 
-        Client {
+        Connection {
             entries: Default::default(),
-            current: Builder::default(),
+            current: S::default(),
             current_start: 0,
             requested_start: 0,
+            first_available: 0,
             websocket,
             reconnecting: false,
+            partition: None,
         }
     }
     pub fn reconnect(&mut self, websocket: WebSocket) {
@@ -72,8 +157,8 @@ impl Client {
         let _ = self.websocket.send_with_u8_array(&data);
     }
     fn request_more(&mut self, start: u64) {
-        if start < self.requested_start {
-            let start = start.min(self.requested_start.saturating_sub(1000));
+        if start < self.requested_start && self.first_available < self.requested_start {
+            let start = start.min(self.requested_start.saturating_sub(1000)).max(self.first_available);
             debug!("requesting range {}..{}", start, self.requested_start);
             self.send(ClientMessage::FetchRange {
                 start,
@@ -82,11 +167,14 @@ impl Client {
             self.requested_start = start;
         }
     }
-    fn maybe_need_more(&mut self, start: u64) {
+    pub fn maybe_need_more(&mut self, start: u64) {
         self.request_more(start.saturating_sub(1000));
     }
     pub fn on_open(&mut self, _: Event) {
-        self.send(ClientMessage::SubScribeWithBacklog { backlog: 1000 });
+        self.subscribe();
+    }
+    fn subscribe(&self) {
+        self.send(ClientMessage::SubScribeWithBacklog { backlog: 1000, partition: self.partition.clone() });
     }
     pub fn on_message(&mut self, event: MessageEvent) -> Option<PacketRange> {
         let data = event.data();
@@ -97,7 +185,7 @@ impl Client {
             end: r.end,
         })
     }
-    fn get_entry(&self, n: u64) -> Option<BatchEntry<'_>> {
+    pub fn get_entry(&self, n: u64) -> Option<S::Item<'_>> {
         if n >= self.current_start {
             if let Some(val) = self.current.get((n - self.current_start) as usize) {
                 return Some(val);
@@ -111,10 +199,20 @@ impl Client {
         }
         None
     }
-    fn get_range(
+    pub fn get_range(
         &self,
         range: Range<u64>,
-    ) -> impl Iterator<Item = (u64, BatchEntry<'_>)> + DoubleEndedIterator {
+    ) -> impl Iterator<Item = (u64, S::Item<'_>)> + DoubleEndedIterator {
+        self.chunks(range).flat_map(move |(n, chunk, rows)| {
+            let start = rows.start;
+            chunk
+                .range(rows)
+                .enumerate()
+                .filter_map(move |(i, e)| Some(((i + start) as u64 + n, e.ok()?)))
+        })
+    }
+    /// the blocks overlapping `range` with their first row, and which of their rows are in `range`
+    fn chunks(&self, range: Range<u64>) -> impl DoubleEndedIterator<Item = (u64, &S, Range<usize>)> {
         let Range { start, end } = range;
         self.entries
             .range(..range.start)
@@ -123,20 +221,15 @@ impl Client {
             .into_iter()
             .chain(self.entries.range(range))
             .chain(std::iter::once((&self.current_start, &self.current)))
-            .flat_map(move |(&n, chunk)| {
+            .map(move |(&n, chunk)| {
                 let start = start.saturating_sub(n).min(chunk.len() as u64) as usize;
                 let end = end.saturating_sub(n).min(chunk.len() as u64) as usize;
-                chunk
-                    .range(start..end)
-                    .enumerate()
-                    .filter_map(move |(i, e)| Some(((i + start) as u64 + n, e.ok()?)))
+                (n, chunk, start..end)
             })
     }
-    pub fn get(&self, n: u64) -> JsValue {
-        match self.get_entry(n) {
-            None => JsValue::null(),
-            Some(e) => wrap(e),
-        }
+    /// all loaded blocks, including the current one
+    pub fn blocks(&self) -> impl Iterator<Item = &S> {
+        self.entries.values().chain(std::iter::once(&self.current))
     }
     pub fn end(&self) -> u64 {
         (self.current_start + self.current.len() as u64).max(
@@ -156,7 +249,7 @@ impl Client {
             PacketType::Batch => {
                 debug!("batch");
                 let (header, rest) = postcard::take_from_bytes::<BatchHeader>(rest).ok()?;
-                let builder = match Builder::from_slice(rest) {
+                let builder = match S::from_slice(rest) {
                     Ok(b) => b,
                     Err(e) => {
                         debug!("batch {}: error: {e:?}", header.start);
@@ -173,7 +266,7 @@ impl Client {
                 Some(range)
             }
             PacketType::Row => {
-                let row = postcard::from_bytes::<BatchEntry>(rest).ok()?;
+                let row = postcard::from_bytes::<S::Item<'_>>(rest).ok()?;
 
                 let start = self.current_start + self.current.len() as u64;
                 self.current.add(row);
@@ -200,11 +293,15 @@ impl Client {
                 if let Ok((msg, _)) = postcard::take_from_bytes::<ServerMessage>(rest) {
                     match msg {
                         ServerMessage::Detached | ServerMessage::NotAttached => {
-                            self.send(ClientMessage::SubScribeWithBacklog { backlog: 1000 });
+                            self.subscribe();
                         }
                         ServerMessage::Error { msg } => {
                             debug!("server error: {msg}");
                         }
+                        ServerMessage::RangeUnavailable { start, end } => {
+                            debug!("range {start}..{end} is no longer available");
+                            self.first_available = self.first_available.max(end);
+                        }
                     }
                 }
                 None
@@ -348,20 +445,17 @@ impl FilterView {
     pub fn scroll_to_end(&mut self, client: &Client) {
         let ctx = FilterCtx::new();
         let filter = &self.filter;
-        let matches = |&(_n, ref e): &(u64, BatchEntry)| matches(filter, &ctx, e);
 
         let end = self.positions.back().cloned().unwrap_or(self.start);
-        for (pos, _) in client.get_range(end + 1..u64::MAX).filter(matches) {
+        for (pos, _) in filtered_range(client, end + 1..u64::MAX, filter, &ctx) {
             if self.positions.len() >= self.len {
                 self.positions.pop_front();
             }
             self.positions.push_back(pos);
         }
         if self.len > self.positions.len() {
-            for (p, _) in client
-                .get_range(0..self.start)
+            for (p, _) in filtered_range(client, 0..self.start, filter, &ctx)
                 .rev()
-                .filter(matches)
                 .take(self.len - self.positions.len())
             {
                 self.positions.push_front(p);
@@ -374,12 +468,11 @@ impl FilterView {
     pub fn scroll_by(&mut self, client: &mut Client, by: isize) -> bool {
         let ctx = FilterCtx::new();
         let filter = &self.filter;
-        let matches = |&(_n, ref e): &(u64, BatchEntry)| matches(filter, &ctx, e);
 
         if by > 0 {
             let end = self.positions.back().cloned().unwrap_or(self.start);
             let mut take = by as usize;
-            for (pos, _) in client.get_range(end + 1..u64::MAX).filter(matches) {
+            for (pos, _) in filtered_range(client, end + 1..u64::MAX, filter, &ctx) {
                 if take == 0 {
                     break;
                 }
@@ -395,10 +488,8 @@ impl FilterView {
             }
             take > 0
         } else {
-            let pos = client
-                .get_range(0..self.start)
+            let pos = filtered_range(client, 0..self.start, filter, &ctx)
                 .rev()
-                .filter(matches)
                 .take((-by) as usize)
                 .last()
                 .map(|(pos, _)| pos)
@@ -426,11 +517,7 @@ impl FilterView {
 
         let mut new = Vec::with_capacity(self.len);
         self.positions.clear();
-        for (n, e) in client
-            .get_range(self.start..u64::MAX)
-            .filter(|(_, e)| matches(&self.filter, &ctx, e))
-            .take(self.len)
-        {
+        for (n, e) in filtered_range(client, self.start..u64::MAX, &self.filter, &ctx).take(self.len) {
             let val = match self.cache.remove(&n) {
                 Some(val) => val,
                 None => self.produce.call2(&JsValue::null(), &bigint(n), &wrap(e))?,
@@ -468,6 +555,14 @@ extern "C" {
         proto: u16,
         location: Option<&str>,
         tls_fp: &str,
+        ttfb: u32,
+        duration: u32,
+        bytes_in: u64,
+        bytes_out: u64,
+        body_len: u32,
+        body_hash: Option<&str>,
+        source: Option<&str>,
+        seq: Option<u64>,
     ) -> JsValue;
 }
 
@@ -502,13 +597,14 @@ impl<'a> std::fmt::Write for ArrayStr<'a> {
 }
 
 fn wrap(e: BatchEntry<'_>) -> JsValue {
-    let mut time_buf = [0; 20];
+    let mut time_buf = [0; 24];
     let mut ip_buf = [0; 40];
 
-    let time = format_time(&mut time_buf, e.time);
+    let time = format_time(&mut time_buf, e.time, e.time_us);
     let ip = format_ip(&mut ip_buf, e.ip);
     let headers: String = headers_string(e.headers.into_iter());
     let tls_fp = format!("{:032x}", u128::from_le_bytes(e.tls_fp));
+    let body_hash = (e.body_hash != [0; 32]).then(|| e.body_hash.iter().map(|b| format!("{b:02x}")).collect::<String>());
 
     unsafe {
         make_entry(
@@ -525,31 +621,53 @@ fn wrap(e: BatchEntry<'_>) -> JsValue {
             e.host,
             e.proto,
             e.location,
-            &tls_fp
+            &tls_fp,
+            e.ttfb,
+            e.duration,
+            e.bytes_in,
+            e.bytes_out,
+            e.body_len,
+            body_hash.as_deref(),
+            (!e.source.is_empty()).then_some(e.source),
+            (!e.source.is_empty()).then_some(e.seq),
         )
     }
 }
 
-fn matches(filter: &Option<Filter>, ctx: &FilterCtx, e: &BatchEntry) -> bool {
-    match filter {
-        Some(f) => f.matches(ctx, e),
-        None => true,
-    }
+/// the rows of `range` matching `filter`, with uri and host filters resolved once per block
+fn filtered_range<'a>(
+    client: &'a Client,
+    range: Range<u64>,
+    filter: &'a Option<Filter>,
+    ctx: &'a FilterCtx,
+) -> impl DoubleEndedIterator<Item = (u64, BatchEntry<'a>)> {
+    client.chunks(range).flat_map(move |(n, block, rows)| {
+        let filter = filter.as_ref().map(|f| BlockFilter::new(f, block));
+        let start = rows.start;
+        block.range(rows).enumerate().filter_map(move |(i, e)| {
+            let idx = i + start;
+            if filter.as_ref().is_some_and(|f| !f.matches(ctx, idx)) {
+                return None;
+            }
+            Some((idx as u64 + n, e.ok()?))
+        })
+    })
 }
 
-fn format_time<'a>(buf: &'a mut [u8; 20], n: u64) -> ArrayStr<'a> {
+fn format_time<'a>(buf: &'a mut [u8; 24], n: u64, us: u32) -> ArrayStr<'a> {
     use std::fmt::Write;
     let mut s = ArrayStr::new(buf);
     match OffsetDateTime::from_unix_timestamp(n as i64) {
         Ok(t) => write!(
             s,
-            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
             t.year(),
             u8::from(t.month()),
             t.day(),
             t.hour(),
             t.minute(),
-            t.second()
+            t.second(),
+            us / 1000
         )
         .unwrap(),
         Err(_) => write!(s, "Invalid time {n}").unwrap(),
@@ -0,0 +1,356 @@
+use std::{net::IpAddr, sync::Arc};
+
+use anyhow::{Context, Error, bail};
+use arrow_array::{
+    Array, ArrayRef, ArrowPrimitiveType, BinaryArray, FixedSizeBinaryArray, PrimitiveArray, RecordBatch, StringArray,
+    builder::{
+        BinaryBuilder, FixedSizeBinaryBuilder, MapBuilder, MapFieldNames, PrimitiveBuilder, StringBuilder,
+        StringDictionaryBuilder,
+    },
+    cast::AsArray,
+    types::{TimestampMicrosecondType, UInt16Type, UInt32Type, UInt64Type},
+};
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use clog_core::shema::{BatchEntry, Builder, Shema};
+use parquet::{
+    arrow::{ArrowWriter, arrow_reader::ParquetRecordBatchReaderBuilder},
+    basic::Compression,
+    file::{properties::WriterProperties, reader::ChunkReader},
+};
+
+fn dictionary() -> DataType {
+    DataType::Dictionary(Box::new(DataType::UInt32), Box::new(DataType::Utf8))
+}
+fn timestamp() -> DataType {
+    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+}
+fn header_names() -> MapFieldNames {
+    MapFieldNames { entry: "entries".into(), key: "key".into(), value: "value".into() }
+}
+fn header_value() -> Field {
+    Field::new("value", DataType::Binary, false)
+}
+fn headers() -> DataType {
+    let entries = Fields::from(vec![Field::new("key", DataType::Utf8, false), header_value()]);
+    DataType::Map(Arc::new(Field::new("entries", DataType::Struct(entries), false)), false)
+}
+
+/// Arrow schema of the request log.
+///
+/// `row` is the index of the row in the log, `time` combines the `time` and `time_us` columns.
+/// `ip` holds the address as text, IPv4 addresses are not written as mapped IPv6 addresses.
+/// `body_hash` is null for rows without a hash, `source` and `seq` for rows without a source.
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("row", DataType::UInt64, false),
+        Field::new("status", DataType::UInt16, false),
+        Field::new("method", dictionary(), false),
+        Field::new("uri", dictionary(), false),
+        Field::new("ua", dictionary(), true),
+        Field::new("referer", dictionary(), true),
+        Field::new("ip", DataType::Utf8, false),
+        Field::new("port", DataType::UInt16, false),
+        Field::new("time", timestamp(), false),
+        Field::new("body", DataType::Binary, true),
+        Field::new("headers", headers(), false),
+        Field::new("host", dictionary(), false),
+        Field::new("proto", DataType::UInt16, false),
+        Field::new("location", dictionary(), true),
+        Field::new("tls_fp", DataType::FixedSizeBinary(16), false),
+        Field::new("ttfb", DataType::UInt32, false),
+        Field::new("duration", DataType::UInt32, false),
+        Field::new("bytes_in", DataType::UInt64, false),
+        Field::new("bytes_out", DataType::UInt64, false),
+        Field::new("body_len", DataType::UInt32, false),
+        Field::new("body_hash", DataType::FixedSizeBinary(32), true),
+        Field::new("source", dictionary(), true),
+        Field::new("seq", DataType::UInt64, true),
+    ]))
+}
+
+/// the rows of a block starting at row `start`
+pub fn to_record_batch(start: u64, builder: &Builder) -> Result<RecordBatch, Error> {
+    let n = builder.len();
+    let mut row = PrimitiveBuilder::<UInt64Type>::with_capacity(n);
+    let mut status = PrimitiveBuilder::<UInt16Type>::with_capacity(n);
+    let mut method = StringDictionaryBuilder::<UInt32Type>::new();
+    let mut uri = StringDictionaryBuilder::<UInt32Type>::new();
+    let mut ua = StringDictionaryBuilder::<UInt32Type>::new();
+    let mut referer = StringDictionaryBuilder::<UInt32Type>::new();
+    let mut ip = StringBuilder::with_capacity(n, 16 * n);
+    let mut port = PrimitiveBuilder::<UInt16Type>::with_capacity(n);
+    let mut time = PrimitiveBuilder::<TimestampMicrosecondType>::with_capacity(n).with_timezone("UTC");
+    let mut body = BinaryBuilder::new();
+    let mut headers = MapBuilder::new(Some(header_names()), StringBuilder::new(), BinaryBuilder::new())
+        .with_values_field(header_value());
+    let mut host = StringDictionaryBuilder::<UInt32Type>::new();
+    let mut proto = PrimitiveBuilder::<UInt16Type>::with_capacity(n);
+    let mut location = StringDictionaryBuilder::<UInt32Type>::new();
+    let mut tls_fp = FixedSizeBinaryBuilder::with_capacity(n, 16);
+    let mut ttfb = PrimitiveBuilder::<UInt32Type>::with_capacity(n);
+    let mut duration = PrimitiveBuilder::<UInt32Type>::with_capacity(n);
+    let mut bytes_in = PrimitiveBuilder::<UInt64Type>::with_capacity(n);
+    let mut bytes_out = PrimitiveBuilder::<UInt64Type>::with_capacity(n);
+    let mut body_len = PrimitiveBuilder::<UInt32Type>::with_capacity(n);
+    let mut body_hash = FixedSizeBinaryBuilder::with_capacity(n, 32);
+    let mut source = StringDictionaryBuilder::<UInt32Type>::new();
+    let mut seq = PrimitiveBuilder::<UInt64Type>::with_capacity(n);
+
+    for (i, e) in builder.iter().enumerate() {
+        let e = e.map_err(|field| anyhow::anyhow!("row {i}: invalid {field}"))?;
+        row.append_value(start + i as u64);
+        status.append_value(e.status);
+        method.append_value(e.method);
+        uri.append_value(e.uri);
+        ua.append_option(e.ua);
+        referer.append_option(e.referer);
+        ip.append_value(e.ip.to_canonical().to_string());
+        port.append_value(e.port);
+        time.append_value(e.timestamp_us() as i64);
+        body.append_option(e.body);
+        for (k, v) in &e.headers {
+            headers.keys().append_value(k);
+            headers.values().append_value(v);
+        }
+        headers.append(true)?;
+        host.append_value(e.host);
+        proto.append_value(e.proto);
+        location.append_option(e.location);
+        tls_fp.append_value(e.tls_fp)?;
+        ttfb.append_value(e.ttfb);
+        duration.append_value(e.duration);
+        bytes_in.append_value(e.bytes_in);
+        bytes_out.append_value(e.bytes_out);
+        body_len.append_value(e.body_len);
+        if e.body_hash == [0; 32] {
+            body_hash.append_null();
+        } else {
+            body_hash.append_value(e.body_hash)?;
+        }
+        if e.source.is_empty() {
+            source.append_null();
+            seq.append_null();
+        } else {
+            source.append_value(e.source);
+            seq.append_value(e.seq);
+        }
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(row.finish()),
+        Arc::new(status.finish()),
+        Arc::new(method.finish()),
+        Arc::new(uri.finish()),
+        Arc::new(ua.finish()),
+        Arc::new(referer.finish()),
+        Arc::new(ip.finish()),
+        Arc::new(port.finish()),
+        Arc::new(time.finish()),
+        Arc::new(body.finish()),
+        Arc::new(headers.finish()),
+        Arc::new(host.finish()),
+        Arc::new(proto.finish()),
+        Arc::new(location.finish()),
+        Arc::new(tls_fp.finish()),
+        Arc::new(ttfb.finish()),
+        Arc::new(duration.finish()),
+        Arc::new(bytes_in.finish()),
+        Arc::new(bytes_out.finish()),
+        Arc::new(body_len.finish()),
+        Arc::new(body_hash.finish()),
+        Arc::new(source.finish()),
+        Arc::new(seq.finish()),
+    ];
+    Ok(RecordBatch::try_new(schema(), columns)?)
+}
+
+fn column(batch: &RecordBatch, name: &str, ty: &DataType) -> Result<Option<ArrayRef>, Error> {
+    let Some(column) = batch.column_by_name(name) else { return Ok(None) };
+    let column = arrow_cast::cast(column, ty).with_context(|| format!("column {name}"))?;
+    Ok(Some(column))
+}
+fn primitive<T: ArrowPrimitiveType>(batch: &RecordBatch, name: &str) -> Result<Option<PrimitiveArray<T>>, Error> {
+    Ok(column(batch, name, &T::DATA_TYPE)?.map(|c| c.as_primitive::<T>().clone()))
+}
+fn strings(batch: &RecordBatch, name: &str) -> Result<Option<StringArray>, Error> {
+    Ok(column(batch, name, &DataType::Utf8)?.map(|c| c.as_string::<i32>().clone()))
+}
+fn binary(batch: &RecordBatch, name: &str) -> Result<Option<BinaryArray>, Error> {
+    Ok(column(batch, name, &DataType::Binary)?.map(|c| c.as_binary::<i32>().clone()))
+}
+fn fixed(batch: &RecordBatch, name: &str, size: i32) -> Result<Option<FixedSizeBinaryArray>, Error> {
+    Ok(column(batch, name, &DataType::FixedSizeBinary(size))?.map(|c| c.as_fixed_size_binary().clone()))
+}
+
+/// Add the rows of `batch` to `builder`.
+///
+/// Columns are cast to the types of `schema()`, so batches written by other tools can be read
+/// as long as the names match. Only `time` is required, missing columns are left empty.
+pub fn add_record_batch(batch: &RecordBatch, builder: &mut Builder) -> Result<(), Error> {
+    let status = primitive::<UInt16Type>(batch, "status")?;
+    let method = strings(batch, "method")?;
+    let uri = strings(batch, "uri")?;
+    let ua = strings(batch, "ua")?;
+    let referer = strings(batch, "referer")?;
+    let ip = strings(batch, "ip")?;
+    let port = primitive::<UInt16Type>(batch, "port")?;
+    let time = column(batch, "time", &DataType::Timestamp(TimeUnit::Microsecond, None))?
+        .context("missing column time")?;
+    let time = time.as_primitive::<TimestampMicrosecondType>();
+    let body = binary(batch, "body")?;
+    let headers = batch.column_by_name("headers").map(|c| c.as_map_opt().context("column headers is not a map")).transpose()?;
+    let header_keys = headers.map(|h| arrow_cast::cast(h.keys(), &DataType::Utf8)).transpose()?;
+    let header_keys = header_keys.as_ref().map(|k| k.as_string::<i32>());
+    let header_values = headers.map(|h| arrow_cast::cast(h.values(), &DataType::Binary)).transpose()?;
+    let header_values = header_values.as_ref().map(|v| v.as_binary::<i32>());
+    let host = strings(batch, "host")?;
+    let proto = primitive::<UInt16Type>(batch, "proto")?;
+    let location = strings(batch, "location")?;
+    let tls_fp = fixed(batch, "tls_fp", 16)?;
+    let ttfb = primitive::<UInt32Type>(batch, "ttfb")?;
+    let duration = primitive::<UInt32Type>(batch, "duration")?;
+    let bytes_in = primitive::<UInt64Type>(batch, "bytes_in")?;
+    let bytes_out = primitive::<UInt64Type>(batch, "bytes_out")?;
+    let body_len = primitive::<UInt32Type>(batch, "body_len")?;
+    let body_hash = fixed(batch, "body_hash", 32)?;
+    let source = strings(batch, "source")?;
+    let seq = primitive::<UInt64Type>(batch, "seq")?;
+
+    fn value<T: ArrowPrimitiveType>(a: &Option<PrimitiveArray<T>>, i: usize) -> T::Native {
+        a.as_ref().filter(|a| a.is_valid(i)).map(|a| a.value(i)).unwrap_or_default()
+    }
+    fn str(a: &Option<StringArray>, i: usize) -> Option<&str> {
+        a.as_ref().filter(|a| a.is_valid(i)).map(|a| a.value(i))
+    }
+    fn bytes<const N: usize>(a: &Option<FixedSizeBinaryArray>, i: usize) -> [u8; N] {
+        a.as_ref().filter(|a| a.is_valid(i)).and_then(|a| a.value(i).try_into().ok()).unwrap_or([0; N])
+    }
+
+    builder.reserve(batch.num_rows());
+    for i in 0..batch.num_rows() {
+        if time.is_null(i) {
+            bail!("row {i}: time is null");
+        }
+        let (secs, us) = (time.value(i).div_euclid(1_000_000), time.value(i).rem_euclid(1_000_000));
+        let ip = match str(&ip, i) {
+            Some(ip) => match ip.parse().with_context(|| format!("row {i}: invalid ip {ip:?}"))? {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            },
+            None => std::net::Ipv6Addr::UNSPECIFIED,
+        };
+        let mut pairs = vec![];
+        if let (Some(map), Some(keys), Some(values)) = (headers, header_keys, header_values)
+            && map.is_valid(i)
+        {
+            let offsets = map.value_offsets();
+            for j in offsets[i] as usize..offsets[i + 1] as usize {
+                pairs.push((keys.value(j), values.value(j)));
+            }
+        }
+        builder.add(BatchEntry {
+            status: value(&status, i),
+            method: str(&method, i).unwrap_or_default(),
+            uri: str(&uri, i).unwrap_or_default(),
+            ua: str(&ua, i),
+            referer: str(&referer, i),
+            ip,
+            port: value(&port, i),
+            time: secs as u64,
+            body: body.as_ref().filter(|b| b.is_valid(i)).map(|b| b.value(i)),
+            headers: pairs,
+            host: str(&host, i).unwrap_or_default(),
+            proto: value(&proto, i),
+            location: str(&location, i),
+            tls_fp: bytes(&tls_fp, i),
+            time_us: us as u32,
+            ttfb: value(&ttfb, i),
+            duration: value(&duration, i),
+            bytes_in: value(&bytes_in, i),
+            bytes_out: value(&bytes_out, i),
+            body_len: value(&body_len, i),
+            body_hash: bytes(&body_hash, i),
+            source: str(&source, i).unwrap_or_default(),
+            seq: value(&seq, i),
+        });
+    }
+    Ok(())
+}
+
+/// Writes blocks into a Parquet file, one row group per block.
+pub struct ParquetWriter<W: std::io::Write + Send> {
+    writer: ArrowWriter<W>,
+}
+impl<W: std::io::Write + Send> ParquetWriter<W> {
+    pub fn new(writer: W) -> Result<Self, Error> {
+        let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = ArrowWriter::try_new(writer, schema(), Some(props))?;
+        Ok(ParquetWriter { writer })
+    }
+    pub fn write(&mut self, start: u64, builder: &Builder) -> Result<(), Error> {
+        self.writer.write(&to_record_batch(start, builder)?)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+    pub fn finish(self) -> Result<W, Error> {
+        Ok(self.writer.into_inner()?)
+    }
+}
+
+/// the record batches of a Parquet file
+pub fn read_parquet<R: ChunkReader + 'static>(reader: R, batch_size: usize) -> Result<impl Iterator<Item = Result<RecordBatch, Error>>, Error> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(reader)?.with_batch_size(batch_size).build()?;
+    Ok(reader.map(|batch| Ok(batch?)))
+}
+
+#[test]
+fn test_arrow_roundtrip() {
+    let mut builder = Builder::default();
+    let ip = "192.168.1.7".parse::<std::net::Ipv4Addr>().unwrap().to_ipv6_mapped();
+    for i in 0..3u16 {
+        builder.add(BatchEntry {
+            status: 200 + i,
+            method: "GET",
+            uri: if i == 1 { "/a" } else { "/" },
+            ua: None,
+            referer: None,
+            ip,
+            port: 443,
+            time: 1_700_000_000 + i as u64,
+            body: (i == 2).then_some(&b"\xff\x00"[..]),
+            headers: [("accept", &b"*/*"[..]), ("x-bin", b"\xfe")][..i as usize].to_vec(),
+            host: "qdat.net",
+            proto: 2,
+            location: (i == 0).then_some("DE"),
+            tls_fp: [i as u8; 16],
+            time_us: 123 * i as u32,
+            ttfb: 10,
+            duration: 20,
+            bytes_in: 100,
+            bytes_out: 2000,
+            body_len: 2 * (i == 2) as u32,
+            body_hash: if i == 2 { [7; 32] } else { [0; 32] },
+            source: if i == 0 { "" } else { "edge-1" },
+            seq: if i == 0 { 0 } else { 40 + i as u64 },
+        });
+    }
+
+    let batch = to_record_batch(5, &builder).unwrap();
+    assert_eq!(batch.num_rows(), 3);
+    assert_eq!(batch.column_by_name("ip").unwrap().as_string::<i32>().value(0), "192.168.1.7");
+    assert_eq!(batch.column_by_name("row").unwrap().as_primitive::<UInt64Type>().value(2), 7);
+    assert!(batch.column_by_name("seq").unwrap().is_null(0));
+
+    let mut writer = ParquetWriter::new(Vec::new()).unwrap();
+    writer.write(5, &builder).unwrap();
+    let file = bytes::Bytes::from(writer.finish().unwrap());
+
+    let mut read = Builder::default();
+    for batch in read_parquet(file, 1024).unwrap() {
+        add_record_batch(&batch.unwrap(), &mut read).unwrap();
+    }
+    assert_eq!(read.len(), 3);
+    for (a, b) in builder.iter().zip(read.iter()) {
+        assert_eq!(format!("{:?}", a.unwrap()), format!("{:?}", b.unwrap()));
+    }
+}
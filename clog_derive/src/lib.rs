@@ -34,22 +34,22 @@ pub fn derive_slice_trait_fn(input: TokenStream) -> TokenStream {
 
         #[derive(Clone, Default)]
         #vis struct #elem_ident {
-            #( #idents: <#types as SliceTrait>::Elem ),*
+            #( #idents: <#types as ::clog_core::__private::SliceTrait>::Elem ),*
         }
 
         #vis struct #slice_ident<'a> {
-            #( #idents: <#types as SliceTrait>::Slice::<'a> ),*
+            #( #idents: <#types as ::clog_core::__private::SliceTrait>::Slice::<'a> ),*
         }
 
         #vis struct #slice_mut_ident<'a> {
-            #( #idents: <#types as SliceTrait>::SliceMut::<'a> ),*
+            #( #idents: <#types as ::clog_core::__private::SliceTrait>::SliceMut::<'a> ),*
         }
 
         #vis struct #slice_uninit_ident<'a> {
-            #( #idents: <#types as SliceTrait>::SliceUninit::<'a> ),*
+            #( #idents: <#types as ::clog_core::__private::SliceTrait>::SliceUninit::<'a> ),*
         }
 
-        impl SliceTrait for #fields_ident {
+        impl ::clog_core::__private::SliceTrait for #fields_ident {
             type Elem = #elem_ident;
             type Slice<'a> = #slice_ident::<'a>;
             type SliceMut<'a> = #slice_mut_ident::<'a>;
@@ -58,28 +58,28 @@ pub fn derive_slice_trait_fn(input: TokenStream) -> TokenStream {
             unsafe fn get(&self, raw: *mut u8, idx: usize) -> Self::Elem {
                 unsafe {
                     #elem_ident {
-                        #( #idents: self.#idents.0.get(raw.offset(self.#idents.1 as isize), idx) ),*
+                        #( #idents: ::clog_core::__private::SliceTrait::get(&self.#idents.0, raw.offset(self.#idents.1 as isize), idx) ),*
                     }
                 }
             }
             unsafe fn slice<'a>(&self, raw: *mut u8, len: usize) -> Self::Slice<'a> {
                 unsafe {
                     #slice_ident {
-                        #( #idents: self.#idents.0.slice(raw.offset(self.#idents.1 as isize), len) ),*
+                        #( #idents: ::clog_core::__private::SliceTrait::slice(&self.#idents.0, raw.offset(self.#idents.1 as isize), len) ),*
                     }
                 }
             }
             unsafe fn slice_mut<'a>(&self, raw: *mut u8, len: usize) -> Self::SliceMut<'a> {
                 unsafe {
                     #slice_mut_ident {
-                        #( #idents : self.#idents.0.slice_mut(raw.offset(self.#idents.1 as isize), len) ),*
+                        #( #idents : ::clog_core::__private::SliceTrait::slice_mut(&self.#idents.0, raw.offset(self.#idents.1 as isize), len) ),*
                     }
                 }
             }
             unsafe fn slice_uninit<'a>(&self, raw: *mut u8, len: usize) -> Self::SliceUninit<'a> {
                 unsafe {
                     #slice_uninit_ident {
-                        #( #idents : self.#idents.0.slice_uninit(raw.offset(self.#idents.1 as isize), len) ),*
+                        #( #idents : ::clog_core::__private::SliceTrait::slice_uninit(&self.#idents.0, raw.offset(self.#idents.1 as isize), len) ),*
                     }
                 }
             }
@@ -87,14 +87,14 @@ pub fn derive_slice_trait_fn(input: TokenStream) -> TokenStream {
             unsafe fn write(&self, raw: *mut u8, idx: usize, val: Self::Elem) {
                 let #elem_ident { #( #idents ),* } = val;
                 unsafe {
-                    #( self.#idents.0.write(raw.offset(self.#idents.1 as isize), idx, #idents); )*
+                    #( ::clog_core::__private::SliceTrait::write(&self.#idents.0, raw.offset(self.#idents.1 as isize), idx, #idents); )*
                 }
             }
 
-            fn layout(n: usize) -> (Layout, #fields_ident) {
-                #( let (#layout_ident, #field_ident) = <#types as SliceTrait>::layout(n); )*
+            fn layout(n: usize) -> (::clog_core::__private::Layout, #fields_ident) {
+                #( let (#layout_ident, #field_ident) = <#types as ::clog_core::__private::SliceTrait>::layout(n); )*
 
-                let layout = Layout::from_size_align(0, 1).unwrap();
+                let layout = ::clog_core::__private::Layout::from_size_align(0, 1).unwrap();
 
                 #( let (layout, #offset_ident) = layout.extend(#layout_ident).unwrap(); )*
 
@@ -105,10 +105,10 @@ pub fn derive_slice_trait_fn(input: TokenStream) -> TokenStream {
                 (layout, fields)
             }
             fn copy_slice<'a, 'b>(from: Self::Slice<'a>, to: Self::SliceMut<'b>) {
-                #( <#types as SliceTrait>::copy_slice(from.#idents, to.#idents); )*
+                #( <#types as ::clog_core::__private::SliceTrait>::copy_slice(from.#idents, to.#idents); )*
             }
             fn copy_slice_uninit<'a, 'b>(from: Self::Slice<'a>, to: Self::SliceUninit<'b>) {
-                #( <#types as SliceTrait>::copy_slice_uninit(from.#idents, to.#idents); )*
+                #( <#types as ::clog_core::__private::SliceTrait>::copy_slice_uninit(from.#idents, to.#idents); )*
             }
         }
     }.into()
@@ -118,12 +118,16 @@ pub fn derive_slice_trait_fn(input: TokenStream) -> TokenStream {
 pub fn derive_shema_fn(input: TokenStream) -> TokenStream {
     let original_struct = parse_macro_input!(input as DeriveInput);
 
-    let ShemaStruct { ident, data } = ShemaStruct::from_derive_input(&original_struct).unwrap();
+    let ShemaStruct { ident, data, version } = match ShemaStruct::from_derive_input(&original_struct) {
+        Ok(s) => s,
+        Err(e) => return e.write_errors().into()
+    };
 
     let vis = original_struct.vis;
     let fields = data.take_struct().unwrap().fields;
     let idents: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
     let types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    let field_vis: Vec<_> = fields.iter().map(|f| &f.vis).collect();
 
     let builder_ident = format_ident!("{}{}", ident, "Builder");
 
@@ -150,83 +154,134 @@ pub fn derive_shema_fn(input: TokenStream) -> TokenStream {
         }
     }).collect();
 
+    let mut codecs = vec![];
+    for f in fields.iter() {
+        let codec = match f.codec {
+            None => format_ident!("Pco"),
+            Some(ref lit) => match lit.value().as_str() {
+                "pco" => format_ident!("Pco"),
+                "brotli" => format_ident!("Brotli"),
+                "zstd" => format_ident!("Zstd"),
+                "lz4" => format_ident!("Lz4"),
+                _ => return syn::Error::new(lit.span(), "expected one of \"pco\", \"brotli\", \"zstd\", \"lz4\"").to_compile_error().into()
+            }
+        };
+        codecs.push(codec);
+    }
+    let time_fields: Vec<_> = fields.iter().filter(|f| f.time).collect();
+    let max_time = match time_fields.as_slice() {
+        [] => quote! {},
+        [f] => {
+            let time_ident = f.ident.as_ref().unwrap();
+            quote! {
+                const HAS_TIME: bool = true;
+                fn max_time(&self) -> Option<u64> {
+                    let #data_slice_ident { #time_ident, .. } = self.soa.slice();
+                    #time_ident.iter().filter_map(|&c| ::clog_core::__private::DataBuilder::get(&self.#time_ident, c)).max()
+                }
+            }
+        }
+        [_, f, ..] => return syn::Error::new(f.ident.as_ref().unwrap().span(), "only one field can be #[clog(time)]").to_compile_error().into()
+    };
+    let levels: Vec<_> = fields.iter().map(|f| match f.level {
+        Some(ref level) => quote! { Some(#level) },
+        None => quote! { None }
+    }).collect();
+
     quote! {
-        #[derive(clog_derive::SliceTrait)]
+        #[derive(::clog_core::__private::SliceTrait)]
         #vis struct #data_ident {
-            #( #idents: <#types as DataBuilder>::Data ),*
+            #( #idents: <#types as ::clog_core::__private::DataBuilder>::Data ),*
         }
 
         #[derive(Default, Clone)]
         #vis struct #builder_ident {
-            soa: Owned<#fields_ident>,
-            #( #idents: #types ),*
+            soa: ::clog_core::__private::Owned<#fields_ident>,
+            #( #field_vis #idents: #types ),*
         }
 
-        #[derive(Debug, Serialize, Deserialize)]
+        #[derive(Debug, ::clog_core::__private::serde::Serialize, ::clog_core::__private::serde::Deserialize)]
+        #[serde(crate = "::clog_core::__private::serde")]
         #vis struct #item_ident<'a> {
             #(
                 #[serde(borrow)]
-                pub #idents: <#types as DataBuilder>::Item<'a>
+                pub #idents: <#types as ::clog_core::__private::DataBuilder>::Item<'a>
             ),*
         }
 
-        impl Shema for #builder_ident {
+        impl ::clog_core::__private::Shema for #builder_ident {
             type Item<'a> = #item_ident<'a>;
             type Fields = #fields_ident;
 
+            const VERSION: u32 = #version;
+
             fn with_capacity(n: usize) -> Self {
                 #builder_ident {
-                    soa: Owned::<#fields_ident>::with_capacity(n),
+                    soa: ::clog_core::__private::Owned::<#fields_ident>::with_capacity(n),
                     #( #idents: <#types as Default>::default() ),*
                 }
             }
 
-            fn fields(&self) -> &Owned<Self::Fields> {
+            fn fields(&self) -> &::clog_core::__private::Owned<Self::Fields> {
                 &self.soa
             }
 
             fn add(&mut self, item: #item_ident<'_>) {
                 let compressed = #elem_ident {
-                    #( #idents: self.#idents.add(item.#idents) ),*
+                    #( #idents: ::clog_core::__private::DataBuilder::add(&mut self.#idents, item.#idents) ),*
                 };
                 self.soa.push(compressed);
             }
             fn get(&self, idx: usize) -> Option<#item_ident<'_>> {
                 let compressed = self.soa.get(idx)?;
                 Some(#item_ident {
-                    #( #idents: self.#idents.get(compressed.#idents)? ),*
+                    #( #idents: ::clog_core::__private::DataBuilder::get(&self.#idents, compressed.#idents)? ),*
                 })
             }
-            fn decompress(&self, c: <#fields_ident as SliceTrait>::Elem) -> Result<#item_ident, &'static str> {
+            fn decompress(&self, c: <#fields_ident as ::clog_core::__private::SliceTrait>::Elem) -> Result<#item_ident<'_>, &'static str> {
                 Ok(#item_ident {
-                    #( #idents: self.#idents.get(c.#idents).ok_or(stringify!(#idents))? ),*
+                    #( #idents: ::clog_core::__private::DataBuilder::get(&self.#idents, c.#idents).ok_or(stringify!(#idents))? ),*
                 })
             }
 
-            #[cfg(feature="encode")]
-            fn write(&self, f: &FileCompressor, mut writer: BytesMut, opt: &Options, version: u32) -> Result<BytesMut, Error> {
-                let mut scratch = Vec::with_capacity(8 * self.soa.len() + 100);
-                let #data_slice_ident { #( #idents ),* } = self.soa.slice();
-                #(
-                    //println!("FIELD {}", stringify!(#idents));
-                    if #version_check {
-                        let (field_size, scratch2) = self.#idents.write(f, #idents, scratch, opt)?;
-                        scratch = scratch2;
-
-                        //println!("    header at {}", writer.len());
-                        writer = clog::shema::encode(field_size, writer)?;
-                        //println!("    data at {}", writer.len());
-                        writer.extend_from_slice(&scratch);
-                        scratch.clear();
-                    } else {
-                        //println!("    skipped");
-                    }
-                )*
-                Ok(writer)
+            ::clog_core::__private::cfg_encode! {
+                fn write(
+                    &self,
+                    f: &::clog_core::__private::FileCompressor,
+                    mut writer: ::clog_core::__private::BytesMut,
+                    opt: &::clog_core::__private::EncodeOptions,
+                    version: u32
+                ) -> Result<::clog_core::__private::BytesMut, ::clog_core::__private::Error> {
+                    let mut scratch = Vec::with_capacity(8 * self.soa.len() + 100);
+                    let #data_slice_ident { #( #idents ),* } = self.soa.slice();
+                    #(
+                        //println!("FIELD {}", stringify!(#idents));
+                        if #version_check {
+                            let opt = ::clog_core::__private::EncodeOptions::column(opt, stringify!(#idents), ::clog_core::__private::Codec::#codecs, #levels);
+                            let (field_size, scratch2) = ::clog_core::__private::DataBuilderEncode::write(&self.#idents, f, #idents, scratch, &opt)?;
+                            scratch = scratch2;
+
+                            //println!("    header at {}", writer.len());
+                            writer = ::clog_core::__private::encode((opt.codec.unwrap_or_default(), field_size), writer)?;
+                            //println!("    data at {}", writer.len());
+                            writer.extend_from_slice(&scratch);
+                            scratch.clear();
+                        } else {
+                            //println!("    skipped");
+                        }
+                    )*
+                    Ok(writer)
+                }
             }
 
-            fn read<'a>(f: &FileDecompressor, data: Input<'a>, len: usize, version: u32) -> Result<(Self, Input<'a>), Error> {
-                let mut soa = Owned::<#fields_ident>::default();
+            fn read<'a>(
+                f: &::clog_core::__private::FileDecompressor,
+                data: ::clog_core::__private::Input<'a>,
+                len: usize,
+                version: u32,
+                codecs: bool
+            ) -> Result<(Self, ::clog_core::__private::Input<'a>), ::clog_core::__private::Error> {
+                let mut soa = ::clog_core::__private::Owned::<#fields_ident>::default();
                 soa.reserve(len as usize);
                 soa.extend(std::iter::repeat(Default::default()).take(len as usize));
 
@@ -239,9 +294,13 @@ pub fn derive_shema_fn(input: TokenStream) -> TokenStream {
                     let (#idents, data) = if ok && #version_check {
                         //println!("    header at {}", data.pos());
                         let start = data.clone();
-                        if let Ok((field_size, data)) = clog::shema::decode(data) {
+                        let header = match codecs {
+                            true => ::clog_core::__private::decode(data),
+                            false => ::clog_core::__private::decode(data).map(|(size, data)| ((::clog_core::__private::Codec::Pco, size), data)),
+                        };
+                        if let Ok(((codec, field_size), data)) = header {
                             //println!("    data at {}", data.pos());
-                            <#types as DataBuilder>::read(f, #idents, data, field_size)?
+                            <#types as ::clog_core::__private::DataBuilder>::read(f, #idents, data, field_size, ::clog_core::__private::ColumnInfo { codec, version, ..Default::default() })?
                         } else {
                             ok = false;
                             (Default::default(), start)
@@ -260,6 +319,7 @@ pub fn derive_shema_fn(input: TokenStream) -> TokenStream {
             fn reserve(&mut self, additional: usize) {
                 self.soa.reserve(additional);
             }
+            #max_time
         }
     }.into()
 }
@@ -282,15 +342,24 @@ struct SliceStruct {
 struct Entry {
     ident: Option<syn::Ident>,
     ty: syn::Type,
+    vis: syn::Visibility,
 
     min_version: Option<Expr>,
     max_version: Option<Expr>,
+
+    codec: Option<syn::LitStr>,
+    level: Option<Expr>,
+    /// unix seconds of the row, see `Shema::max_time`
+    #[darling(default)]
+    time: bool,
 }
 
 
 #[derive(Debug, FromDeriveInput)]
+#[darling(attributes(clog))]
 struct ShemaStruct {
     ident: Ident,
     data: ast::Data<util::Ignored, Entry>,
 
+    version: Expr,
 }
@@ -0,0 +1,335 @@
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, ready},
+    time::{Instant, SystemTime},
+};
+
+use bytes::{Buf, BytesMut};
+use clog_core::{Headers, Protocol, RequestEntry, privacy::PrivacyPolicy};
+use http::{HeaderMap, Request, Response};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use tokio::sync::mpsc::Sender;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// What `ClogLayer` records.
+#[derive(Clone)]
+pub struct Config {
+    /// fraction of requests recorded, 1.0 records all
+    pub sample_rate: f64,
+    /// record responses with a 5xx status even if they were not sampled
+    pub keep_errors: bool,
+    /// keep up to this many bytes of each request body
+    pub capture_body: Option<usize>,
+    /// applied before the entry leaves the service
+    pub privacy: PrivacyPolicy,
+    /// protocol of requests whose uri has no scheme, i.e. HTTP/1 requests
+    pub proto: Protocol,
+    /// `RequestEntry::source` of the entries, e.g. the name of this instance.
+    /// their `seq` counts up from the time the layer was created in microseconds, so it isn't reused after a restart.
+    ///
+    /// Each layer counts on its own, clones of a layer share the count. Two layers with the same source
+    /// hand out the same seqs, and `clog merge` keeps only one of the entries with a seq.
+    /// So give every layer its own source, e.g. add the worker or process id.
+    pub source: Option<String>,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config { sample_rate: 1.0, keep_errors: true, capture_body: None, privacy: PrivacyPolicy::default(), proto: Protocol::Http, source: None }
+    }
+}
+
+/// Records a `RequestEntry` for every request and sends it to the collector, e.g. the sender of `init_log`.
+///
+/// The entry is sent when the response body was sent or dropped. Entries are dropped when the
+/// channel is full instead of holding up responses. The peer address is taken from a
+/// `SocketAddr` extension, or axum's `ConnectInfo<SocketAddr>` with the `axum` feature.
+#[derive(Clone)]
+pub struct ClogLayer {
+    tx: Sender<RequestEntry>,
+    config: Arc<Config>,
+    seen: Arc<AtomicU64>,
+    /// next `seq`, if the entries have a source
+    seq: Arc<AtomicU64>,
+}
+impl ClogLayer {
+    pub fn new(tx: Sender<RequestEntry>) -> Self {
+        Self::with_config(tx, Config::default())
+    }
+    pub fn with_config(tx: Sender<RequestEntry>, config: Config) -> Self {
+        let start = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        ClogLayer { tx, config: Arc::new(config), seen: Default::default(), seq: Arc::new(AtomicU64::new(start.as_micros() as u64)) }
+    }
+    /// keeps `sample_rate` of the requests, spread evenly
+    fn sampled(&self, status: u16) -> bool {
+        let rate = self.config.sample_rate;
+        if rate >= 1.0 || (self.config.keep_errors && status >= 500) {
+            return true;
+        }
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        (n * rate).ceil() < ((n + 1.0) * rate).ceil()
+    }
+}
+impl<S> Layer<S> for ClogLayer {
+    type Service = ClogService<S>;
+    fn layer(&self, inner: S) -> Self::Service {
+        ClogService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct ClogService<S> {
+    inner: S,
+    layer: ClogLayer,
+}
+
+/// size of the headers on the wire, roughly
+fn headers_len(headers: &HeaderMap) -> u64 {
+    headers.iter().map(|(k, v)| (k.as_str().len() + v.len() + 4) as u64).sum()
+}
+
+fn peer<B>(req: &Request<B>) -> Option<SocketAddr> {
+    #[cfg(feature = "axum")]
+    if let Some(axum::extract::ConnectInfo(addr)) = req.extensions().get::<axum::extract::ConnectInfo<SocketAddr>>() {
+        return Some(*addr);
+    }
+    req.extensions().get::<SocketAddr>().copied()
+}
+
+impl<S, ReqB, ResB> Service<Request<ReqB>> for ClogService<S>
+where
+    S: Service<Request<RequestBody<ReqB>>, Response = Response<ResB>>,
+{
+    type Response = Response<ResponseBody<ResB>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+    fn call(&mut self, req: Request<ReqB>) -> Self::Future {
+        let since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let peer = peer(&req);
+        let host = match req.headers().get(http::header::HOST) {
+            Some(host) => String::from_utf8_lossy(host.as_bytes()).into_owned(),
+            None => req.uri().authority().map(|a| a.to_string()).unwrap_or_default(),
+        };
+        let proto = match req.uri().scheme_str() {
+            Some("https") => Protocol::Https,
+            Some("http") => Protocol::Http,
+            _ => self.layer.config.proto,
+        };
+        let entry = RequestEntry {
+            method: req.method().as_str().into(),
+            uri: req.uri().path_and_query().map_or("/", |p| p.as_str()).into(),
+            ip: peer.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |p| p.ip()),
+            port: peer.map_or(0, |p| p.port()),
+            time: since_epoch.as_secs(),
+            headers: Headers::from(req.headers()),
+            host: host.as_str().into(),
+            proto,
+            time_us: since_epoch.subsec_micros(),
+            bytes_in: headers_len(req.headers()),
+            source: self.layer.config.source.as_deref().map(Into::into),
+            ..Default::default()
+        };
+        let capture = Arc::new(Mutex::new(Capture::default()));
+        let max = self.layer.config.capture_body;
+        let req = req.map(|inner| RequestBody { inner, capture: capture.clone(), max });
+        let pending = Pending { entry: Some(entry), capture, start: Instant::now(), layer: self.layer.clone(), responded: false };
+        ResponseFuture { inner: self.inner.call(req), pending: Some(pending) }
+    }
+}
+
+#[derive(Default)]
+struct Capture {
+    body: BytesMut,
+    len: u64,
+}
+
+/// the entry of a request in flight, sent when dropped after the response started
+struct Pending {
+    entry: Option<RequestEntry>,
+    capture: Arc<Mutex<Capture>>,
+    start: Instant,
+    layer: ClogLayer,
+    responded: bool,
+}
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let Some(mut e) = self.entry.take() else { return };
+        if !self.responded || !self.layer.sampled(e.status) {
+            return;
+        }
+        e.duration = self.start.elapsed().as_micros().try_into().unwrap_or(u32::MAX);
+        if let Ok(capture) = self.capture.lock() {
+            e.bytes_in += capture.len;
+            e.body_len = capture.len.try_into().unwrap_or(u32::MAX);
+            if self.layer.config.capture_body.is_some() && capture.len > 0 {
+                e.body = Some(capture.body.clone().freeze());
+            }
+        }
+        self.layer.config.privacy.apply(&mut e);
+        if e.source.is_some() {
+            e.seq = Some(self.layer.seq.fetch_add(1, Ordering::Relaxed));
+        }
+        let _ = self.layer.tx.try_send(e);
+    }
+}
+
+pin_project! {
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        pending: Option<Pending>,
+    }
+}
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<ResponseBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let response = ready!(this.inner.poll(cx))?;
+        let mut pending = this.pending.take();
+        if let Some(ref mut p) = pending
+            && let Some(ref mut e) = p.entry
+        {
+            p.responded = true;
+            e.status = response.status().as_u16();
+            e.ttfb = p.start.elapsed().as_micros().try_into().unwrap_or(u32::MAX);
+            e.bytes_out = headers_len(response.headers());
+        }
+        Poll::Ready(Ok(response.map(|inner| ResponseBody { inner, pending })))
+    }
+}
+
+pin_project! {
+    /// request body that counts and optionally keeps the bytes read by the service
+    pub struct RequestBody<B> {
+        #[pin]
+        inner: B,
+        capture: Arc<Mutex<Capture>>,
+        max: Option<usize>,
+    }
+}
+impl<B: Body> Body for RequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(ref frame)) = frame
+            && let Some(data) = frame.data_ref()
+            && let Ok(mut capture) = this.capture.lock()
+        {
+            capture.len += data.remaining() as u64;
+            if let Some(max) = *this.max {
+                let chunk = data.chunk();
+                let keep = max.saturating_sub(capture.body.len()).min(chunk.len());
+                capture.body.extend_from_slice(&chunk[..keep]);
+            }
+        }
+        Poll::Ready(frame)
+    }
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+pin_project! {
+    /// response body that sends the entry once it ends or is dropped
+    pub struct ResponseBody<B> {
+        #[pin]
+        inner: B,
+        pending: Option<Pending>,
+    }
+}
+impl<B: Body> Body for ResponseBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match frame {
+            Some(Ok(ref frame)) => if let (Some(data), Some(e)) = (frame.data_ref(), this.pending.as_mut().and_then(|p| p.entry.as_mut())) {
+                e.bytes_out += data.remaining() as u64;
+            }
+            // the last frame was sent
+            None => drop(this.pending.take()),
+            Some(Err(_)) => {}
+        }
+        Poll::Ready(frame)
+    }
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[tokio::test]
+async fn test_layer() {
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use tower::{ServiceBuilder, ServiceExt, service_fn};
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+    let config = Config {
+        sample_rate: 0.5,
+        capture_body: Some(4),
+        privacy: PrivacyPolicy { mask_headers: vec!["authorization".into()], ..Default::default() },
+        source: Some("web-1".into()),
+        ..Default::default()
+    };
+    let service = ServiceBuilder::new()
+        .layer(ClogLayer::with_config(tx, config))
+        .service(service_fn(|req: Request<RequestBody<Full<Bytes>>>| async move {
+            let body = req.into_body().collect().await.unwrap().to_bytes();
+            let status = if body.is_empty() { 500 } else { 201 };
+            Ok::<_, std::convert::Infallible>(Response::builder().status(status).body(Full::new(Bytes::from_static(b"created"))).unwrap())
+        }));
+
+    let request = |body: &'static [u8]| {
+        let mut req = Request::post("/items?id=1")
+            .header("host", "qdat.net")
+            .header("authorization", "Bearer secret")
+            .body(Full::new(Bytes::from_static(body)))
+            .unwrap();
+        req.extensions_mut().insert("192.0.2.1:41000".parse::<SocketAddr>().unwrap());
+        req
+    };
+    for body in [&b"hello world"[..], b"a", b"b", b"", b"c"] {
+        let response = service.clone().oneshot(request(body)).await.unwrap();
+        response.into_body().collect().await.unwrap();
+    }
+
+    let e = rx.try_recv().unwrap();
+    assert_eq!((e.status, &*e.method, &*e.uri, &*e.host), (201, "POST", "/items?id=1", "qdat.net"));
+    assert_eq!((e.ip, e.port), ("192.0.2.1".parse::<IpAddr>().unwrap(), 41000));
+    assert_eq!((e.body.as_deref(), e.body_len), (Some(&b"hell"[..]), 11));
+    assert!(e.bytes_out >= 7 && e.bytes_in >= 11);
+    assert!(e.headers.pairs().contains(&("authorization", &b"redacted"[..])));
+    assert!(e.duration >= e.ttfb);
+
+    // every second request is sampled, errors are always kept
+    let rest: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+    assert_eq!(rest.iter().map(|e| e.status).collect::<Vec<_>>(), [201, 500]);
+    let seq = e.seq.unwrap();
+    assert_eq!(rest.iter().map(|e| e.seq.unwrap() - seq).collect::<Vec<_>>(), [1, 2]);
+}
@@ -1,49 +1,399 @@
 
-use std::{collections::BTreeMap, net::IpAddr, path::{Path, PathBuf}, pin::Pin};
+use std::{collections::HashMap, io::{BufRead, Write}, net::IpAddr, path::{Path, PathBuf}, pin::Pin, sync::Arc};
 
-use anyhow::Error;
+use anyhow::{Context, Error, anyhow, bail};
 use bytes::Bytes;
-use clap::{arg, builder, command, Parser};
-use clog_collector::{decode_batch, encode_batch, init_log, LogOptions};
-use clog_core::{RequestEntry, shema::{BatchEntry, Builder, Shema}};
+use clap::{arg, builder, command, Parser, Subcommand, ValueEnum};
+use clog_collector::{decode_batch_bytes, encode_batch, init_log, seal_block, tail, BlockReader, Keyring, LogOptions, TailOptions};
+use clog_arrow::{ParquetWriter, add_record_batch, read_parquet};
+use clog_core::{Options, RequestEntry, shema::{BatchEntry, Builder, Shema}};
+use clog_core::export::{self as text, Column, Exporter, Selection};
+use clog_core::filter::Filter;
+use clog_core::import::LogFormat;
 use futures::future::join_all;
 use itertools::Itertools;
-use tokio::{fs::File, io::{AsyncBufReadExt, BufReader}, spawn, sync::mpsc::{channel, Receiver}, task::JoinHandle};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::{fs::File, io::{AsyncBufReadExt, BufReader}, select, spawn, sync::mpsc::{channel, Receiver}, task::{spawn_blocking, JoinHandle}};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Name of the person to greet
-    #[arg(short, long)]
-    output: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// without a command, the inputs are merged
+    #[command(flatten)]
+    merge: MergeArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// merge block folders and JSON logs into one block folder, ordered by time
+    Merge(MergeArgs),
+    /// write the blocks of a folder to a file
+    Export {
+        #[arg(short, long, value_enum, default_value = "parquet")]
+        format: ExportFormat,
+
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// text formats are written to stdout without it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// decrypt input blocks with the keys in this file
+        #[arg(short, long)]
+        key_file: Option<PathBuf>,
+
+        /// comma separated csv columns, e.g. `time,status,uri`
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<Column>,
+
+        /// only rows matching this filter
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// only rows at or after this time, in unix seconds or RFC 3339
+        #[arg(long, value_parser = parse_time)]
+        from: Option<u64>,
+
+        /// only rows before this time, in unix seconds or RFC 3339
+        #[arg(long, value_parser = parse_time)]
+        until: Option<u64>,
+    },
+    /// read a file written by `export` or a web server access log into a new block folder
+    Import {
+        #[arg(short, long, value_enum, default_value = "parquet")]
+        format: ImportFormat,
+
+        /// the `log_format` / `LogFormat` string for `--format nginx` and `--format apache`
+        #[arg(long)]
+        log_format: Option<String>,
+
+        #[arg(short, long)]
+        input: PathBuf,
+
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(short, long, default_value = "100000")]
+        block_size: usize,
+
+        /// encrypt output blocks with the keys in this file
+        #[arg(short, long)]
+        key_file: Option<PathBuf>,
+    },
+    /// follow web server access logs, across rotation, and collect them into a block folder
+    Tail {
+        #[arg(short, long, value_enum, default_value = "combined")]
+        format: ImportFormat,
+
+        /// the `log_format` / `LogFormat` string for `--format nginx` and `--format apache`
+        #[arg(long)]
+        log_format: Option<String>,
+
+        /// also collect the lines already in the files
+        #[arg(long)]
+        from_start: bool,
+
+        /// data folder of the collector
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// encrypt blocks with the keys in this file
+        #[arg(short, long)]
+        key_file: Option<PathBuf>,
+
+        /// source name of the collected rows, e.g. the host name
+        #[arg(long)]
+        source: Option<String>,
+
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
+    /// folder to write the merged blocks to
+    #[arg(short, long, required = true)]
+    output: Option<PathBuf>,
 
     #[arg(short, long, default_value="10000")]
     block_size: usize,
 
     #[arg(short, long)]
-    input: Vec<PathBuf>
+    input: Vec<PathBuf>,
+
+    /// decrypt input blocks and encrypt output blocks with the keys in this file
+    #[arg(short, long)]
+    key_file: Option<PathBuf>,
+
+    /// comma separated source names of rows that don't have one, one per input.
+    /// defaults to the names of the input folders and files.
+    #[arg(long, value_delimiter = ',')]
+    source: Vec<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Parquet,
+    /// one JSON object per line, as read by `merge`
+    Json,
+    Csv,
+    /// Apache/nginx combined log format
+    Combined,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ImportFormat {
+    Parquet,
+    /// nginx and Apache combined log format
+    Combined,
+    /// nginx `log_format` given with `--log-format`
+    Nginx,
+    /// Apache `LogFormat` given with `--log-format`
+    Apache,
+    /// Caddy JSON access log
+    Caddy,
+}
+
+fn log_format(format: ImportFormat, log_format: Option<&str>) -> Result<LogFormat, Error> {
+    let custom = || log_format.context("--log-format is required for this format");
+    match format {
+        ImportFormat::Parquet => bail!("parquet is not a text log format"),
+        ImportFormat::Combined => Ok(LogFormat::combined()),
+        ImportFormat::Nginx => LogFormat::nginx(custom()?),
+        ImportFormat::Apache => LogFormat::apache(custom()?),
+        ImportFormat::Caddy => Ok(LogFormat::caddy()),
+    }
+}
+
+fn parse_time(s: &str) -> Result<u64, Error> {
+    if let Ok(t) = s.parse() {
+        return Ok(t);
+    }
+    let t = OffsetDateTime::parse(s, &Rfc3339)?;
+    Ok(t.unix_timestamp().try_into()?)
+}
+
+fn load_keys(path: Option<&Path>) -> Result<Option<Arc<Keyring>>, Error> {
+    Ok(path.map(Keyring::load).transpose()?.map(Arc::new))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let args = Args::parse();
-    merge(&args.input, &args.output, args.block_size).await?;
+    match args.command.unwrap_or(Command::Merge(args.merge)) {
+        Command::Merge(args) => {
+            let keys = load_keys(args.key_file.as_deref())?;
+            let output = args.output.context("--output is required")?;
+            let sources = match args.source.is_empty() {
+                true => args.input.iter().map(|p| input_name(p)).collect::<Result<_, _>>()?,
+                false => args.source,
+            };
+            merge(&args.input, &sources, &output, args.block_size, keys).await?;
+        }
+        Command::Export { format, input, output, key_file, columns, filter, from, until } => {
+            let keys = load_keys(key_file.as_deref())?;
+            let filter = filter.map(|f| Filter::parse(&f).map_err(|e| anyhow!("invalid filter: {e}"))).transpose()?;
+            let selection = Selection { filter, from, until };
+            let format = match format {
+                ExportFormat::Parquet => {
+                    let output = output.context("--output is required for parquet")?;
+                    if selection.filter.is_some() || from.is_some() || until.is_some() {
+                        bail!("--filter, --from and --until are not supported for parquet");
+                    }
+                    spawn_blocking(move || export_parquet(&input, &output, keys)).await??;
+                    return Ok(());
+                }
+                ExportFormat::Json => text::Format::Json,
+                ExportFormat::Csv if columns.is_empty() => text::Format::Csv(Column::DEFAULT.to_vec()),
+                ExportFormat::Csv => text::Format::Csv(columns),
+                ExportFormat::Combined => text::Format::Combined,
+            };
+            spawn_blocking(move || match output {
+                Some(path) => export_text(&input, std::io::BufWriter::new(std::fs::File::create(path)?), format, selection, keys),
+                None => export_text(&input, std::io::stdout().lock(), format, selection, keys),
+            }).await??;
+        }
+        Command::Import { format: ImportFormat::Parquet, input, output, block_size, key_file, .. } => {
+            let keys = load_keys(key_file.as_deref())?;
+            import_parquet(input, &output, block_size, keys).await?;
+        }
+        Command::Import { format, log_format: custom, input, output, block_size, key_file } => {
+            let keys = load_keys(key_file.as_deref())?;
+            let format = log_format(format, custom.as_deref())?;
+            import_log(input, &output, format, block_size, keys).await?;
+        }
+        Command::Tail { format, log_format: custom, from_start, output, key_file, source, files } => {
+            let format = Arc::new(log_format(format, custom.as_deref())?);
+            let keys = load_keys(key_file.as_deref())?;
+            tail_logs(files, format, from_start, output, source, keys).await?;
+        }
+    }
+    Ok(())
+}
+
+fn export_parquet(input: &Path, output: &Path, keys: Option<Arc<Keyring>>) -> Result<(), Error> {
+    let mut reader = BlockReader::open(input, 1 << 30)?.with_keys(keys);
+    let file = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let mut writer = ParquetWriter::new(file)?;
+
+    let starts: Vec<u64> = reader.starts().collect();
+    for n in starts {
+        println!("  block {n}");
+        if let Some(data) = reader.get(n)? {
+            let (start, builder) = decode_batch_bytes::<Builder>(&data)?;
+            writer.write(start, &builder)?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn export_text(input: &Path, out: impl Write, format: text::Format, selection: Selection, keys: Option<Arc<Keyring>>) -> Result<(), Error> {
+    let mut reader = BlockReader::open(input, 1 << 30)?.with_keys(keys);
+    let mut exporter = Exporter::new(out, format, selection);
+
+    let starts: Vec<u64> = reader.starts().collect();
+    for n in starts {
+        if let Some(data) = reader.get(n)? {
+            let (start, builder) = decode_batch_bytes::<Builder>(&data)?;
+            match exporter.write_block(start, &builder) {
+                Ok(_) => {}
+                // e.g. piped into `head`
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    exporter.finish()?;
     Ok(())
 }
 
-async fn merge(input_folders: &[PathBuf], output: &PathBuf, block_size: usize) -> Result<(), Error> {
+async fn import_parquet(input: PathBuf, output: &Path, block_size: usize, keys: Option<Arc<Keyring>>) -> Result<(), Error> {
     if !output.exists() {
         tokio::fs::create_dir(output).await?;
     }
-    let mut output = Writer::new(output.into(), 100_000);
+    let mut writer = Writer::new(output.into(), block_size, keys);
 
-    let (rxs, handles) = join(input_folders, block_size).await?;
-    let mut inputs = Inputs::new(rxs).await?;
+    let (tx, mut rx) = channel(4);
+    let handle = spawn_blocking(move || {
+        for batch in read_parquet(std::fs::File::open(input)?, block_size)? {
+            let mut builder = Builder::with_capacity(block_size);
+            add_record_batch(&batch?, &mut builder)?;
+            tx.blocking_send(builder)?;
+        }
+        Result::<(), Error>::Ok(())
+    });
+    while let Some(builder) = rx.recv().await {
+        for e in builder.iter() {
+            writer.push(e.map_err(Error::msg)?).await?;
+        }
+    }
+    writer.flush().await?;
+    handle.await??;
+    Ok(())
+}
 
+async fn import_log(input: PathBuf, output: &Path, format: LogFormat, block_size: usize, keys: Option<Arc<Keyring>>) -> Result<(), Error> {
+    if !output.exists() {
+        tokio::fs::create_dir(output).await?;
+    }
+    let mut writer = Writer::new(output.into(), block_size, keys);
+
+    let (tx, mut rx) = channel(1024);
+    let handle = spawn_blocking(move || {
+        let reader = std::io::BufReader::new(std::fs::File::open(&input)?);
+        for (n, line) in reader.split(b'\n').enumerate() {
+            match format.parse(&String::from_utf8_lossy(&line?)) {
+                Ok(Some(e)) => tx.blocking_send(e)?,
+                Ok(None) => {}
+                Err(e) => eprintln!("{}:{}: {e:#}", input.display(), n + 1),
+            }
+        }
+        Result::<(), Error>::Ok(())
+    });
+    while let Some(e) = rx.recv().await {
+        writer.push(BatchEntry::from(&e)).await?;
+    }
+    writer.flush().await?;
+    handle.await??;
+    Ok(())
+}
+
+async fn tail_logs(files: Vec<PathBuf>, format: Arc<LogFormat>, from_start: bool, data_dir: PathBuf, source: Option<String>, keys: Option<Arc<Keyring>>) -> Result<(), Error> {
+    if !data_dir.exists() {
+        tokio::fs::create_dir(&data_dir).await?;
+    }
+    let options = LogOptions { data_dir: Some(data_dir), read_old: true, keys, source, ..Default::default() };
+    let (collector, tx) = init_log(options).await?;
+
+    let mut handles = vec![];
+    for path in files {
+        let (format, tx) = (format.clone(), tx.clone());
+        handles.push(spawn(async move {
+            let options = TailOptions { from_start, ..Default::default() };
+            tail(&path, &format, options, tx).await
+        }));
+    }
+    drop(tx);
+
+    select! {
+        r = join_all(handles) => {
+            for r in r {
+                r??;
+            }
+        }
+        r = tokio::signal::ctrl_c() => r?,
+    }
+    collector.flush().await?;
+    Ok(())
+}
+
+fn input_name(path: &Path) -> Result<String, Error> {
+    let name = if path.is_dir() { path.file_name() } else { path.file_stem() };
+    name.and_then(|n| n.to_str()).map(String::from).with_context(|| format!("no source name for {}, use --source", path.display()))
+}
+
+/// Merge the inputs ordered by time.
+///
+/// Rows without a source get the source of their input and their row id in it as seq,
+/// so rows keep their identity in the output. Rows seen before, with the same source and seq, are skipped.
+async fn merge(input_folders: &[PathBuf], sources: &[String], output: &Path, block_size: usize, keys: Option<Arc<Keyring>>) -> Result<(), Error> {
+    if sources.len() != input_folders.len() {
+        bail!("{} sources for {} inputs", sources.len(), input_folders.len());
+    }
+    if let Some(name) = sources.iter().duplicates().next() {
+        bail!("inputs without source names would be merged as {name:?}, use --source");
+    }
+    if !output.exists() {
+        tokio::fs::create_dir(output).await?;
+    }
+    let mut output = Writer::new(output.into(), 100_000, keys.clone());
+
+    let (rxs, handles) = join(input_folders, block_size, keys).await?;
+    let mut inputs = Inputs::new(rxs, sources).await?;
+
+    // seqs of the rows written so far, by source
+    let mut seen: HashMap<String, SeqRanges> = HashMap::new();
+    let mut duplicates = 0;
     while let Some(e) = inputs.read() {
-        output.push(e).await?;
+        let seqs = match seen.get_mut(e.source) {
+            Some(seqs) => seqs,
+            None => seen.entry(e.source.to_string()).or_default(),
+        };
+        if seqs.insert(e.seq) {
+            output.push(e).await?;
+        } else {
+            duplicates += 1;
+        }
         inputs.advance().await?;
     }
+    if duplicates > 0 {
+        println!("skipped {duplicates} duplicate rows");
+    }
 
     output.flush().await?;
 
@@ -54,12 +404,47 @@ async fn merge(input_folders: &[PathBuf], output: &PathBuf, block_size: usize) -
     Ok(())
 }
 
-async fn join(inputs: &[PathBuf], block_size: usize) -> Result<(Vec<Receiver<Bytes>>, Vec<JoinHandle<Result<(), Error>>>), Error> {
+/// Seqs seen, as sorted non-overlapping ranges.
+///
+/// Seqs increase within a source, so this stays a handful of ranges
+/// instead of one entry per row.
+#[derive(Default)]
+struct SeqRanges(Vec<std::ops::Range<u64>>);
+
+impl SeqRanges {
+    /// false if seq was seen before
+    fn insert(&mut self, seq: u64) -> bool {
+        // first range ending at or after seq
+        let i = self.0.partition_point(|r| r.end < seq);
+        let Some(r) = self.0.get(i) else {
+            self.0.push(seq..seq + 1);
+            return true;
+        };
+        if r.contains(&seq) {
+            return false;
+        }
+        if r.end == seq {
+            // join the next range if seq closes the gap
+            let end = match self.0.get(i + 1) {
+                Some(next) if next.start == seq + 1 => self.0.remove(i + 1).end,
+                _ => seq + 1,
+            };
+            self.0[i].end = end;
+        } else if r.start == seq + 1 {
+            self.0[i].start = seq;
+        } else {
+            self.0.insert(i, seq..seq + 1);
+        }
+        true
+    }
+}
+
+async fn join(inputs: &[PathBuf], block_size: usize, keys: Option<Arc<Keyring>>) -> Result<(Vec<Receiver<Bytes>>, Vec<JoinHandle<Result<(), Error>>>), Error> {
     let mut rxs = vec![];
     let mut handles = vec![];
     for path in inputs {
         if path.is_dir() {
-            let (rx, handle) = read_buffers(path).await?;
+            let (rx, handle) = read_buffers(path, keys.clone()).await?;
             rxs.push(rx);
             handles.push(handle);
         } else {
@@ -72,26 +457,18 @@ async fn join(inputs: &[PathBuf], block_size: usize) -> Result<(Vec<Receiver<Byt
     Ok((rxs, handles))
 }
 
-async fn read_buffers(path: &Path) -> Result<(Receiver<Bytes>, JoinHandle<Result<(), Error>>), Error> {
-    let mut dir = tokio::fs::read_dir(path).await?;
-
-    let mut entries: BTreeMap<u64, PathBuf> = BTreeMap::new();
+async fn read_buffers(path: &Path, keys: Option<Arc<Keyring>>) -> Result<(Receiver<Bytes>, JoinHandle<Result<(), Error>>), Error> {
+    let mut reader = BlockReader::open(path, 1 << 30)?.with_keys(keys);
 
     let (tx, rx) = channel(4);
-    while let Some(entry) = dir.next_entry().await? {
-        let path = entry.path();
-        if path.extension().map(|e| e == "clog").unwrap_or(false) {
-            if let Some(n) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.strip_prefix("block-")).and_then(|s| s.parse::<u64>().ok()) {
-                println!("  block {n}");
-                entries.insert(n, path);
+    let handle = spawn_blocking(move || {
+        let starts: Vec<u64> = reader.starts().collect();
+        for n in starts {
+            println!("  block {n}");
+            if let Some(data) = reader.get(n)? {
+                tx.blocking_send(data)?;
             }
         }
-    }
-    let handle = spawn(async move {
-        for (n, path) in entries {
-            let data = tokio::fs::read(path).await?;
-            tx.send(data.into()).await?;
-        }
         Result::<(), Error>::Ok(())
     });
     Ok((rx, handle))
@@ -113,10 +490,11 @@ async fn read_log(path: &Path, block_size: usize) -> Result<(Receiver<Bytes>, Jo
             if n == 0 {
                 break;
             }
-            if let Ok(out) = serde_json::from_str::<RequestEntry>(&line) {
+            if let Ok(mut out) = serde_json::from_str::<RequestEntry>(&line) {
+                out.seq.get_or_insert(start + builder.len() as u64);
                 builder.add(BatchEntry::from(&out));
                 if builder.len() >= block_size {
-                    let bytes = encode_batch(start, &builder, 11);
+                    let bytes = encode_batch(start, &builder, &Options::archive());
                     tx.send(bytes).await?;
 
                     start += builder.len() as u64;
@@ -126,7 +504,7 @@ async fn read_log(path: &Path, block_size: usize) -> Result<(Receiver<Bytes>, Jo
             line.clear();
         }
         if builder.len() > 0 {
-            let bytes = encode_batch(start, &builder, 11);
+            let bytes = encode_batch(start, &builder, &Options::archive());
             tx.send(bytes).await?;
         }
         Result::<(), Error>::Ok(())
@@ -140,6 +518,9 @@ struct Input {
     builder: Builder,
     rx: Receiver<Bytes>,
     pos: usize,
+    /// row id of the first row of `builder`
+    start: u64,
+    source: String,
 }
 
 struct Inputs {
@@ -147,16 +528,16 @@ struct Inputs {
     next_idx: usize,
 }
 impl Inputs {
-    pub async fn new(rxs: Vec<Receiver<Bytes>>) -> Result<Self, Error> {
+    pub async fn new(rxs: Vec<Receiver<Bytes>>, sources: &[String]) -> Result<Self, Error> {
         let mut inputs = Vec::with_capacity(rxs.len());
 
         println!("{} channels", rxs.len());
-        for (j, mut rx) in rxs.into_iter().enumerate() {
+        for (j, (mut rx, source)) in rxs.into_iter().zip(sources).enumerate() {
             if let Some(batch) = rx.recv().await {
-                let (_, builder) = decode_batch(&batch)?;
+                let (start, builder) = decode_batch_bytes::<Builder>(&batch)?;
                 println!("{j} batch with {} items", builder.len());
                 if let Some(e) = builder.get(0) {
-                    inputs.push(Input { t: e.time, builder, rx, pos: 0 });
+                    inputs.push(Input { t: e.time, builder, rx, pos: 0, start, source: source.clone() });
                 }
             } else {
                 println!("{j} no input");
@@ -167,9 +548,15 @@ impl Inputs {
         Ok(i)
     }
 
+    /// the next row, with the source of its input if it has none
     pub fn read(&self) -> Option<BatchEntry> {
         let i = self.inputs.get(self.next_idx)?;
-        i.builder.get(i.pos)
+        let mut e = i.builder.get(i.pos)?;
+        if e.source.is_empty() {
+            e.source = &i.source;
+            e.seq = i.start + i.pos as u64;
+        }
+        Some(e)
     }
     fn find_next(&mut self) -> Option<u64> {
         let (idx, i) = self.inputs.iter().enumerate().min_by_key(|(n, i)| i.t)?;
@@ -188,11 +575,12 @@ impl Inputs {
                 }
                 None => {
                     if let Some(batch) = i.rx.recv().await {
-                        let (_, builder) = decode_batch(&batch)?;
+                        let (start, builder) = decode_batch_bytes::<Builder>(&batch)?;
                         println!("new batch with {} items", builder.len());
                         if let Some(e) = builder.get(0) {
                             let t = e.time;
                             i.builder = builder;
+                            i.start = start;
                             i.pos = 0;
                             i.t = t;
                             return Ok(Some(t));
@@ -214,14 +602,16 @@ struct Writer {
     current: Builder,
     current_start: u64,
     block_limit: usize,
+    keys: Option<Arc<Keyring>>,
 }
 impl Writer {
-    pub fn new(folder: PathBuf, block_limit: usize) -> Self {
+    pub fn new(folder: PathBuf, block_limit: usize, keys: Option<Arc<Keyring>>) -> Self {
         Writer {
             folder,
             current: Builder::with_capacity(block_limit),
             current_start: 0,
-            block_limit
+            block_limit,
+            keys,
         }
     }
     async fn push<'a>(&mut self, entry: BatchEntry<'a>) -> Result<(), Error> {
@@ -234,7 +624,8 @@ impl Writer {
     }
     async fn flush(&mut self) -> Result<(), Error> {
         if self.current.len() > 0 {
-            let data = encode_batch(self.current_start, &self.current, 11);
+            let data = encode_batch(self.current_start, &self.current, &Options::archive());
+            let data = seal_block(self.keys.as_deref(), &data)?;
             let path = self.folder.join(format!("block-{}.clog", self.current_start));
 
             tokio::fs::write(path, &data).await?;
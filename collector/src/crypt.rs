@@ -0,0 +1,129 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Error, bail};
+use bytes::Bytes;
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, Nonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+
+/// start of an encrypted block file, followed by the key id (u32 LE), the nonce and the ciphertext.
+/// plain block files start with `PacketType::Batch`.
+const MAGIC: &[u8; 4] = b"CLE1";
+const HEADER_LEN: usize = MAGIC.len() + 4;
+const NONCE_LEN: usize = 12;
+
+/// ChaCha20-Poly1305 keys for block files, by key id.
+///
+/// The key file has one key per line, `<id> <64 hex digits>`. Empty lines and lines starting
+/// with `#` are ignored. New blocks are encrypted with the last key, so keys are rotated
+/// by appending a line and old blocks stay readable as long as their key is kept.
+pub struct Keyring {
+    keys: BTreeMap<u32, Key>,
+    current: u32,
+}
+impl Keyring {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut keys = BTreeMap::new();
+        let mut current = None;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (id, hex) = line.split_once(char::is_whitespace).context("expected `<id> <key>`")?;
+            let id: u32 = id.parse()?;
+            let key = parse_key(hex.trim())?;
+            if keys.insert(id, key).is_some() {
+                bail!("duplicate key id {id}");
+            }
+            current = Some(id);
+        }
+        let current = current.context("no keys")?;
+        Ok(Keyring { keys, current })
+    }
+    pub fn encrypt(&self, data: &[u8]) -> Result<Bytes, Error> {
+        let mut out = Vec::with_capacity(HEADER_LEN + NONCE_LEN + data.len() + 16);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&self.current.to_le_bytes());
+
+        let cipher = ChaCha20Poly1305::new(&self.keys[&self.current]);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = cipher.encrypt(&nonce, Payload { msg: data, aad: &out })
+            .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out.into())
+    }
+    fn decrypt(&self, data: &[u8]) -> Result<Bytes, Error> {
+        let (header, rest) = data.split_at_checked(HEADER_LEN).context("truncated block")?;
+        let id = u32::from_le_bytes(header[MAGIC.len()..].try_into()?);
+        let key = self.keys.get(&id).with_context(|| format!("no key with id {id}"))?;
+        let (nonce, sealed) = rest.split_at_checked(NONCE_LEN).context("truncated block")?;
+
+        let cipher = ChaCha20Poly1305::new(key);
+        let plain = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: header })
+            .map_err(|_| anyhow::anyhow!("block failed authentication with key {id}"))?;
+        Ok(plain.into())
+    }
+}
+
+pub(crate) fn parse_key(hex: &str) -> Result<Key, Error> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        bail!("key must be 64 hex digits");
+    }
+    let mut key = Key::default();
+    for (b, pair) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *b = u8::from_str_radix(std::str::from_utf8(pair)?, 16)?;
+    }
+    Ok(key)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// the plain block of a block file, decrypting it if needed.
+/// an encrypted block is decrypted into a new buffer, `data` is returned as is otherwise.
+pub fn open_block(keys: Option<&Keyring>, data: Bytes) -> Result<Bytes, Error> {
+    if !is_encrypted(&data) {
+        return Ok(data);
+    }
+    match keys {
+        Some(keys) => keys.decrypt(&data),
+        None => bail!("block is encrypted, but no key file was given"),
+    }
+}
+
+/// the data to write to a block file, encrypting it if there are keys
+pub fn seal_block(keys: Option<&Keyring>, data: &Bytes) -> Result<Bytes, Error> {
+    match keys {
+        Some(keys) => keys.encrypt(data),
+        None => Ok(data.clone()),
+    }
+}
+
+#[test]
+fn test_keyring() {
+    let old = Keyring::parse(&format!("# test\n1 {}\n", "ab".repeat(32))).unwrap();
+    let new = Keyring::parse(&format!("1 {}\n2 {}\n", "ab".repeat(32), "0f".repeat(32))).unwrap();
+    let block = Bytes::from_static(b"\x01block data");
+
+    let sealed = seal_block(Some(&old), &block).unwrap();
+    assert!(is_encrypted(&sealed));
+    assert_eq!(open_block(Some(&new), sealed.clone()).unwrap(), block);
+    assert!(open_block(None, sealed.clone()).is_err());
+
+    let sealed2 = seal_block(Some(&new), &block).unwrap();
+    assert!(open_block(Some(&old), sealed2.clone()).is_err());
+
+    let mut tampered = sealed2.to_vec();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(open_block(Some(&new), tampered.into()).is_err());
+
+    assert_eq!(open_block(None, block.clone()).unwrap(), block);
+    assert!(Keyring::parse("1 abcd").is_err());
+}
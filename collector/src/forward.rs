@@ -0,0 +1,628 @@
+//! Forwarding of blocks, and optionally live rows, from edge collectors to a central one.
+//!
+//! The edge connects over TCP and sends frames of a u32 LE length followed by a packet.
+//! The central collector starts with a random 32 byte challenge, the edge answers with the name
+//! of the source and the BLAKE3 MAC of challenge and name keyed with the shared secret.
+//! Then it sends `Batch` packets of finished blocks, and with `live`
+//! a `Sync` packet, the unfinished block as a `Batch` packet and `Row` packets.
+//! After the name and after each finished block the central collector answers with the
+//! number of rows it stored, as u64 LE. The edge continues from there after a reconnect.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Error, anyhow, bail};
+use bytes::Bytes;
+use chacha20poly1305::aead::{OsRng, rand_core::RngCore};
+use clog_core::{Options, PacketType, SyncHeader, shema::{Builder, Shema}};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::{TcpListener, TcpStream},
+    select,
+    sync::{Mutex, broadcast, mpsc::{Sender, channel}, oneshot},
+    time::{interval, sleep},
+};
+
+use crate::{
+    BatchHeader, ClientMsg, CollectorBackend, LogCollector, LogOptions, PastCommand, PastManager,
+    crypt::parse_key, decode_batch_bytes, encode_batch,
+};
+
+/// blocks are far smaller than this, even after compaction
+const MAX_FRAME: usize = 64 << 20;
+const MAX_SOURCE: usize = 255;
+
+/// Key shared by the edge and central collectors.
+pub type ForwardSecret = [u8; 32];
+
+/// read a `ForwardSecret` from a file of 64 hex digits
+pub fn load_secret(path: &Path) -> Result<ForwardSecret, Error> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    Ok(parse_key(text.trim()).with_context(|| format!("parsing {}", path.display()))?.into())
+}
+
+/// answer to the `challenge` of the central collector
+fn proof(secret: &ForwardSecret, challenge: &[u8; 32], source: &[u8]) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new_keyed(secret);
+    hasher.update(challenge);
+    hasher.update(source);
+    hasher.finalize()
+}
+
+pub struct ForwardOptions {
+    /// name of this collector on the central one
+    pub source: String,
+    /// the secret of `Replicas::new`
+    pub secret: ForwardSecret,
+    /// send rows as they arrive, not only finished blocks
+    pub live: bool,
+    /// how often to look for finished blocks
+    pub poll: Duration,
+    /// wait before connecting again
+    pub retry: Duration,
+}
+impl ForwardOptions {
+    pub fn new(source: &str, secret: ForwardSecret) -> Self {
+        ForwardOptions { source: source.into(), secret, live: false, poll: Duration::from_secs(1), retry: Duration::from_secs(5) }
+    }
+}
+
+async fn write_frame(w: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> Result<(), Error> {
+    w.write_u32_le(data.len().try_into()?).await?;
+    w.write_all(data).await?;
+    Ok(())
+}
+/// the next frame of up to `max` bytes, `None` at the end of the stream
+async fn read_frame(r: &mut (impl AsyncRead + Unpin), max: usize) -> Result<Option<Bytes>, Error> {
+    let len = match r.read_u32_le().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len == 0 || len > max {
+        bail!("invalid frame length {len}");
+    }
+    // grows as the data arrives, not to what the peer claims
+    let mut buf = Vec::with_capacity(len.min(64 << 10));
+    r.take(len as u64).read_to_end(&mut buf).await?;
+    if buf.len() != len {
+        bail!("frame ended after {} of {len} bytes", buf.len());
+    }
+    Ok(Some(buf.into()))
+}
+
+/// start of a `Batch` packet, without decoding the block
+fn batch_start(data: &[u8]) -> Option<u64> {
+    let (&ptype, rest) = data.split_first()?;
+    if ptype != PacketType::Batch as u8 {
+        return None;
+    }
+    postcard::take_from_bytes::<BatchHeader>(rest).ok().map(|(h, _)| h.start)
+}
+
+/// Send the blocks of `collector` to the central collector at `upstream` (`host:port`), see `Replicas::serve`.
+///
+/// Connects again after errors and continues with the first block the central collector did not store.
+/// Runs until the collector stopped. Only collectors of `RequestEntry`s can be forwarded.
+pub async fn forward(collector: LogCollector, upstream: &str, options: ForwardOptions) -> Result<(), Error> {
+    let mut last = None;
+    loop {
+        let session = async {
+            let stream = TcpStream::connect(upstream).await?;
+            stream.set_nodelay(true)?;
+            send_session(&collector, stream, &options, &mut last).await
+        };
+        match session.await {
+            Ok(()) => return Ok(()),
+            Err(e) => eprintln!("forwarding to {upstream}: {e:#}"),
+        }
+        sleep(options.retry).await;
+    }
+}
+
+/// the start, size and end of the last block sent, so it is decoded only once
+type LastBlock = Option<(u64, usize, u64)>;
+
+async fn send_session(collector: &LogCollector, stream: TcpStream, options: &ForwardOptions, last: &mut LastBlock) -> Result<(), Error> {
+    let (rd, wr) = stream.into_split();
+    let mut rd = BufReader::new(rd);
+    let mut wr = BufWriter::new(wr);
+    let mut challenge = [0; 32];
+    rd.read_exact(&mut challenge).await?;
+    write_frame(&mut wr, options.source.as_bytes()).await?;
+    wr.write_all(proof(&options.secret, &challenge, options.source.as_bytes()).as_bytes()).await?;
+    wr.flush().await?;
+    let mut next = rd.read_u64_le().await?;
+
+    let mut rows = match options.live {
+        true => Some(attach(collector, &mut wr).await?),
+        false => None,
+    };
+    let mut timer = interval(options.poll);
+    loop {
+        select! {
+            _ = timer.tick() => {
+                if send_blocks(collector, &mut rd, &mut wr, &mut next, last).await? {
+                    return Ok(());
+                }
+            }
+            row = async { rows.as_mut().unwrap().recv().await }, if rows.is_some() => match row {
+                Ok(row) => {
+                    write_frame(&mut wr, &row).await?;
+                    if rows.as_ref().is_some_and(|r| r.is_empty()) {
+                        wr.flush().await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => rows = Some(attach(collector, &mut wr).await?),
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// follow the rows of `collector`, after sending where they start and the unfinished block before them
+async fn attach(collector: &LogCollector, wr: &mut (impl AsyncWrite + Unpin)) -> Result<broadcast::Receiver<Bytes>, Error> {
+    let mut handle = collector.attach_with_backlog(0).await?;
+    let sync = handle.batch_rx.recv().await.context("collector stopped")?;
+    let (_, header) = sync.split_first().context("empty sync")?;
+    let header: SyncHeader = postcard::from_bytes(header)?;
+    write_frame(wr, &sync).await?;
+    if header.first_backlog < header.start {
+        // the last finished block is sent as backlog as well
+        loop {
+            let data = handle.batch_rx.recv().await.context("collector stopped")?;
+            if batch_start(&data) == Some(header.first_backlog) {
+                write_frame(wr, &data).await?;
+                break;
+            }
+        }
+    }
+    wr.flush().await?;
+    Ok(handle.row_rx)
+}
+
+/// send the finished blocks from `next` on, returns true if the collector stopped
+async fn send_blocks(
+    collector: &LogCollector,
+    rd: &mut (impl AsyncRead + Unpin),
+    wr: &mut (impl AsyncWrite + Unpin),
+    next: &mut u64,
+    last: &mut LastBlock,
+) -> Result<bool, Error> {
+    loop {
+        let data = match collector.block_at(*next).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(false),
+            Err(_) => return Ok(true),
+        };
+        let start = batch_start(&data).context("invalid block")?;
+        let size = data.len();
+        let data = if start < *next {
+            // sent already, or merged with blocks that were sent
+            let end = match *last {
+                Some((s, n, end)) if (s, n) == (start, size) => end,
+                _ => {
+                    let (_, builder) = decode_batch_bytes::<Builder>(&data)?;
+                    start + builder.len() as u64
+                }
+            };
+            *last = Some((start, size, end));
+            if end <= *next {
+                return Ok(false);
+            }
+            let (_, builder) = decode_batch_bytes::<Builder>(&data)?;
+            let mut rest = Builder::default();
+            for idx in (*next - start) as usize..builder.len() {
+                rest.add(builder.get(idx).context("corrupt row")?);
+            }
+            encode_batch(*next, &rest, &Options::archive())
+        } else {
+            data
+        };
+        write_frame(wr, &data).await?;
+        wr.flush().await?;
+        let stored = rd.read_u64_le().await?;
+        if stored <= *next {
+            bail!("block {start} was not stored");
+        }
+        *last = Some((start, size, stored));
+        *next = stored;
+    }
+}
+
+enum Replicated {
+    /// rows stored so far
+    Next(oneshot::Sender<u64>),
+    Block(Bytes, oneshot::Sender<Result<u64, Error>>),
+    /// the unfinished block of the source, live rows follow it
+    Sync { block_size: usize, start: u64, current: Option<Bytes> },
+    Row(Bytes),
+}
+
+#[derive(Clone)]
+struct Replica {
+    collector: LogCollector,
+    tx: Sender<Replicated>,
+    /// held while the source is connected
+    connected: Arc<Mutex<()>>,
+}
+
+/// Collectors of the sources forwarding to this one, each with its own directory in `dir`.
+pub struct Replicas {
+    dir: PathBuf,
+    secret: ForwardSecret,
+    options: Box<dyn Fn() -> LogOptions + Send + Sync>,
+    sources: Mutex<HashMap<String, Replica>>,
+}
+impl Replicas {
+    /// `options` gives the options of each source's collector, its `data_dir` is set to `dir/{source}`.
+    /// only edges with `secret` can connect.
+    pub fn new(dir: PathBuf, secret: ForwardSecret, options: impl Fn() -> LogOptions + Send + Sync + 'static) -> Self {
+        Replicas { dir, secret, options: Box::new(options), sources: Default::default() }
+    }
+    /// names of the sources that forwarded blocks
+    pub async fn sources(&self) -> Result<Vec<String>, Error> {
+        let mut names = vec![];
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if entry.file_type().await?.is_dir()
+                && let Some(name) = entry.file_name().to_str()
+                && valid_source(name)
+            {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+    /// the collector of `source`, e.g. for `handle_ws`. only sources that forwarded blocks are opened.
+    pub async fn collector(&self, source: &str) -> Result<LogCollector, Error> {
+        if !valid_source(source) || !tokio::fs::try_exists(self.dir.join(source)).await? {
+            bail!("no source {source:?}");
+        }
+        Ok(self.replica(source).await?.collector)
+    }
+    async fn replica(&self, source: &str) -> Result<Replica, Error> {
+        if !valid_source(source) {
+            bail!("invalid source name {source:?}");
+        }
+        let mut sources = self.sources.lock().await;
+        if let Some(replica) = sources.get(source) {
+            return Ok(replica.clone());
+        }
+        let dir = self.dir.join(source);
+        tokio::fs::create_dir_all(&dir).await?;
+        let options = LogOptions { data_dir: Some(dir), ..(self.options)() };
+        let (collector, tx) = init_replica(options).await.with_context(|| format!("opening source {source}"))?;
+        let replica = Replica { collector, tx, connected: Default::default() };
+        sources.insert(source.into(), replica.clone());
+        Ok(replica)
+    }
+    /// accept connections from `forward`
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<(), Error> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let replicas = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = replicas.receive_session(stream).await {
+                    eprintln!("forwarding from {peer}: {e:#}");
+                }
+            });
+        }
+    }
+    async fn receive_session(&self, stream: TcpStream) -> Result<(), Error> {
+        stream.set_nodelay(true)?;
+        let (rd, mut wr) = stream.into_split();
+        let mut rd = BufReader::new(rd);
+        let mut challenge = [0; 32];
+        OsRng.fill_bytes(&mut challenge);
+        wr.write_all(&challenge).await?;
+        let hello = read_frame(&mut rd, MAX_SOURCE).await?.context("no source name")?;
+        let mut mac = [0; 32];
+        rd.read_exact(&mut mac).await?;
+        // `Hash` compares in constant time
+        if proof(&self.secret, &challenge, &hello) != blake3::Hash::from(mac) {
+            bail!("wrong secret");
+        }
+        let source = std::str::from_utf8(&hello)?;
+        let replica = self.replica(source).await?;
+        let _connected = replica.connected.clone().try_lock_owned().map_err(|_| anyhow!("{source} is connected already"))?;
+        let closed = || anyhow!("collector of {source} stopped");
+
+        let (tx, rx) = oneshot::channel();
+        replica.tx.send(Replicated::Next(tx)).await.map_err(|_| closed())?;
+        wr.write_u64_le(rx.await?).await?;
+
+        while let Some(frame) = read_frame(&mut rd, MAX_FRAME).await? {
+            let msg = match PacketType::parse(frame[0]) {
+                Some(PacketType::Batch) => {
+                    let (tx, rx) = oneshot::channel();
+                    replica.tx.send(Replicated::Block(frame, tx)).await.map_err(|_| closed())?;
+                    wr.write_u64_le(rx.await??).await?;
+                    continue;
+                }
+                Some(PacketType::Sync) => {
+                    let header: SyncHeader = postcard::from_bytes(&frame[1..])?;
+                    let current = match header.first_backlog < header.start {
+                        true => Some(read_frame(&mut rd, MAX_FRAME).await?.context("unfinished block missing")?),
+                        false => None,
+                    };
+                    Replicated::Sync { block_size: header.block_size, start: header.first_backlog, current }
+                }
+                Some(PacketType::Row) => Replicated::Row(frame),
+                _ => bail!("unexpected packet {}", frame[0]),
+            };
+            replica.tx.send(msg).await.map_err(|_| closed())?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn valid_source(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+/// a collector that stores the blocks it is sent instead of cutting its own
+async fn init_replica(options: LogOptions) -> Result<(LogCollector, Sender<Replicated>), Error> {
+    let (client_tx, mut client_rx) = channel::<ClientMsg>(128);
+    let (past_tx, past_rx) = channel(128);
+    let (row_tx, _) = broadcast::channel(4096);
+    let (tx, mut rx) = channel(128);
+
+    let mut past = PastManager::new::<Builder>(&options, past_rx);
+    past.read().await?;
+    let mut next = 0;
+    if let Some(&start) = past.past_buffers.keys().next_back() {
+        let data = past.load(start).await.with_context(|| format!("reading block {start}"))?;
+        let (_, builder) = decode_batch_bytes::<Builder>(&data)?;
+        next = start + builder.len() as u64;
+    }
+    let mut backend = CollectorBackend {
+        past_tx,
+        blocks: options.blocks.clone(),
+        current: Builder::default(),
+        current_start: next,
+        current_bytes: 0,
+        current_since: None,
+        tx: row_tx,
+        wal: None,
+        last_stats: Default::default(),
+    };
+
+    // row id of the next live row
+    let mut live = next;
+    tokio::spawn(async move {
+        loop {
+            select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => if let Err(e) = replicate(&mut backend, &mut next, &mut live, msg).await {
+                        eprintln!("{e:#}");
+                    }
+                    None => break,
+                },
+                Some(msg) = client_rx.recv() => {
+                    backend.handle_msg(msg).await;
+                }
+                else => return
+            }
+        }
+        while let Some(msg) = client_rx.recv().await {
+            backend.handle_msg(msg).await;
+        }
+    });
+    tokio::spawn(async move {
+        past.run().await;
+    });
+
+    Ok((LogCollector { tx: client_tx }, tx))
+}
+
+/// apply `msg` of the edge. `next` is the end of the stored rows, `live` the row id of the next live row.
+async fn replicate(backend: &mut CollectorBackend<Builder>, next: &mut u64, live: &mut u64, msg: Replicated) -> Result<(), Error> {
+    match msg {
+        Replicated::Next(tx) => {
+            let _ = tx.send(*next);
+        }
+        Replicated::Block(data, tx) => {
+            let _ = tx.send(store_block(backend, next, data).await);
+        }
+        Replicated::Sync { block_size, start, current } => {
+            backend.blocks.max_rows = block_size;
+            backend.current = Builder::default();
+            backend.current_start = start;
+            if let Some(data) = current {
+                let (start2, builder) = decode_batch_bytes::<Builder>(&data)?;
+                if start != start2 {
+                    bail!("unfinished block at {start2}, expected {start}");
+                }
+                backend.current = builder;
+            }
+            *live = start + backend.current.len() as u64;
+            drop_stored(backend, *next)?;
+        }
+        Replicated::Row(row) => {
+            let id = *live;
+            *live += 1;
+            let current_end = backend.current_start + backend.current.len() as u64;
+            if id < current_end {
+                // stored with a finished block that overtook the row
+                return Ok(());
+            }
+            if id > current_end {
+                bail!("live row {id} doesn't continue the rows before {current_end}");
+            }
+            backend.current.add(postcard::from_bytes(&row[1..])?);
+            let _ = backend.tx.send(row);
+        }
+    }
+    Ok(())
+}
+
+/// store a finished block, returns the rows stored now
+async fn store_block(backend: &mut CollectorBackend<Builder>, next: &mut u64, data: Bytes) -> Result<u64, Error> {
+    let (start, builder) = decode_batch_bytes::<Builder>(&data)?;
+    if start < *next {
+        bail!("block {start} overlaps the rows before {next}");
+    }
+    let end = start + builder.len() as u64;
+    let (tx, rx) = oneshot::channel();
+    backend.past_tx.send(PastCommand::AddBuffer { start, data, time: builder.max_time(), wal: vec![] }).await?;
+    backend.past_tx.send(PastCommand::Flush { tx }).await?;
+    rx.await?;
+    *next = end;
+    drop_stored(backend, end)?;
+    Ok(end)
+}
+
+/// remove the live rows before `end` from the unfinished block
+fn drop_stored(backend: &mut CollectorBackend<Builder>, end: u64) -> Result<(), Error> {
+    let Some(stored) = end.checked_sub(backend.current_start).filter(|&n| n > 0) else { return Ok(()) };
+    let mut rest = Builder::default();
+    for idx in stored as usize..backend.current.len() {
+        rest.add(backend.current.get(idx).context("corrupt row")?);
+    }
+    backend.current = rest;
+    backend.current_start = end;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_forward() {
+    use clog_core::RequestEntry;
+
+    let entry = |time: u64| RequestEntry { uri: format!("/page/{time}"), time, ..RequestEntry::sample() };
+    let dir = crate::ScratchDir::new("forward");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let secret = [7; 32];
+    let replicas = Arc::new(Replicas::new(dir.to_path_buf(), secret, LogOptions::default));
+    tokio::spawn(replicas.clone().serve(listener));
+
+    let (edge, edge_tx) = crate::init_log(LogOptions::default()).await.unwrap();
+    let push = async |times: std::ops::Range<u64>| {
+        for t in times {
+            edge_tx.send(entry(t)).await.unwrap();
+        }
+        sleep(Duration::from_millis(50)).await;
+    };
+    push(0..3).await;
+    edge.flush().await.unwrap();
+    let start_forward = || {
+        let (edge, addr) = (edge.clone(), addr.clone());
+        let options = ForwardOptions { live: true, poll: Duration::from_millis(10), ..ForwardOptions::new("edge-1", secret) };
+        tokio::spawn(async move { forward(edge, &addr, options).await })
+    };
+    let forwarding = start_forward();
+
+    let mut central = replicas.collector("edge-1").await;
+    for _ in 0..500 {
+        if central.is_ok() {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+        central = replicas.collector("edge-1").await;
+    }
+    let central = central.unwrap();
+    // the row after the rows of finished blocks and live rows
+    let live_end = async || {
+        let mut handle = central.attach_with_backlog(0).await.unwrap();
+        let sync = handle.batch_rx.recv().await.unwrap();
+        postcard::from_bytes::<SyncHeader>(&sync[1..]).unwrap().start
+    };
+    let wait_for = async |end: u64| {
+        for _ in 0..500 {
+            if live_end().await == end {
+                return;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("central collector did not reach row {end}");
+    };
+    wait_for(3).await;
+    let block = central.block_at(0).await.unwrap().unwrap();
+    assert_eq!(decode_batch_bytes::<Builder>(&block).unwrap().1.len(), 3);
+
+    // live rows, then the block that contains them
+    push(3..5).await;
+    wait_for(5).await;
+    assert!(batch_start(&central.block_at(3).await.unwrap().unwrap()) == Some(0));
+    edge.flush().await.unwrap();
+    for _ in 0..500 {
+        if central.block_at(3).await.unwrap().as_deref().and_then(batch_start) == Some(3) {
+            break;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(live_end().await, 5);
+
+    // resume after a disconnect
+    forwarding.abort();
+    push(5..6).await;
+    edge.flush().await.unwrap();
+    let forwarding = start_forward();
+    wait_for(6).await;
+    forwarding.abort();
+
+    let (tx, mut rx) = channel(8);
+    central.get_range(0..6, tx).await.unwrap();
+    let mut starts = vec![];
+    while let Some(data) = rx.recv().await {
+        let (start, builder) = decode_batch_bytes::<Builder>(&data).unwrap();
+        starts.push((start, builder.len()));
+    }
+    starts.sort();
+    assert_eq!(starts, [(0, 3), (3, 2), (5, 1)]);
+    assert_eq!(replicas.sources().await.unwrap(), ["edge-1"]);
+
+    // edges without the secret are turned away before anything is created for them
+    let (edge, addr) = (edge.clone(), addr.clone());
+    let intruder = tokio::spawn(async move { forward(edge, &addr, ForwardOptions::new("edge-2", [8; 32])).await });
+    sleep(Duration::from_millis(100)).await;
+    intruder.abort();
+    assert_eq!(replicas.sources().await.unwrap(), ["edge-1"]);
+    assert!(replicas.collector("edge-2").await.is_err());
+}
+
+#[tokio::test]
+async fn test_late_rows() {
+    use bytes::BytesMut;
+    use clog_core::{RequestEntry, shema::BatchEntry};
+
+    let entry = |time: u64| RequestEntry { uri: format!("/page/{time}"), time, ..RequestEntry::sample() };
+    let row = |time: u64| {
+        let mut buf = BytesMut::new();
+        PacketType::Row.write_to(&mut buf);
+        Replicated::Row(postcard::to_extend(&BatchEntry::from(&entry(time)), buf).unwrap().freeze())
+    };
+    let (central, tx) = init_replica(LogOptions::default()).await.unwrap();
+    tx.send(Replicated::Sync { block_size: 10, start: 0, current: None }).await.unwrap();
+    tx.send(row(0)).await.unwrap();
+    tx.send(row(1)).await.unwrap();
+    // the block was finished and sent before the last of its rows
+    let mut builder = Builder::default();
+    for t in 0..3 {
+        builder.add((&entry(t)).into());
+    }
+    let (stored_tx, stored_rx) = oneshot::channel();
+    tx.send(Replicated::Block(encode_batch(0, &builder, &Options::archive()), stored_tx)).await.unwrap();
+    assert_eq!(stored_rx.await.unwrap().unwrap(), 3);
+    tx.send(row(2)).await.unwrap();
+    tx.send(row(3)).await.unwrap();
+    sleep(Duration::from_millis(50)).await;
+
+    let mut handle = central.attach_with_backlog(0).await.unwrap();
+    let sync = handle.batch_rx.recv().await.unwrap();
+    assert_eq!(postcard::from_bytes::<SyncHeader>(&sync[1..]).unwrap().start, 4);
+    // the finished block, then the unfinished one
+    handle.batch_rx.recv().await.unwrap();
+    let current = handle.batch_rx.recv().await.unwrap();
+    let (start, builder) = decode_batch_bytes::<Builder>(&current).unwrap();
+    assert_eq!((start, builder.len()), (3, 1));
+    assert_eq!(builder.get(0).unwrap().uri, "/page/3");
+}
@@ -1,10 +1,12 @@
-use anyhow::{Error, bail};
+use anyhow::{Context, Error, bail};
 use bytes::{Bytes, BytesMut};
 use std::{
     collections::BTreeMap ,
     mem::replace,
     ops::Range,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
 };
 use tokio::{
     select,
@@ -14,12 +16,32 @@ use tokio::{
         oneshot,
     },
     task::spawn_blocking,
+    time::interval,
 };
 
 use clog_core::{
-    BatchHeader, PacketType, RequestEntry, SyncHeader,
-    shema::{BatchEntry, Builder, Shema},
+    body::BodyPolicy,
+    privacy::PrivacyPolicy,
+    BatchHeader, BlockStats, Options, PacketType, RequestEntry, SyncHeader,
+    shema::{Builder, Shema},
 };
+use clog_ws_api::ServerMessage;
+
+mod crypt;
+mod forward;
+mod lru;
+mod partition;
+mod reader;
+mod tail;
+mod wal;
+
+pub use crypt::{Keyring, open_block, seal_block};
+pub use forward::{ForwardOptions, ForwardSecret, Replicas, forward, load_secret};
+pub use lru::Lru;
+pub use partition::{OTHER_PARTITION, Partitions};
+pub use reader::BlockReader;
+pub use tail::{TailOptions, tail};
+use wal::Wal;
 
 enum ClientMsg {
     AttachWithBacklog {
@@ -35,6 +57,16 @@ enum ClientMsg {
     Flush {
         tx: oneshot::Sender<Result<(), ()>>,
     },
+    CacheStats {
+        tx: oneshot::Sender<CacheStats>,
+    },
+    BlockStats {
+        tx: oneshot::Sender<Option<BlockStats>>,
+    },
+    BlockAt {
+        row: u64,
+        tx: oneshot::Sender<Option<Bytes>>,
+    },
 }
 
 #[derive(Clone)]
@@ -77,6 +109,18 @@ impl LogCollector {
             .map_err(|_| anyhow::anyhow!("flush not successful"))?;
         Ok(())
     }
+    /// usage of the block cache
+    pub async fn cache_stats(&self) -> Result<CacheStats, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(ClientMsg::CacheStats { tx }).await?;
+        Ok(rx.await?)
+    }
+    /// how the columns of the last completed block were encoded, including the chosen pco specs
+    pub async fn block_stats(&self) -> Result<Option<BlockStats>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(ClientMsg::BlockStats { tx }).await?;
+        Ok(rx.await?)
+    }
     pub async fn get_range(&self, range: Range<u64>, tx: Sender<Bytes>) -> Result<(), Error> {
         self.tx
             .send(ClientMsg::GetRange {
@@ -87,6 +131,13 @@ impl LogCollector {
             .await?;
         Ok(())
     }
+    /// the finished block containing `row`, the first block after it if those rows were deleted,
+    /// or the last block if `row` is not in a finished block yet
+    pub async fn block_at(&self, row: u64) -> Result<Option<Bytes>, Error> {
+        let (tx, rx) = oneshot::channel();
+        self.tx.send(ClientMsg::BlockAt { row, tx }).await?;
+        Ok(rx.await?)
+    }
 }
 impl ClientHandle {
     pub async fn get_range(&self, start: u64, end: u64) -> Result<(), Error> {
@@ -101,29 +152,197 @@ impl ClientHandle {
     }
 }
 
+#[derive(Default)]
 pub struct LogOptions {
     pub data_dir: Option<PathBuf>,
     pub read_old: bool,
+    /// applied to each `RequestEntry` before it is stored
+    pub body: BodyPolicy,
+    /// redaction applied to each `RequestEntry` before the body policy
+    pub privacy: PrivacyPolicy,
+    /// encrypt block files with these keys. encrypted blocks can't be read without them.
+    pub keys: Option<Arc<Keyring>>,
+    pub retention: Retention,
+    pub cache: CacheOptions,
+    /// merge small blocks in the background. off by default.
+    pub compaction: Option<Compaction>,
+    /// `RequestEntry::source` of entries that don't name one
+    pub source: Option<String>,
+    /// when the current block is finished
+    pub blocks: BlockOptions,
+}
+
+/// When the collector finishes the block it is filling and how it is compressed.
+/// The block is finished at the first limit reached.
+#[derive(Clone, Debug)]
+pub struct BlockOptions {
+    pub max_rows: usize,
+    /// encoded size of the rows added since the block was started or resumed
+    pub max_bytes: Option<usize>,
+    /// time since the first row was added
+    pub max_age: Option<Duration>,
+    /// don't let blocks span hour or day boundaries, by the time rows arrive
+    pub align: Option<Align>,
+    pub brotli_level: u8,
+}
+impl Default for BlockOptions {
+    fn default() -> Self {
+        BlockOptions { max_rows: 10_000, max_bytes: None, max_age: None, align: None, brotli_level: 11 }
+    }
+}
+impl BlockOptions {
+    /// a block started at `since` has to be finished at `now`
+    fn expired(&self, since: SystemTime, now: SystemTime) -> bool {
+        let age = now.duration_since(since).unwrap_or_default();
+        self.max_age.is_some_and(|max| age >= max)
+            || self.align.is_some_and(|align| align.period(since) != align.period(now))
+    }
+    /// how often the age of the block is checked, `None` if blocks don't expire
+    fn check_interval(&self) -> Option<Duration> {
+        let check = Duration::from_secs(1);
+        match (self.max_age, self.align) {
+            (None, None) => None,
+            (Some(max), _) => Some(max.clamp(Duration::from_millis(1), check)),
+            (None, Some(_)) => Some(check),
+        }
+    }
+}
+
+/// Boundaries, in UTC, blocks don't span.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Hour,
+    Day,
+}
+impl Align {
+    /// number of the hour or day `t` is in
+    fn period(self, t: SystemTime) -> u64 {
+        let secs = t.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+        match self {
+            Align::Hour => secs / 3600,
+            Align::Day => secs / 86400,
+        }
+    }
+}
+
+/// Merges runs of adjacent small blocks into one block, re-encoded with `Options::archive()`
+/// at `BlockOptions::brotli_level` and the current shema version. Runs with the retention checks.
+#[derive(Clone, Debug)]
+pub struct Compaction {
+    /// blocks smaller than this are merged
+    pub min_bytes: u64,
+    /// merged blocks grow up to about this size
+    pub target_bytes: u64,
+}
+impl Default for Compaction {
+    fn default() -> Self {
+        Compaction { min_bytes: 256 << 10, target_bytes: 4 << 20 }
+    }
+}
+
+/// How many blocks `PastManager` keeps in memory.
+/// Without a `data_dir` blocks can't be read again, so all of them are kept.
+#[derive(Clone, Debug)]
+pub struct CacheOptions {
+    /// bytes of older blocks kept in memory, least recently used ones are dropped first
+    pub budget: usize,
+    /// the most recent blocks are always kept and don't count against the budget
+    pub pinned_blocks: usize,
+}
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions { budget: 256 << 20, pinned_blocks: 4 }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CacheStats {
+    /// bytes of cached blocks, not counting the pinned ones
+    pub used: usize,
+    pub budget: usize,
+    pub cached_blocks: usize,
+    pub pinned_bytes: usize,
+    /// blocks served from memory
+    pub hits: u64,
+    /// blocks read from disk
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// Limits on the blocks the collector keeps, in memory and in `data_dir`.
+/// The oldest blocks are deleted in the background until all limits hold.
+#[derive(Clone, Debug)]
+pub struct Retention {
+    /// delete blocks whose newest row is older than this, by the field marked `#[clog(time)]`,
+    /// which is kept in `block-{n}.time` next to the block file.
+    /// without one the time the block was completed is used, or when the file was written for blocks found on disk.
+    pub max_age: Option<Duration>,
+    /// total size of all block files
+    pub max_bytes: Option<u64>,
+    pub max_blocks: Option<usize>,
+    /// how often the limits are checked, and blocks compacted
+    pub interval: Duration,
+}
+impl Default for Retention {
+    fn default() -> Self {
+        Retention { max_age: None, max_bytes: None, max_blocks: None, interval: Duration::from_secs(60) }
+    }
 }
 
 pub async fn init_log(options: LogOptions) -> Result<(LogCollector, Sender<RequestEntry>), Error> {
+    let body = options.body.clone();
+    let privacy = options.privacy.clone();
+    let source = options.source.clone();
+    start_log::<Builder, RequestEntry>(options, move |e, row| {
+        privacy.apply(e);
+        body.apply(e);
+        match e.source {
+            // the row id is only unique among the rows of this collector
+            Some(ref name) if e.seq.is_none() => bail!("entry of source {name:?} without seq"),
+            Some(_) => {}
+            None => {
+                e.source = source.as_deref().map(Into::into);
+                e.seq.get_or_insert(row);
+            }
+        }
+        Ok(())
+    }).await
+}
+
+/// Start a collector for the shema `S`, accepting events of type `E`.
+pub async fn init_shema_log<S, E>(options: LogOptions) -> Result<(LogCollector, Sender<E>), Error>
+where
+    S: Shema + Default + Clone + Send + Sync + 'static,
+    E: Send + 'static,
+    for<'a> S::Item<'a>: From<&'a E>,
+{
+    start_log::<S, E>(options, |_, _| Ok(())).await
+}
+
+/// `prepare` is called with each event and the row id it is stored at. events it fails for are dropped.
+async fn start_log<S, E>(options: LogOptions, mut prepare: impl FnMut(&mut E, u64) -> Result<(), Error> + Send + 'static) -> Result<(LogCollector, Sender<E>), Error>
+where
+    S: Shema + Default + Clone + Send + Sync + 'static,
+    E: Send + 'static,
+    for<'a> S::Item<'a>: From<&'a E>,
+{
     let (client_tx, mut client_rx) = channel(128);
     let (past_tx, past_rx) = channel(128);
     let (row_tx, _) = broadcast::channel(4096);
-    let (event_tx, mut event_rx) = channel::<RequestEntry>(128);
+    let (event_tx, mut event_rx) = channel::<E>(128);
 
-    let mut past = PastManager {
-        past_buffers: Default::default(),
-        past_rx,
-        dir: options.data_dir,
-    };
+    let mut past = PastManager::new::<S>(&options, past_rx);
 
     let mut backend = CollectorBackend {
         past_tx,
-        block_limit: 10_000,
-        current: Builder::default(),
+        blocks: options.blocks.clone(),
+        current: S::default(),
         current_start: 0,
+        current_bytes: 0,
+        current_since: None,
         tx: row_tx,
+        wal: None,
+        last_stats: Default::default(),
     };
 
     if options.read_old {
@@ -138,22 +357,70 @@ pub async fn init_log(options: LogOptions) -> Result<(LogCollector, Sender<Reque
             println!("resume log at {start}+{}", backend.current.len());
         }
     }
+    if let Some(ref dir) = past.dir {
+        let mut replayed = vec![];
+        if options.read_old {
+            for (first, path) in wal::segments(dir)? {
+                let mut idx = first;
+                wal::replay(&path, |row| {
+                    let end = backend.current_start + backend.current.len() as u64;
+                    if idx > end {
+                        bail!("rows {end}..{idx} are missing");
+                    }
+                    if idx == end {
+                        backend.current.add(postcard::from_bytes(row)?);
+                    }
+                    idx += 1;
+                    Ok(())
+                }).with_context(|| format!("replaying {path:?}"))?;
+                replayed.push(path);
+            }
+            if !replayed.is_empty() {
+                println!("replayed the WAL up to {}", backend.current_start + backend.current.len() as u64);
+            }
+        } else {
+            // rows of an earlier run would leave a gap in the numbering the next time the WAL is replayed
+            for (_, path) in wal::segments(dir)? {
+                std::fs::remove_file(&path).with_context(|| format!("removing {path:?}"))?;
+            }
+        }
+        let mut wal = Wal::open(dir, backend.current_start + backend.current.len() as u64)?;
+        wal.adopt(replayed);
+        backend.wal = Some(wal);
+    }
+    if backend.current.len() > 0 {
+        backend.current_since = Some(SystemTime::now());
+    }
 
+    let mut check = backend.blocks.check_interval().map(interval);
+    let mut sync = backend.wal.is_some().then(|| interval(wal::WAL_SYNC));
     tokio::spawn(async move {
         loop {
             select! {
                 r = event_rx.recv() => {
                     match r {
-                        Some(e) => backend.push((&e).into()),
+                        Some(mut e) => {
+                            match prepare(&mut e, backend.current_start + backend.current.len() as u64) {
+                                Ok(()) => backend.push((&e).into()),
+                                Err(e) => eprintln!("dropping an entry: {e}"),
+                            }
+                        }
                         None => break
                     }
                 }
                 Some(msg) = client_rx.recv() => {
                     backend.handle_msg(msg).await;
                 }
+                _ = async { check.as_mut().unwrap().tick().await }, if check.is_some() => {
+                    backend.check_age();
+                }
+                _ = async { sync.as_mut().unwrap().tick().await }, if sync.is_some() => {
+                    backend.sync_wal();
+                }
                 else => return
             }
         }
+        backend.sync_wal();
         while let Some(msg) = client_rx.recv().await {
             backend.handle_msg(msg).await;
         }
@@ -166,24 +433,57 @@ pub async fn init_log(options: LogOptions) -> Result<(LogCollector, Sender<Reque
     Ok((LogCollector { tx: client_tx }, event_tx))
 }
 
-struct CollectorBackend {
+struct CollectorBackend<S> {
     past_tx: Sender<PastCommand>,
-    current: Builder,
+    current: S,
     current_start: u64,
+    /// encoded size of the rows added to `current`
+    current_bytes: usize,
+    /// when the first row of `current` was added
+    current_since: Option<SystemTime>,
     tx: broadcast::Sender<Bytes>,
-    block_limit: usize,
+    blocks: BlockOptions,
+    wal: Option<Wal>,
+    /// stats of the last block encoded by `send_current`
+    last_stats: Arc<Mutex<Option<BlockStats>>>,
 }
-impl CollectorBackend {
-    fn push<'a>(&mut self, entry: BatchEntry<'a>) {
-        if self.tx.receiver_count() > 0 {
+impl<S: Shema + Default + Clone + Send + Sync + 'static> CollectorBackend<S> {
+    fn push<'a>(&mut self, entry: S::Item<'a>) {
+        let now = SystemTime::now();
+        if self.current_since.is_some_and(|since| self.blocks.expired(since, now)) {
+            self.send_current(None);
+        }
+        if self.tx.receiver_count() > 0 || self.wal.is_some() || self.blocks.max_bytes.is_some() {
             let mut buf = BytesMut::with_capacity(100);
             PacketType::Row.write_to(&mut buf);
             let buf = postcard::to_extend(&entry, buf).unwrap();
+            if let Some(ref mut wal) = self.wal
+                && let Err(e) = wal.append(&buf[1..])
+            {
+                eprintln!("can't write to the WAL: {e}");
+            }
+            self.current_bytes += buf.len();
             let _ = self.tx.send(buf.into());
         }
 
         self.current.add(entry);
-        if self.current.len() >= self.block_limit {
+        self.current_since.get_or_insert(now);
+        if self.current.len() >= self.blocks.max_rows
+            || self.blocks.max_bytes.is_some_and(|max| self.current_bytes >= max)
+        {
+            self.send_current(None);
+        }
+    }
+    fn sync_wal(&mut self) {
+        if let Some(ref mut wal) = self.wal
+            && let Err(e) = wal.sync()
+        {
+            eprintln!("can't write to the WAL: {e}");
+        }
+    }
+    /// finish the current block if it is too old
+    fn check_age(&mut self) {
+        if self.current_since.is_some_and(|since| self.blocks.expired(since, SystemTime::now())) {
             self.send_current(None);
         }
     }
@@ -191,16 +491,30 @@ impl CollectorBackend {
         if self.current.len() == 0 {
             return;
         }
-        let builder = replace(&mut self.current, Builder::default());
+        self.current_bytes = 0;
+        self.current_since = None;
+        let options = Options { brotli_level: self.blocks.brotli_level, ..Options::archive() };
+        let builder = replace(&mut self.current, S::default());
         let builder_start = self.current_start;
         self.current_start += builder.len() as u64;
         let tx = self.past_tx.clone();
+        let last_stats = self.last_stats.clone();
+        let wal = match self.wal {
+            Some(ref mut wal) => wal.rotate(self.current_start).unwrap_or_else(|e| {
+                eprintln!("can't start a new WAL segment: {e}");
+                vec![]
+            }),
+            None => vec![],
+        };
 
         spawn_blocking(move || {
-            let data = encode_batch(builder_start, &builder, 11);
+            let (data, stats) = encode_batch_with_stats(builder_start, &builder, &options);
+            *last_stats.lock().unwrap() = Some(stats);
             let _ = tx.blocking_send(PastCommand::AddBuffer {
                 start: builder_start,
                 data,
+                time: builder.max_time(),
+                wal,
             });
             if let Some(flush_tx) = flush_tx {
                 let _ = tx.blocking_send(PastCommand::Flush { tx: flush_tx });
@@ -209,7 +523,7 @@ impl CollectorBackend {
     }
     async fn send_sync(&self, tx: &Sender<Bytes>, first_backlog: u64) {
         let info = SyncHeader {
-            block_size: self.block_limit,
+            block_size: self.blocks.max_rows,
             first_backlog,
             first_block: 0,
             start: self.current_start + self.current.len() as u64,
@@ -224,7 +538,7 @@ impl CollectorBackend {
         if self.current.len() > 0 {
             let current = self.current.clone();
             spawn_blocking(move || {
-                let data = encode_batch(start, &current, 5);
+                let data = encode_batch(start, &current, &Options::live());
                 let _ = tx.blocking_send(data.into());
             });
         }
@@ -277,6 +591,15 @@ impl CollectorBackend {
                 let r = self.flush().await.map_err(|_| ());
                 let _ = tx.send(r);
             }
+            ClientMsg::CacheStats { tx } => {
+                let _ = self.past_tx.send(PastCommand::CacheStats { tx }).await;
+            }
+            ClientMsg::BlockStats { tx } => {
+                let _ = tx.send(self.last_stats.lock().unwrap().clone());
+            }
+            ClientMsg::BlockAt { row, tx } => {
+                let _ = self.past_tx.send(PastCommand::BlockAt { row, tx }).await;
+            }
         }
     }
     async fn flush(&mut self) -> Result<(), Error> {
@@ -288,21 +611,20 @@ impl CollectorBackend {
     }
 }
 
-pub fn encode_batch(start: u64, builder: &Builder, brotli_level: u8) -> Bytes {
-    let mut buffer = BytesMut::with_capacity(builder.len() * 10);
+pub fn encode_batch<S: Shema>(start: u64, builder: &S, opt: &Options) -> Bytes {
+    builder.write_to(batch_header(start, builder.len()), opt).into()
+}
+/// like `encode_batch`, but also reports how each column was encoded
+pub fn encode_batch_with_stats<S: Shema>(start: u64, builder: &S, opt: &Options) -> (Bytes, BlockStats) {
+    let (data, stats) = builder.write_to_with_stats(batch_header(start, builder.len()), opt);
+    (data.into(), stats)
+}
+fn batch_header(start: u64, len: usize) -> BytesMut {
+    let mut buffer = BytesMut::with_capacity(len * 10);
     PacketType::Batch.write_to(&mut buffer);
-    let buffer = postcard::to_extend(&BatchHeader { start }, buffer).unwrap();
-
-    let data = builder.write_to(
-        buffer,
-        &clog_core::Options {
-            brotli_level,
-            dict: &[],
-        },
-    );
-    data.into()
-}
-pub fn decode_batch(data: &[u8]) -> Result<(u64, Builder), Error> {
+    postcard::to_extend(&BatchHeader { start }, buffer).unwrap()
+}
+pub fn decode_batch<S: Shema>(data: &[u8]) -> Result<(u64, S), Error> {
     let (&ptype, data) = data.split_first().ok_or(anyhow::anyhow!("no data"))?;
 
     if ptype != PacketType::Batch as u8 {
@@ -310,14 +632,81 @@ pub fn decode_batch(data: &[u8]) -> Result<(u64, Builder), Error> {
     }
 
     let (header, data) = postcard::take_from_bytes::<BatchHeader>(data)?;
-    let builder = Builder::from_slice(data)?;
+    let builder = S::from_slice(data)?;
     Ok((header.start, builder))
 }
+/// like `decode_batch`, but the builder borrows fixed size tables from `data`.
+pub fn decode_batch_bytes<S: Shema>(data: &Bytes) -> Result<(u64, S), Error> {
+    let (&ptype, rest) = data.split_first().ok_or(anyhow::anyhow!("no data"))?;
+
+    if ptype != PacketType::Batch as u8 {
+        bail!("invalid header");
+    }
+
+    let (header, rest) = postcard::take_from_bytes::<BatchHeader>(rest)?;
+    let builder = S::from_bytes(&data.slice_ref(rest))?;
+    Ok((header.start, builder))
+}
+
+/// merge consecutive blocks into one, re-encoded with the current shema version.
+/// also returns its `Shema::max_time`.
+pub fn merge_blocks<S: Shema + Default>(blocks: &[Bytes], options: &Options) -> Result<(Bytes, Option<u64>), Error> {
+    let mut merged = S::default();
+    let mut start = None;
+    for data in blocks {
+        let (block_start, builder) = decode_batch_bytes::<S>(data)?;
+        let expected = *start.get_or_insert(block_start) + merged.len() as u64;
+        if block_start != expected {
+            bail!("block {block_start} doesn't follow the previous block ending at {expected}");
+        }
+        merged.reserve(builder.len());
+        for idx in 0..builder.len() {
+            merged.add(builder.get(idx).context("corrupt row")?);
+        }
+    }
+    let start = start.context("no blocks to merge")?;
+    Ok((encode_batch(start, &merged, options), merged.max_time()))
+}
+
+/// `Shema::max_time` of an encoded block
+/// `block-{start}.time`, the highest row time of the block in unix seconds
+fn time_path(dir: &Path, start: u64) -> PathBuf {
+    dir.join(format!("block-{start}.time"))
+}
+async fn write_time(dir: &Path, start: u64, time: Option<u64>) -> std::io::Result<()> {
+    match time {
+        Some(time) => tokio::fs::write(time_path(dir, start), time.to_string()).await,
+        None => Ok(()),
+    }
+}
+async fn read_time(dir: &Path, start: u64) -> Result<u64, Error> {
+    Ok(tokio::fs::read_to_string(time_path(dir, start)).await?.trim().parse()?)
+}
+/// remove the file of a block and its time
+async fn remove_block(dir: &Path, start: u64) -> std::io::Result<()> {
+    tokio::fs::remove_file(dir.join(format!("block-{start}.clog"))).await?;
+    match tokio::fs::remove_file(time_path(dir, start)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// start offset of a `block-{start}.clog` file
+pub fn block_start(path: &Path) -> Option<u64> {
+    if path.extension()? != "clog" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.strip_prefix("block-")?.parse().ok()
+}
 
 enum PastCommand {
     AddBuffer {
         start: u64,
         data: Bytes,
+        /// `Shema::max_time` of the block
+        time: Option<u64>,
+        /// WAL segments to delete once the block is written
+        wal: Vec<PathBuf>,
     },
     Get {
         start: u64,
@@ -327,68 +716,344 @@ enum PastCommand {
     Flush {
         tx: oneshot::Sender<()>,
     },
+    CacheStats {
+        tx: oneshot::Sender<CacheStats>,
+    },
+    BlockAt {
+        row: u64,
+        tx: oneshot::Sender<Option<Bytes>>,
+    },
+}
+
+struct PastBlock {
+    data: Option<Bytes>,
+    /// size of the block file, or the data when there is no data dir
+    size: u64,
+    time: BlockTime,
+}
+/// what the age of a block is measured from
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BlockTime {
+    /// highest row time in unix seconds, see `Shema::max_time`
+    Newest(u64),
+    /// when the block was completed, for shemas without a time
+    Written(SystemTime),
+    /// the time of a block on disk was lost
+    Unknown,
+}
+impl PastBlock {
+    fn age(&self, now: SystemTime) -> Option<Duration> {
+        let newest = match self.time {
+            BlockTime::Newest(time) => SystemTime::UNIX_EPOCH + Duration::from_secs(time),
+            BlockTime::Written(written) => written,
+            BlockTime::Unknown => return None,
+        };
+        Some(now.duration_since(newest).unwrap_or_default())
+    }
 }
 
+/// `merge_blocks` of the collector's shema
+type MergeFn = fn(&[Bytes], &Options) -> Result<(Bytes, Option<u64>), Error>;
+
 struct PastManager {
     past_rx: Receiver<PastCommand>,
-    past_buffers: BTreeMap<u64, Option<Bytes>>,
+    past_buffers: BTreeMap<u64, PastBlock>,
     dir: Option<PathBuf>,
+    keys: Option<Arc<Keyring>>,
+    retention: Retention,
+    /// rows before this were deleted by the retention policy
+    deleted_before: u64,
+    /// blocks with data that are outside the pinned window
+    cache: Lru<u64, ()>,
+    pinned_blocks: usize,
+    stats: CacheStats,
+    compaction: Option<Compaction>,
+    /// `BlockOptions::brotli_level` of merged blocks
+    brotli_level: u8,
+    merge: MergeFn,
+    /// `Shema::HAS_TIME`
+    has_time: bool,
 }
 impl PastManager {
+    fn new<S: Shema + Default>(options: &LogOptions, past_rx: Receiver<PastCommand>) -> Self {
+        PastManager {
+            past_buffers: Default::default(),
+            past_rx,
+            dir: options.data_dir.clone(),
+            keys: options.keys.clone(),
+            retention: options.retention.clone(),
+            deleted_before: 0,
+            cache: Lru::new(options.cache.budget),
+            pinned_blocks: options.cache.pinned_blocks,
+            stats: CacheStats::default(),
+            compaction: options.compaction.clone(),
+            brotli_level: options.blocks.brotli_level,
+            merge: merge_blocks::<S>,
+            has_time: S::HAS_TIME,
+        }
+    }
     async fn run(&mut self) {
-        while let Some(cmd) = self.past_rx.recv().await {
-            match cmd {
-                PastCommand::AddBuffer { start, data } => {
-                    //println!("add buffer at {}", start);
-                    if let Some(ref root) = self.dir {
-                        let path = root.join(format!("block-{start}.clog"));
-                        let temp_path = path.with_extension("new");
-                        if tokio::fs::write(&temp_path, &data).await.is_ok() {
-                            let _ = tokio::fs::rename(&temp_path, path).await;
-                        }
-                    }
-                    self.past_buffers.insert(start, Some(data));
+        let mut timer = interval(self.retention.interval);
+        loop {
+            select! {
+                cmd = self.past_rx.recv() => match cmd {
+                    Some(cmd) => self.handle(cmd).await,
+                    None => break,
+                },
+                _ = timer.tick() => {
+                    self.apply_retention().await;
+                    self.compact().await;
                 }
-                PastCommand::Get { start, end, tx } => {
-                    //println!("GET {start}..{end}");
-
-                    for (&pos, data) in self.past_buffers.range_mut(..end).rev() {
-                        if data.is_none() {
-                            if let Some(ref dir) = self.dir {
-                                let path = dir.join(format!("block-{pos}.clog"));
-                                println!("reading {path:?}");
-                                if let Ok(new) = tokio::fs::read(path).await {
-                                    let bytes = Bytes::from(new);
-                                    *data = Some(bytes.clone());
-                                }
+            }
+        }
+    }
+    async fn handle(&mut self, cmd: PastCommand) {
+        match cmd {
+            PastCommand::AddBuffer { start, data, time, wal } => {
+                //println!("add buffer at {}", start);
+                let mut size = data.len() as u64;
+                if let Some(ref root) = self.dir {
+                    let path = root.join(format!("block-{start}.clog"));
+                    let temp_path = path.with_extension("new");
+                    match seal_block(self.keys.as_deref(), &data) {
+                        Ok(file_data) => if tokio::fs::write(&temp_path, &file_data).await.is_ok()
+                            && write_time(root, start, time).await.is_ok()
+                            && tokio::fs::rename(&temp_path, path).await.is_ok()
+                        {
+                            size = file_data.len() as u64;
+                            for segment in wal {
+                                let _ = tokio::fs::remove_file(segment).await;
                             }
-                        };
-                        if let Some(data) = data {
-                            let _ = tx.send(data.clone()).await;
-                            //println!("  send {} bytes", data.len());
-                        }
-                        if pos < start {
-                            break;
                         }
+                        Err(e) => eprintln!("not writing block {start}: {e}"),
                     }
                 }
-                PastCommand::Flush { tx } => {
-                    let _ = tx.send(());
+                self.deleted_before = self.deleted_before.min(start);
+                let time = time.map_or(BlockTime::Written(SystemTime::now()), BlockTime::Newest);
+                self.past_buffers.insert(start, PastBlock { data: Some(data), size, time });
+
+                // the block that just left the pinned window
+                if let Some((&old, block)) = self.past_buffers.iter().nth_back(self.pinned_blocks)
+                    && let Some(data) = block.data.clone()
+                    && !self.cache.contains(&old)
+                {
+                    self.cache_block(old, data);
+                }
+            }
+            PastCommand::Get { start, end, tx } => {
+                //println!("GET {start}..{end}");
+                if start < self.deleted_before {
+                    let msg = ServerMessage::RangeUnavailable { start, end: end.min(self.deleted_before) };
+                    let _ = tx.send(msg.encode()).await;
+                }
+
+                let mut blocks = vec![];
+                for &pos in self.past_buffers.range(..end).rev().map(|(pos, _)| pos) {
+                    blocks.push(pos);
+                    if pos < start {
+                        break;
+                    }
+                }
+                for pos in blocks {
+                    if let Some(data) = self.load(pos).await {
+                        let _ = tx.send(data).await;
+                        //println!("  send {} bytes", data.len());
+                    }
                 }
             }
+            PastCommand::Flush { tx } => {
+                let _ = tx.send(());
+            }
+            PastCommand::CacheStats { tx } => {
+                let pinned_bytes = self.past_buffers.values().rev().take(self.pinned_blocks)
+                    .filter_map(|b| b.data.as_ref()).map(|d| d.len()).sum();
+                let _ = tx.send(CacheStats {
+                    used: self.cache.used(),
+                    budget: self.cache.budget(),
+                    cached_blocks: self.cache.len(),
+                    pinned_bytes,
+                    ..self.stats.clone()
+                });
+            }
+            PastCommand::BlockAt { row, tx } => {
+                // blocks are contiguous, retention only deletes from the front
+                let start = self.past_buffers.range(..=row).next_back().or(self.past_buffers.first_key_value()).map(|(&s, _)| s);
+                let data = match start {
+                    Some(start) => self.load(start).await,
+                    None => None,
+                };
+                let _ = tx.send(data);
+            }
         }
     }
 
+    /// the data of block `start`, from memory or from disk
+    async fn load(&mut self, start: u64) -> Option<Bytes> {
+        let block = self.past_buffers.get(&start)?;
+        if let Some(ref data) = block.data {
+            let data = data.clone();
+            self.cache.get(&start);
+            self.stats.hits += 1;
+            return Some(data);
+        }
+        let path = self.dir.as_ref()?.join(format!("block-{start}.clog"));
+        println!("reading {path:?}");
+        let new = tokio::fs::read(&path).await.ok()?;
+        match open_block(self.keys.as_deref(), Bytes::from(new)) {
+            Ok(data) => {
+                self.stats.misses += 1;
+                self.cache_block(start, data.clone());
+                Some(data)
+            }
+            Err(e) => {
+                eprintln!("can't read {path:?}: {e}");
+                None
+            }
+        }
+    }
+
+    /// keep `data` for block `start`, dropping the least recently used blocks over the budget
+    fn cache_block(&mut self, start: u64, data: Bytes) {
+        let Some(block) = self.past_buffers.get_mut(&start) else { return };
+        let size = data.len();
+        block.data = Some(data);
+
+        let pinned = self.past_buffers.keys().nth_back(self.pinned_blocks).is_none_or(|&old| start > old);
+        if self.dir.is_none() || pinned {
+            return;
+        }
+        for (evicted, ()) in self.cache.insert(start, (), size) {
+            if let Some(block) = self.past_buffers.get_mut(&evicted) {
+                block.data = None;
+                self.stats.evictions += 1;
+            }
+        }
+    }
+
+    /// delete the oldest blocks until the retention limits hold
+    async fn apply_retention(&mut self) {
+        let Retention { max_age, max_bytes, max_blocks, .. } = self.retention;
+        let now = SystemTime::now();
+        let mut total: u64 = self.past_buffers.values().map(|b| b.size).sum();
+
+        while let Some((&start, block)) = self.past_buffers.first_key_value() {
+            // a block of unknown age is older than the blocks after it
+            let age = self.past_buffers.values().find_map(|b| b.age(now));
+            let too_old = max_age.is_some_and(|max| age.is_some_and(|age| age > max));
+            let too_big = max_bytes.is_some_and(|max| total > max);
+            let too_many = max_blocks.is_some_and(|max| self.past_buffers.len() > max);
+            if !(too_old || too_big || too_many) {
+                break;
+            }
+            total -= block.size;
+            self.past_buffers.remove(&start);
+            self.cache.remove(&start);
+            self.deleted_before = self.past_buffers.keys().next().copied().unwrap_or(u64::MAX);
+
+            if let Some(ref dir) = self.dir {
+                println!("removing block {start}");
+                if let Err(e) = remove_block(dir, start).await {
+                    eprintln!("can't remove block {start}: {e}");
+                }
+            }
+        }
+    }
+
+    /// merge runs of small blocks, leaving the most recent blocks alone
+    async fn compact(&mut self) {
+        let Some(Compaction { min_bytes, target_bytes }) = self.compaction else { return };
+        let keep = self.pinned_blocks.max(1);
+        let n = self.past_buffers.len().saturating_sub(keep);
+
+        let mut groups = vec![];
+        let mut run = vec![];
+        let mut run_size = 0;
+        for (&start, block) in self.past_buffers.iter().take(n) {
+            if block.size >= min_bytes || run_size + block.size > target_bytes {
+                if run.len() > 1 {
+                    groups.push(std::mem::take(&mut run));
+                }
+                run.clear();
+                run_size = 0;
+            }
+            if block.size < min_bytes {
+                run.push(start);
+                run_size += block.size;
+            }
+        }
+        if run.len() > 1 {
+            groups.push(run);
+        }
+
+        for group in groups {
+            if let Err(e) = self.merge_group(&group).await {
+                eprintln!("compacting blocks {group:?} failed: {e}");
+            }
+        }
+    }
+    /// replace the blocks in `group` by one block starting at `group[0]`.
+    ///
+    /// on disk the merged block is written as `block-{first}.merged` and a `compact-{first}-{last}` marker
+    /// is created before it is renamed over `block-{first}.clog`. the other blocks and the marker
+    /// are deleted after that. `recover_compaction` finishes or undoes this after a crash.
+    async fn merge_group(&mut self, group: &[u64]) -> Result<(), Error> {
+        let (&first, &last) = group.first().zip(group.last()).context("empty group")?;
+        let mut blocks = Vec::with_capacity(group.len());
+        for &start in group {
+            blocks.push(self.load(start).await.with_context(|| format!("block {start} is not available"))?);
+        }
+        let merge = self.merge;
+        let options = Options { brotli_level: self.brotli_level, ..Options::archive() };
+        let (data, time) = spawn_blocking(move || merge(&blocks, &options)).await??;
+
+        let mut size = data.len() as u64;
+        if let Some(ref dir) = self.dir {
+            let file_data = seal_block(self.keys.as_deref(), &data)?;
+            let merged_path = dir.join(format!("block-{first}.merged"));
+            let marker = dir.join(format!("compact-{first}-{last}"));
+            tokio::fs::write(&merged_path, &file_data).await?;
+            tokio::fs::write(&marker, b"").await?;
+            // if this is interrupted, the first block keeps the later time of the merged one, which only delays its expiry
+            write_time(dir, first, time).await?;
+            tokio::fs::rename(&merged_path, dir.join(format!("block-{first}.clog"))).await?;
+            for &start in &group[1..] {
+                remove_block(dir, start).await?;
+            }
+            tokio::fs::remove_file(&marker).await?;
+            size = file_data.len() as u64;
+        }
+        println!("compacted {} blocks into block-{first}", group.len());
+
+        let time = match time {
+            Some(time) => BlockTime::Newest(time),
+            None => group.iter()
+                .filter_map(|s| match self.past_buffers.get(s)?.time {
+                    BlockTime::Written(written) => Some(written),
+                    _ => None,
+                })
+                .max()
+                .map_or(BlockTime::Unknown, BlockTime::Written),
+        };
+        for start in group {
+            self.past_buffers.remove(start);
+            self.cache.remove(start);
+        }
+        self.past_buffers.insert(first, PastBlock { data: None, size, time });
+        self.cache_block(first, data);
+        Ok(())
+    }
+
     async fn take_last(&mut self) -> Result<Option<(u64, Bytes)>, Error> {
-        if let Some((start, data)) = self.past_buffers.pop_last() {
-            if let Some(data) = data {
+        if let Some((start, block)) = self.past_buffers.pop_last() {
+            self.cache.remove(&start);
+            if let Some(data) = block.data {
                 return Ok(Some((start, data)));
             }
             if let Some(ref dir) = self.dir {
                 let path = dir.join(format!("block-{start}.clog"));
                 println!("reading {path:?}");
                 if let Ok(new) = tokio::fs::read(path).await {
-                    let bytes = Bytes::from(new);
+                    let bytes = open_block(self.keys.as_deref(), Bytes::from(new))?;
                     return Ok(Some((start, bytes)));
                 }
             }
@@ -400,26 +1065,95 @@ impl PastManager {
         let Some(ref path) = self.dir else {
             return Ok(());
         };
+        recover_compaction(path).await?;
         let mut dir = tokio::fs::read_dir(path).await?;
 
         while let Some(entry) = dir.next_entry().await? {
-            let path = entry.path();
-            if path.extension().map(|e| e == "clog").unwrap_or(false) {
-                if let Some(n) = path
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .and_then(|s| s.strip_prefix("block-"))
-                    .and_then(|s| s.parse::<u64>().ok())
-                {
-                    //println!("  block {n}");
-                    self.past_buffers.insert(n, None);
-                }
+            if let Some(n) = block_start(&entry.path()) {
+                //println!("  block {n}");
+                let meta = entry.metadata().await?;
+                let time = match self.has_time {
+                    false => BlockTime::Written(meta.modified().unwrap_or_else(|_| SystemTime::now())),
+                    true if self.retention.max_age.is_none() => BlockTime::Unknown,
+                    true => read_time(path, n).await.map(BlockTime::Newest).unwrap_or_else(|e| {
+                        eprintln!("no time for block {n}, it expires with the blocks after it: {e:#}");
+                        BlockTime::Unknown
+                    }),
+                };
+                self.past_buffers.insert(n, PastBlock { data: None, size: meta.len(), time });
             }
         }
+        // rows before the first block were deleted before the restart
+        if let Some(&first) = self.past_buffers.keys().next() {
+            self.deleted_before = first;
+        }
         Ok(())
     }
 }
 
+/// finish compactions that were interrupted after the merged block was renamed into place,
+/// and remove the merged blocks of those that were interrupted before.
+async fn recover_compaction(dir: &Path) -> Result<(), Error> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut markers = vec![];
+    let mut merged = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else { continue };
+        if let Some((first, last)) = name.strip_prefix("compact-").and_then(|r| r.split_once('-')) {
+            if let (Ok(first), Ok(last)) = (first.parse::<u64>(), last.parse::<u64>()) {
+                markers.push((path, first, last));
+            }
+        } else if path.extension().is_some_and(|e| e == "merged") {
+            merged.push(path);
+        }
+    }
+    for (marker, first, last) in markers {
+        let merged_path = dir.join(format!("block-{first}.merged"));
+        if !merged_path.exists() {
+            let mut entries = tokio::fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if let Some(n) = block_start(&entry.path()) && first < n && n <= last {
+                    println!("removing compacted {:?}", entry.path());
+                    remove_block(dir, n).await?;
+                }
+            }
+        }
+        tokio::fs::remove_file(marker).await?;
+    }
+    for path in merged {
+        println!("removing unfinished {path:?}");
+        tokio::fs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+/// a fresh directory under the temp dir, removed when dropped
+#[cfg(test)]
+struct ScratchDir(PathBuf);
+#[cfg(test)]
+impl ScratchDir {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("clog-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        ScratchDir(dir)
+    }
+}
+#[cfg(test)]
+impl std::ops::Deref for ScratchDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+#[cfg(test)]
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn test_batch() {
     let mut list = vec![];
@@ -437,6 +1171,202 @@ fn test_batch() {
         let path = format!("../logs/block-{n}.clog");
         println!("{path}");
         let data = std::fs::read(&path).unwrap();
-        decode_batch(&data).unwrap();
+        decode_batch::<Builder>(&data).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_retention() {
+    let options = LogOptions {
+        retention: Retention { max_blocks: Some(2), ..Default::default() },
+        cache: CacheOptions { budget: 0, pinned_blocks: 0 },
+        ..Default::default()
+    };
+    let (_past_tx, past_rx) = channel(1);
+    let mut past = PastManager::new::<Builder>(&options, past_rx);
+    for start in [0, 10, 20] {
+        past.handle(PastCommand::AddBuffer { start, data: Bytes::from(vec![1; 10]), time: None, wal: vec![] }).await;
+    }
+    past.apply_retention().await;
+    assert_eq!(past.past_buffers.keys().copied().collect::<Vec<_>>(), [10, 20]);
+
+    let (tx, mut rx) = channel(8);
+    past.handle(PastCommand::Get { start: 5, end: 30, tx }).await;
+    let msg = rx.recv().await.unwrap();
+    assert_eq!(msg, ServerMessage::RangeUnavailable { start: 5, end: 10 }.encode());
+    assert_eq!(rx.recv().await.unwrap().len(), 10);
+
+    past.retention = Retention { max_bytes: Some(5), ..Default::default() };
+    past.apply_retention().await;
+    assert!(past.past_buffers.is_empty());
+    past.handle(PastCommand::AddBuffer { start: 30, data: Bytes::from(vec![1; 10]), time: None, wal: vec![] }).await;
+    assert_eq!(past.deleted_before, 30);
+}
+
+#[tokio::test]
+async fn test_retention_by_time() {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
+    let block = |start: u64, time: u64| {
+        let mut builder = Builder::default();
+        builder.add((&RequestEntry { time, ..Default::default() }).into());
+        encode_batch(start, &builder, &Options::live())
+    };
+    let dir = ScratchDir::new("retention");
+    // rows before 9 were deleted, the block files were just copied. block 9 lost its time.
+    std::fs::write(dir.join("block-9.clog"), block(9, now - 7300)).unwrap();
+    for (start, time) in [(10, now - 7200), (11, now)] {
+        std::fs::write(dir.join(format!("block-{start}.clog")), block(start, time)).unwrap();
+        std::fs::write(dir.join(format!("block-{start}.time")), time.to_string()).unwrap();
     }
+
+    let options = LogOptions {
+        data_dir: Some(dir.to_path_buf()),
+        retention: Retention { max_age: Some(Duration::from_secs(3600)), ..Default::default() },
+        ..Default::default()
+    };
+    let (_past_tx, past_rx) = channel(1);
+    let mut past = PastManager::new::<Builder>(&options, past_rx);
+    past.read().await.unwrap();
+    assert_eq!(past.deleted_before, 9);
+    assert_eq!(past.past_buffers[&9].time, BlockTime::Unknown);
+    past.apply_retention().await;
+    assert_eq!(past.past_buffers.keys().copied().collect::<Vec<_>>(), [11]);
+    assert!(!dir.join("block-9.clog").exists());
+    assert!(!dir.join("block-10.clog").exists() && !dir.join("block-10.time").exists());
+    assert!(dir.join("block-11.time").exists());
+}
+
+#[tokio::test]
+async fn test_block_cache() {
+    let dir = ScratchDir::new("cache");
+
+    let options = LogOptions {
+        data_dir: Some(dir.to_path_buf()),
+        cache: CacheOptions { budget: 15, pinned_blocks: 1 },
+        ..Default::default()
+    };
+    let (_past_tx, past_rx) = channel(1);
+    let mut past = PastManager::new::<Builder>(&options, past_rx);
+    for start in [0, 10, 20] {
+        past.handle(PastCommand::AddBuffer { start, data: Bytes::from(vec![start as u8; 10]), time: None, wal: vec![] }).await;
+    }
+    // 20 is pinned, 10 is cached and 0 was evicted
+    assert!(past.past_buffers[&0].data.is_none());
+    assert!(past.past_buffers[&10].data.is_some());
+
+    let (tx, mut rx) = channel(8);
+    past.handle(PastCommand::Get { start: 0, end: 10, tx }).await;
+    assert_eq!(rx.recv().await.unwrap(), Bytes::from(vec![0; 10]));
+    assert!(past.past_buffers[&10].data.is_none());
+    assert!(past.past_buffers[&20].data.is_some());
+
+    let (tx, rx) = oneshot::channel();
+    past.handle(PastCommand::CacheStats { tx }).await;
+    let stats = rx.await.unwrap();
+    assert_eq!((stats.used, stats.cached_blocks, stats.pinned_bytes), (10, 1, 10));
+    assert_eq!((stats.misses, stats.evictions), (1, 2));
+}
+
+#[tokio::test]
+async fn test_compaction() {
+    let entry = |time: u64| RequestEntry { uri: format!("/page/{time}"), time, ..RequestEntry::sample() };
+    let block = |start: u64, n: u64| {
+        let mut builder = Builder::default();
+        for t in start..start + n {
+            builder.add((&entry(t)).into());
+        }
+        encode_batch(start, &builder, &Options::live())
+    };
+
+    let dir = ScratchDir::new("compact");
+    let options = LogOptions {
+        data_dir: Some(dir.to_path_buf()),
+        cache: CacheOptions { budget: 1 << 20, pinned_blocks: 1 },
+        compaction: Some(Compaction::default()),
+        ..Default::default()
+    };
+    let (_past_tx, past_rx) = channel(1);
+    let mut past = PastManager::new::<Builder>(&options, past_rx);
+    for (start, n) in [(0, 3), (3, 2), (5, 4), (9, 1)] {
+        past.handle(PastCommand::AddBuffer { start, data: block(start, n), time: None, wal: vec![] }).await;
+    }
+    past.compact().await;
+    assert_eq!(past.past_buffers.keys().copied().collect::<Vec<_>>(), [0, 9]);
+    assert!(!dir.join("block-3.clog").exists());
+
+    let (start, merged) = decode_batch::<Builder>(&std::fs::read(dir.join("block-0.clog")).unwrap()).unwrap();
+    assert_eq!((start, merged.len()), (0, 9));
+    assert_eq!(merged.get(8).unwrap().uri, "/page/8");
+
+    // a compaction interrupted after the rename
+    std::fs::write(dir.join("block-3.clog"), block(3, 2)).unwrap();
+    std::fs::write(dir.join("compact-0-5"), b"").unwrap();
+    // and one interrupted before
+    std::fs::write(dir.join("block-9.merged"), b"").unwrap();
+    recover_compaction(&dir).await.unwrap();
+    let mut names: Vec<_> = std::fs::read_dir(&*dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    names.sort();
+    assert_eq!(names, ["block-0.clog", "block-0.time", "block-9.clog"]);
+    assert_eq!(std::fs::read_to_string(dir.join("block-0.time")).unwrap(), "8");
+}
+
+#[tokio::test]
+async fn test_wal_replay() {
+    let dir = ScratchDir::new("wal-replay");
+    let options = || LogOptions { data_dir: Some(dir.to_path_buf()), read_old: true, ..Default::default() };
+
+    let (_collector, tx) = init_log(options()).await.unwrap();
+    for status in [200, 404, 500] {
+        tx.send(RequestEntry { status, ..RequestEntry::sample() }).await.unwrap();
+    }
+    tokio::time::sleep(wal::WAL_SYNC + Duration::from_millis(200)).await;
+
+    // the first collector never flushed, a new one recovers the rows from the WAL
+    let (collector, _tx) = init_log(options()).await.unwrap();
+    collector.flush().await.unwrap();
+    let (start, builder) = decode_batch::<Builder>(&std::fs::read(dir.join("block-0.clog")).unwrap()).unwrap();
+    assert_eq!((start, builder.len()), (0, 3));
+    assert_eq!(builder.get(2).unwrap().status, 500);
+    assert!(!dir.join("wal-0.log").exists());
+    assert!(dir.join("wal-3.log").exists());
+
+    // without replaying, the segments of earlier runs are removed
+    std::fs::write(dir.join("wal-7.log"), [1, 0, 0, 0, 0]).unwrap();
+    let (_collector, _tx) = init_log(LogOptions { read_old: false, ..options() }).await.unwrap();
+    let names: Vec<_> = wal::segments(&dir).unwrap().into_iter().map(|(n, _)| n).collect();
+    assert_eq!(names, [0]);
+}
+
+#[tokio::test]
+async fn test_block_limits() {
+    let blocks = BlockOptions { max_rows: 3, max_age: Some(Duration::from_millis(50)), ..Default::default() };
+    let (collector, tx) = init_log(LogOptions { blocks, ..Default::default() }).await.unwrap();
+    // a source without seq gives the entry no identity, it is dropped
+    tx.send(RequestEntry { source: Some("edge-1".into()), ..RequestEntry::sample() }).await.unwrap();
+    for _ in 0..5 {
+        tx.send(RequestEntry::sample()).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // three rows by size, the other two once they got too old
+    let (batch_tx, mut batch_rx) = channel(8);
+    collector.get_range(0..5, batch_tx).await.unwrap();
+    let mut blocks = vec![];
+    while let Some(data) = batch_rx.recv().await {
+        let (start, builder) = decode_batch_bytes::<Builder>(&data).unwrap();
+        blocks.push((start, builder.len()));
+    }
+    blocks.sort();
+    assert_eq!(blocks, [(0, 3), (3, 2)]);
+
+    let stats = collector.block_stats().await.unwrap().unwrap();
+    assert!(stats.chunks.iter().any(|c| c.column == "status" && c.specs.is_some()));
+
+    let hour = |h: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(h * 3600);
+    let aligned = BlockOptions { align: Some(Align::Hour), ..Default::default() };
+    assert!(!aligned.expired(hour(5), hour(5) + Duration::from_secs(3599)));
+    assert!(aligned.expired(hour(5) + Duration::from_secs(3599), hour(6)));
+    let daily = BlockOptions { align: Some(Align::Day), ..Default::default() };
+    assert!(!daily.expired(hour(1), hour(23)));
+    assert!(daily.expired(hour(23), hour(24)));
 }
@@ -0,0 +1,103 @@
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+/// least recently used cache, limited by the total size of the values instead of their number.
+pub struct Lru<K, V> {
+    entries: HashMap<K, (V, usize, u64)>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    used: usize,
+    budget: usize,
+}
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    pub fn new(budget: usize) -> Self {
+        Lru {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            used: 0,
+            budget,
+        }
+    }
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, _, tick) = self.entries.get_mut(key)?;
+        let k = self.order.remove(tick).unwrap();
+        self.tick += 1;
+        *tick = self.tick;
+        self.order.insert(self.tick, k);
+        Some(value)
+    }
+    /// insert `value` taking `size` bytes of the budget.
+    /// evicts the least recently used entries until it fits and returns them.
+    /// a value larger than the budget is not kept, but returned as well.
+    pub fn insert(&mut self, key: K, value: V, size: usize) -> Vec<(K, V)> {
+        self.remove(&key);
+        if size > self.budget {
+            return vec![(key, value)];
+        }
+        let mut evicted = vec![];
+        while self.used + size > self.budget {
+            let Some((_, k)) = self.order.pop_first() else { break };
+            if let Some((v, s, _)) = self.entries.remove(&k) {
+                self.used -= s;
+                evicted.push((k, v));
+            }
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, size, self.tick));
+        self.used += size;
+        evicted
+    }
+    pub fn contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.contains_key(key)
+    }
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (value, size, tick) = self.entries.remove(key)?;
+        self.order.remove(&tick);
+        self.used -= size;
+        Some(value)
+    }
+    pub fn used(&self) -> usize {
+        self.used
+    }
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[test]
+fn test_lru() {
+    let mut lru = Lru::new(10);
+    lru.insert(1, "a", 4);
+    lru.insert(2, "b", 4);
+    assert_eq!(lru.get(&1), Some(&"a"));
+    assert_eq!(lru.insert(3, "c", 4), [(2, "b")]);
+    assert_eq!(lru.get(&2), None);
+    assert_eq!(lru.get(&1), Some(&"a"));
+    assert_eq!(lru.used(), 8);
+    assert_eq!(lru.insert(4, "d", 11), [(4, "d")]);
+    assert_eq!(lru.len(), 2);
+    assert!(lru.contains(&3));
+}
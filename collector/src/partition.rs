@@ -0,0 +1,212 @@
+//! Splitting one stream of entries into partitions, e.g. one per host, each with its own collector.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    path::PathBuf,
+    sync::{Arc, atomic::{AtomicU64, Ordering}},
+};
+
+use anyhow::{Context, Error, bail};
+use clog_core::RequestEntry;
+use tokio::sync::{Mutex, mpsc::{Sender, channel}};
+
+use crate::{LogCollector, LogOptions, forward::valid_source, init_log};
+
+/// partition of the entries whose key is not allowed, or that came after `max_partitions` was reached.
+/// escaped keys never have this name.
+pub const OTHER_PARTITION: &str = "_other";
+
+#[derive(Clone)]
+struct Partition {
+    collector: LogCollector,
+    tx: Sender<RequestEntry>,
+    /// entries dropped because the collector fell behind
+    dropped: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct State {
+    open: HashMap<String, Partition>,
+    /// partitions with a directory, read on first use
+    known: Option<HashSet<String>>,
+}
+
+/// Collectors of the partitions of a stream, each with its own directory in `dir`,
+/// options and row numbering.
+pub struct Partitions {
+    dir: PathBuf,
+    key: Box<dyn Fn(&RequestEntry) -> String + Send + Sync>,
+    options: Box<dyn Fn(&str) -> LogOptions + Send + Sync>,
+    max_partitions: Option<usize>,
+    allowed: Option<HashSet<String>>,
+    state: Mutex<State>,
+}
+impl Partitions {
+    /// entries go to the partition named by `key`, see `Partitions::name`. `options` gives the options
+    /// of each partition's collector, its `data_dir` is set to `dir/{name}` and `source`
+    /// to `{source}/{name}`, or the name without a source, as `seq` is counted per partition.
+    pub fn new(
+        dir: PathBuf,
+        key: impl Fn(&RequestEntry) -> String + Send + Sync + 'static,
+        options: impl Fn(&str) -> LogOptions + Send + Sync + 'static,
+    ) -> Self {
+        Partitions {
+            dir,
+            key: Box::new(key),
+            options: Box::new(options),
+            max_partitions: None,
+            allowed: None,
+            state: Default::default(),
+        }
+    }
+    /// one partition per `host`. as the host is chosen by the client, limit the partitions
+    /// with `with_max_partitions` or `with_allowed`.
+    pub fn by_host(dir: PathBuf, options: impl Fn(&str) -> LogOptions + Send + Sync + 'static) -> Self {
+        Self::new(dir, |e| e.host.to_string(), options)
+    }
+    /// entries of new keys go to `OTHER_PARTITION` once there are `max` partitions
+    pub fn with_max_partitions(self, max: usize) -> Self {
+        Partitions { max_partitions: Some(max), ..self }
+    }
+    /// only these keys get their own partition, the others go to `OTHER_PARTITION`
+    pub fn with_allowed(self, keys: impl IntoIterator<Item = String>) -> Self {
+        Partitions { allowed: Some(keys.into_iter().collect()), ..self }
+    }
+    /// the partition of `key`. letters, digits, `-` and `.` are kept, other bytes are escaped as `_xx`,
+    /// so different keys never share a partition.
+    pub fn name(key: &str) -> String {
+        let mut name = String::with_capacity(key.len());
+        for (i, b) in key.bytes().enumerate() {
+            match b.is_ascii_alphanumeric() || b == b'-' || (b == b'.' && i > 0) {
+                true => name.push(b as char),
+                false => write!(name, "_{b:02x}").unwrap(),
+            }
+        }
+        match name.is_empty() {
+            true => "_".into(),
+            false => name,
+        }
+    }
+    /// names of the partitions that received entries
+    pub async fn partitions(&self) -> Result<Vec<String>, Error> {
+        let mut names: Vec<_> = self.scan().await?.into_iter().collect();
+        names.sort();
+        Ok(names)
+    }
+    async fn scan(&self) -> Result<HashSet<String>, Error> {
+        let mut names = HashSet::new();
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if entry.file_type().await?.is_dir()
+                && let Some(name) = entry.file_name().to_str()
+                && valid_source(name)
+            {
+                names.insert(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+    /// the collector of the partition `name`, e.g. for `handle_ws`
+    pub async fn collector(&self, name: &str) -> Result<LogCollector, Error> {
+        if !valid_source(name) || !tokio::fs::try_exists(self.dir.join(name)).await? {
+            bail!("no partition {name:?}");
+        }
+        Ok(self.partition(name).await?.collector)
+    }
+    /// entries each partition dropped because its collector fell behind
+    pub async fn dropped(&self) -> Vec<(String, u64)> {
+        let state = self.state.lock().await;
+        let mut dropped: Vec<_> = state.open.iter().map(|(name, p)| (name.clone(), p.dropped.load(Ordering::Relaxed))).collect();
+        dropped.sort();
+        dropped
+    }
+    /// the partition entries with `key` go to
+    async fn route(&self, key: &str) -> Result<Partition, Error> {
+        if self.allowed.as_ref().is_some_and(|allowed| !allowed.contains(key)) {
+            return self.partition(OTHER_PARTITION).await;
+        }
+        let name = Self::name(key);
+        if let Some(max) = self.max_partitions {
+            let mut state = self.state.lock().await;
+            if state.known.is_none() {
+                state.known = Some(self.scan().await?);
+            }
+            let known = state.known.as_ref().unwrap();
+            if !known.contains(&name) && known.len() >= max {
+                drop(state);
+                return self.partition(OTHER_PARTITION).await;
+            }
+        }
+        self.partition(&name).await
+    }
+    async fn partition(&self, name: &str) -> Result<Partition, Error> {
+        let mut state = self.state.lock().await;
+        if let Some(partition) = state.open.get(name) {
+            return Ok(partition.clone());
+        }
+        let dir = self.dir.join(name);
+        tokio::fs::create_dir_all(&dir).await?;
+        let options = (self.options)(name);
+        let source = match options.source {
+            Some(ref source) => format!("{source}/{name}"),
+            None => name.into(),
+        };
+        let options = LogOptions { data_dir: Some(dir), source: Some(source), ..options };
+        let (collector, tx) = init_log(options).await.with_context(|| format!("opening partition {name}"))?;
+        let partition = Partition { collector, tx, dropped: Default::default() };
+        state.open.insert(name.into(), partition.clone());
+        if let Some(ref mut known) = state.known {
+            known.insert(name.into());
+        }
+        Ok(partition)
+    }
+    /// sender of entries to be partitioned, like the one returned by `init_log`.
+    /// entries are dropped when their partition falls behind, so that it doesn't hold up the others.
+    pub fn sender(self: Arc<Self>) -> Sender<RequestEntry> {
+        let (tx, mut rx) = channel::<RequestEntry>(128);
+        tokio::spawn(async move {
+            while let Some(e) = rx.recv().await {
+                match self.route(&(self.key)(&e)).await {
+                    Ok(partition) => if partition.tx.try_send(e).is_err() {
+                        partition.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => eprintln!("{e:#}"),
+                }
+            }
+        });
+        tx
+    }
+}
+
+#[tokio::test]
+async fn test_partitions() {
+    use clog_core::SyncHeader;
+    use std::time::Duration;
+
+    let entry = |host: &str| RequestEntry { time: 1700000000, host: host.into(), ..RequestEntry::sample() };
+    let dir = crate::ScratchDir::new("partition");
+
+    let partitions = Partitions::by_host(dir.to_path_buf(), |_| LogOptions::default()).with_max_partitions(2);
+    let partitions = Arc::new(partitions);
+    let tx = partitions.clone().sender();
+    for host in ["a.example", "b.example:8080", "a.example", "c.example", "a.example"] {
+        tx.send(entry(host)).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(partitions.partitions().await.unwrap(), ["_other", "a.example", "b.example_3a8080"]);
+    assert!(partitions.collector("c.example").await.is_err());
+    assert!(partitions.collector("..").await.is_err());
+    for (name, rows) in [("a.example", 3), ("b.example_3a8080", 1), (OTHER_PARTITION, 1)] {
+        let collector = partitions.collector(name).await.unwrap();
+        let mut handle = collector.attach_with_backlog(0).await.unwrap();
+        let sync = handle.batch_rx.recv().await.unwrap();
+        assert_eq!(postcard::from_bytes::<SyncHeader>(&sync[1..]).unwrap().start, rows);
+    }
+    assert!(partitions.dropped().await.iter().all(|&(_, n)| n == 0));
+
+    assert_ne!(Partitions::name("a:b"), Partitions::name("a_b"));
+    assert_eq!(Partitions::name(".hidden"), "_2ehidden");
+    assert_eq!(Partitions::name(""), "_");
+}
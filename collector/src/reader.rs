@@ -0,0 +1,91 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Error;
+use bytes::Bytes;
+use clog_core::shema::Shema;
+use memmap2::Mmap;
+
+use crate::{block_start, crypt::{open_block, Keyring}, decode_batch_bytes, lru::Lru};
+
+/// Reads block files from a directory by mapping them into memory.
+///
+/// Mapped blocks are kept in a LRU cache until `budget` bytes are mapped.
+/// Builders decoded from a block borrow its fixed size tables,
+/// so the mapping stays alive as long as the builder does, even when evicted from the cache.
+///
+/// The collector only replaces block files by renaming, which is safe while they are mapped.
+/// If another process truncates or rewrites a mapped block file in place,
+/// reading it raises SIGBUS and kills the process.
+///
+/// With keys set, nothing is mapped: blocks are read into memory and decrypted into a new buffer.
+pub struct BlockReader {
+    blocks: BTreeMap<u64, PathBuf>,
+    cache: Lru<u64, Bytes>,
+    keys: Option<Arc<Keyring>>,
+}
+impl BlockReader {
+    pub fn open(dir: &Path, budget: usize) -> Result<Self, Error> {
+        let mut blocks = BTreeMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if let Some(n) = block_start(&path) {
+                blocks.insert(n, path);
+            }
+        }
+        Ok(BlockReader { blocks, cache: Lru::new(budget), keys: None })
+    }
+    /// keys to decrypt encrypted blocks with.
+    /// the blocks are then read and copied instead of mapped.
+    pub fn with_keys(mut self, keys: Option<Arc<Keyring>>) -> Self {
+        self.keys = keys;
+        self
+    }
+    /// start offsets of all blocks, in order
+    pub fn starts(&self) -> impl Iterator<Item = u64> + '_ {
+        self.blocks.keys().copied()
+    }
+    /// the raw block data starting at `start`
+    pub fn get(&mut self, start: u64) -> Result<Option<Bytes>, Error> {
+        if let Some(data) = self.cache.get(&start) {
+            return Ok(Some(data.clone()));
+        }
+        let Some(path) = self.blocks.get(&start) else {
+            return Ok(None);
+        };
+        let data = match self.keys {
+            Some(ref keys) => open_block(Some(keys), std::fs::read(path)?.into())?,
+            None => {
+                let file = File::open(path)?;
+                // the collector only ever replaces block files by renaming, never modifies them in place
+                let map = unsafe { Mmap::map(&file)? };
+                open_block(None, Bytes::from_owner(map))?
+            }
+        };
+        self.cache.insert(start, data.clone(), data.len());
+        Ok(Some(data))
+    }
+    pub fn decode<S: Shema>(&mut self, start: u64) -> Result<Option<S>, Error> {
+        let Some(data) = self.get(start)? else {
+            return Ok(None);
+        };
+        let (_, builder) = decode_batch_bytes(&data)?;
+        Ok(Some(builder))
+    }
+    /// decode every block that could contain rows in `range`, one at a time.
+    pub fn scan<S: Shema>(&mut self, range: Range<u64>, mut f: impl FnMut(u64, &S) -> Result<(), Error>) -> Result<(), Error> {
+        let first = self.blocks.range(..=range.start).next_back().map(|(&n, _)| n).unwrap_or(range.start);
+        let starts: Vec<u64> = self.blocks.range(first..range.end).map(|(&n, _)| n).collect();
+        for start in starts {
+            if let Some(builder) = self.decode::<S>(start)? {
+                f(start, &builder)?;
+            }
+        }
+        Ok(())
+    }
+}
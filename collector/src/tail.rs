@@ -0,0 +1,168 @@
+use std::{
+    fs::Metadata,
+    io::SeekFrom,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, Error};
+use clog_core::{RequestEntry, import::LogFormat};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+    sync::mpsc::Sender,
+    time::sleep,
+};
+
+pub struct TailOptions {
+    /// read the lines already in the file, not only the ones appended later
+    pub from_start: bool,
+    /// how often to look for new lines and rotation
+    pub poll: Duration,
+}
+impl Default for TailOptions {
+    fn default() -> Self {
+        TailOptions { from_start: false, poll: Duration::from_millis(250) }
+    }
+}
+
+struct Followed {
+    reader: BufReader<File>,
+    id: Option<(u64, u64)>,
+    pos: u64,
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+async fn open(path: &Path, from_start: bool) -> Result<Followed, Error> {
+    let mut file = File::open(path).await.with_context(|| format!("opening {}", path.display()))?;
+    let id = file_id(&file.metadata().await?);
+    let pos = match from_start {
+        true => 0,
+        false => file.seek(SeekFrom::End(0)).await?,
+    };
+    Ok(Followed { reader: BufReader::new(file), id, pos })
+}
+
+/// sends the entry of `line` to `tx`, false once `tx` is closed
+async fn send_line(path: &Path, format: &LogFormat, line: &[u8], tx: &Sender<RequestEntry>) -> bool {
+    match format.parse(&String::from_utf8_lossy(line)) {
+        Ok(Some(e)) => return tx.send(e).await.is_ok(),
+        Ok(None) => {}
+        Err(e) => eprintln!("{}: {e:#}", path.display()),
+    }
+    true
+}
+
+/// Follow the access log at `path` like `tail -F` and send its entries to `tx`,
+/// e.g. the sender returned by `init_log`.
+///
+/// When the file is renamed and a new one created, the rest of the old file is read, then the new one from its start.
+/// A last line of the old file without a newline is taken as complete.
+/// A truncated file is read again from its start. Lines that can't be parsed are reported and skipped.
+/// Returns once `tx` is closed.
+///
+/// Renames are recognized by the inode of the file, so only on unix. Elsewhere only truncation is.
+pub async fn tail(path: &Path, format: &LogFormat, options: TailOptions, tx: Sender<RequestEntry>) -> Result<(), Error> {
+    let mut file = open(path, options.from_start).await?;
+    let mut line = Vec::new();
+    let mut rotated = false;
+    loop {
+        let n = file.reader.read_until(b'\n', &mut line).await?;
+        if n > 0 {
+            file.pos += n as u64;
+            // a partial line stays in `line` until the writer finishes it
+            if line.ends_with(b"\n") {
+                if !send_line(path, format, &line, &tx).await {
+                    return Ok(());
+                }
+                line.clear();
+            }
+            continue;
+        }
+        if tx.is_closed() {
+            return Ok(());
+        }
+        if rotated {
+            // the old file was read to its end after the rotation was seen
+            if let Ok(new) = open(path, true).await {
+                if !line.is_empty() && !send_line(path, format, &line, &tx).await {
+                    return Ok(());
+                }
+                file = new;
+                line.clear();
+                rotated = false;
+                continue;
+            }
+        }
+        sleep(options.poll).await;
+        match tokio::fs::metadata(path).await {
+            Ok(meta) if file_id(&meta) != file.id => rotated = true,
+            Ok(meta) if meta.len() < file.pos => {
+                file.pos = file.reader.seek(SeekFrom::Start(0)).await?;
+                line.clear();
+            }
+            // missing while being rotated
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_tail() {
+    use tokio::time::timeout;
+    use std::io::Write;
+
+    let dir = crate::ScratchDir::new("tail");
+    let path = dir.join("access.log");
+    let line = |uri: &str| format!("10.0.0.1 - - [30/Jan/2024:09:00:00 +0000] \"GET {uri} HTTP/1.1\" 200 5 \"-\" \"-\"\n");
+    let append = |path: &Path, text: &str| {
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        f.write_all(text.as_bytes()).unwrap();
+    };
+    append(&path, &line("/old"));
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+    let task = {
+        let path = path.clone();
+        tokio::spawn(async move {
+            let options = TailOptions { from_start: false, poll: Duration::from_millis(5) };
+            tail(&path, &LogFormat::combined(), options, tx).await
+        })
+    };
+    let mut next = async || timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap().uri;
+
+    sleep(Duration::from_millis(50)).await;
+    let a = line("/a");
+    append(&path, &a[..20]);
+    sleep(Duration::from_millis(20)).await;
+    append(&path, &a[20..]);
+    assert_eq!(next().await, "/a");
+
+    // rotation by rename, the old file ends without a newline
+    if cfg!(unix) {
+        std::fs::rename(&path, dir.join("access.log.1")).unwrap();
+        append(&dir.join("access.log.1"), line("/b").trim_end());
+        append(&path, &line("/c"));
+        assert_eq!(next().await, "/b");
+        assert_eq!(next().await, "/c");
+    }
+
+    // truncation
+    sleep(Duration::from_millis(20)).await;
+    std::fs::write(&path, "").unwrap();
+    sleep(Duration::from_millis(20)).await;
+    append(&path, &line("/d"));
+    assert_eq!(next().await, "/d");
+
+    drop(rx);
+    task.await.unwrap().unwrap();
+}
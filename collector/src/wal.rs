@@ -0,0 +1,133 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Error;
+
+/// Write-ahead log of the rows of the current block.
+///
+/// Rows are appended to `wal-{n}.log`, where `n` is the index of the first row in the file,
+/// each one as a u32 LE length followed by the postcard encoded row.
+/// A new segment is started for each block, the old ones are deleted once the block is written.
+///
+/// Rows are buffered and only written and synced by `sync`, which the collector calls every `WAL_SYNC`,
+/// so a crash loses the rows of up to that long.
+pub(crate) struct Wal {
+    dir: PathBuf,
+    file: BufWriter<File>,
+    /// rows were appended since the last `sync`
+    dirty: bool,
+    /// segments holding rows of the current block
+    segments: Vec<PathBuf>,
+}
+impl Wal {
+    /// start a new segment for rows from `start` on
+    pub fn open(dir: &Path, start: u64) -> io::Result<Self> {
+        let path = segment_path(dir, start);
+        let file = new_segment(&path)?;
+        Ok(Wal { dir: dir.into(), file: BufWriter::new(file), dirty: false, segments: vec![path] })
+    }
+    /// rows of the current block are also in these replayed segments
+    pub fn adopt(&mut self, segments: impl IntoIterator<Item = PathBuf>) {
+        for path in segments {
+            if !self.segments.contains(&path) {
+                self.segments.push(path);
+            }
+        }
+    }
+    pub fn append(&mut self, row: &[u8]) -> io::Result<()> {
+        self.file.write_all(&(row.len() as u32).to_le_bytes())?;
+        self.file.write_all(row)?;
+        self.dirty = true;
+        Ok(())
+    }
+    /// write the buffered rows and wait until they are on disk
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.file.flush()?;
+            self.file.get_ref().sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+    /// start a new segment for the block starting at `start`.
+    /// returns the segments of the previous block, to be deleted when it is written.
+    pub fn rotate(&mut self, start: u64) -> io::Result<Vec<PathBuf>> {
+        // the old segment is needed until its block is written
+        self.sync()?;
+        let path = segment_path(&self.dir, start);
+        self.file = BufWriter::new(new_segment(&path)?);
+        let mut old = std::mem::replace(&mut self.segments, vec![path.clone()]);
+        // the new segment replaces one for the same first row, which holds no rows of the old block
+        old.retain(|p| *p != path);
+        Ok(old)
+    }
+}
+
+fn segment_path(dir: &Path, start: u64) -> PathBuf {
+    dir.join(format!("wal-{start}.log"))
+}
+fn new_segment(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).write(true).truncate(true).open(path)
+}
+
+/// how often the buffered rows are written to disk
+pub(crate) const WAL_SYNC: Duration = Duration::from_secs(1);
+
+/// all segments in `dir`, ordered by their first row
+pub(crate) fn segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let mut list = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let n = path.file_name().and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix("wal-")?.strip_suffix(".log")?.parse().ok());
+        if let Some(n) = n {
+            list.push((n, path));
+        }
+    }
+    list.sort();
+    Ok(list)
+}
+
+/// call `f` with each complete row in the segment at `path`
+pub(crate) fn replay(path: &Path, mut f: impl FnMut(&[u8]) -> Result<(), Error>) -> Result<(), Error> {
+    let data = std::fs::read(path)?;
+    let mut rest = &data[..];
+    while let Some((len, tail)) = rest.split_first_chunk::<4>() {
+        let Some((row, tail)) = tail.split_at_checked(u32::from_le_bytes(*len) as usize) else {
+            eprintln!("ignoring a truncated row at the end of {path:?}");
+            break;
+        };
+        f(row)?;
+        rest = tail;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_wal() {
+    let dir = crate::ScratchDir::new("wal");
+
+    let mut wal = Wal::open(&dir, 10).unwrap();
+    wal.append(b"a").unwrap();
+    wal.append(b"bc").unwrap();
+    let old = wal.rotate(12).unwrap();
+    wal.append(b"d").unwrap();
+    wal.sync().unwrap();
+    assert_eq!(old, [dir.join("wal-10.log")]);
+
+    // a row cut off by a crash
+    let mut file = OpenOptions::new().append(true).open(dir.join("wal-12.log")).unwrap();
+    file.write_all(&[5, 0, 0, 0, b'e']).unwrap();
+
+    let list = segments(&dir).unwrap();
+    assert_eq!(list.iter().map(|(n, _)| *n).collect::<Vec<_>>(), [10, 12]);
+    let mut rows = vec![];
+    for (_, path) in &list {
+        replay(path, |row| Ok(rows.push(row.to_vec()))).unwrap();
+    }
+    assert_eq!(rows, [&b"a"[..], b"bc", b"d"]);
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use strum::EnumString;
+
+use crate::shema::BatchEntry;
+
+/// numeric column to sum up
+#[derive(Copy, Clone, Debug, PartialEq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Metric {
+    Count,
+    BytesIn,
+    BytesOut,
+    Duration,
+    Ttfb,
+}
+impl Metric {
+    pub fn get(&self, e: &BatchEntry) -> u64 {
+        match self {
+            Metric::Count => 1,
+            Metric::BytesIn => e.bytes_in,
+            Metric::BytesOut => e.bytes_out,
+            Metric::Duration => e.duration as u64,
+            Metric::Ttfb => e.ttfb as u64,
+        }
+    }
+}
+
+/// column to group entries by
+#[derive(Copy, Clone, Debug, PartialEq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum GroupBy {
+    All,
+    Ip,
+    Uri,
+    Host,
+    Method,
+    Status,
+    Location,
+}
+impl GroupBy {
+    pub fn key(&self, e: &BatchEntry) -> String {
+        match self {
+            GroupBy::All => String::new(),
+            GroupBy::Ip => match e.ip.to_ipv4_mapped() {
+                Some(ip) => IpAddr::V4(ip).to_string(),
+                None => e.ip.to_string(),
+            },
+            GroupBy::Uri => e.uri.into(),
+            GroupBy::Host => e.host.into(),
+            GroupBy::Method => e.method.into(),
+            GroupBy::Status => e.status.to_string(),
+            GroupBy::Location => e.location.unwrap_or_default().into(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub count: u64,
+    pub sum: u64,
+    pub min: u64,
+    pub max: u64,
+}
+impl Stats {
+    pub fn add(&mut self, value: u64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+    }
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+    }
+}
+
+/// `metric` summed up per `group_by` key, e.g. `bytes_out` per `ip`.
+pub struct Aggregation {
+    pub group_by: GroupBy,
+    pub metric: Metric,
+    groups: HashMap<String, Stats>,
+}
+impl Aggregation {
+    pub fn new(group_by: GroupBy, metric: Metric) -> Self {
+        Aggregation { group_by, metric, groups: HashMap::new() }
+    }
+    pub fn add(&mut self, e: &BatchEntry) {
+        let value = self.metric.get(e);
+        self.groups.entry(self.group_by.key(e)).or_default().add(value);
+    }
+    pub fn get(&self, key: &str) -> Option<&Stats> {
+        self.groups.get(key)
+    }
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+    /// the `n` groups with the largest sum
+    pub fn top(&self, n: usize) -> Vec<(&str, &Stats)> {
+        let mut groups: Vec<_> = self.groups.iter().map(|(k, s)| (k.as_str(), s)).collect();
+        groups.sort_unstable_by(|a, b| b.1.sum.cmp(&a.1.sum).then(a.0.cmp(b.0)));
+        groups.truncate(n);
+        groups
+    }
+}
+
+/// Nearest-rank percentiles. `ps` are in 0.0 ..= 100.0.
+/// `values` is sorted in place. returns an empty Vec if there are no values.
+pub fn percentiles<T: Ord + Copy>(values: &mut [T], ps: &[f64]) -> Vec<T> {
+    if values.is_empty() {
+        return vec![];
+    }
+    values.sort_unstable();
+
+    let n = values.len();
+    ps.iter().map(|&p| {
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * n as f64).ceil() as usize;
+        values[rank.clamp(1, n) - 1]
+    }).collect()
+}
+
+#[test]
+fn test_percentiles() {
+    let mut v: Vec<u32> = (1 ..= 100).rev().collect();
+    assert_eq!(percentiles(&mut v, &[0.0, 50.0, 90.0, 99.0, 100.0]), [1, 50, 90, 99, 100]);
+
+    let mut v = [15u32, 20, 35, 40, 50];
+    assert_eq!(percentiles(&mut v, &[5.0, 30.0, 40.0, 50.0, 100.0]), [15, 20, 20, 35, 50]);
+
+    assert!(percentiles::<u32>(&mut [], &[50.0]).is_empty());
+}
+
+#[cfg(feature="encode")]
+#[test]
+fn test_aggregation() {
+    use crate::{shema::Builder, shema::Shema, RequestEntry};
+
+    let entries: Vec<RequestEntry> = serde_json::from_str(r#"[
+        {"status": 200, "method": "GET", "uri": "/big.iso", "ip": "10.0.0.1", "port": 1000, "host": "a", "proto": "Https", "tls_fingerprint": null, "bytes_out": 5000000},
+        {"status": 200, "method": "GET", "uri": "/big.iso", "ip": "10.0.0.2", "port": 1001, "host": "a", "proto": "Https", "tls_fingerprint": null, "bytes_out": 5000000},
+        {"status": 200, "method": "GET", "uri": "/", "ip": "10.0.0.1", "port": 1002, "host": "a", "proto": "Https", "tls_fingerprint": null, "bytes_out": 1200}
+    ]"#).unwrap();
+    let mut builder = Builder::default();
+    for e in entries.iter() {
+        builder.add(e.into());
+    }
+
+    let mut agg = Aggregation::new("ip".parse().unwrap(), "bytes_out".parse().unwrap());
+    for e in builder.iter() {
+        agg.add(&e.unwrap());
+    }
+    assert_eq!(agg.top(1), [("10.0.0.1", &Stats { count: 2, sum: 5001200, min: 1200, max: 5000000 })]);
+    assert_eq!(agg.get("10.0.0.2").unwrap().sum, 5000000);
+}
@@ -0,0 +1,96 @@
+use serde::Deserialize;
+
+use crate::RequestEntry;
+
+/// What is kept of request bodies at ingest.
+/// The default keeps every body as it is.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BodyPolicy {
+    /// bodies are truncated to this many bytes
+    pub max_len: Option<usize>,
+    /// keep bodies only for these content types, e.g. `application/json` or `text/*`.
+    /// empty keeps all.
+    pub content_types: Vec<String>,
+    /// keep bodies only for these methods. empty keeps all.
+    pub methods: Vec<String>,
+    /// store only the length and a BLAKE3 hash of the bodies that would be kept
+    pub hash_only: bool,
+}
+
+impl BodyPolicy {
+    /// Apply the policy to `e`, setting `body_len` and `body_hash` from the body as received.
+    pub fn apply(&self, e: &mut RequestEntry) {
+        let Some(mut body) = e.body.take() else { return };
+        e.body_len = e.body_len.max(body.len().try_into().unwrap_or(u32::MAX));
+        if !self.keeps(e) {
+            return;
+        }
+        if self.hash_only {
+            e.body_hash = Some(blake3::hash(&body).into());
+            return;
+        }
+        if let Some(max_len) = self.max_len {
+            body.truncate(max_len);
+        }
+        e.body = Some(body);
+    }
+    fn keeps(&self, e: &RequestEntry) -> bool {
+        if !self.methods.is_empty() && !self.methods.iter().any(|m| m.eq_ignore_ascii_case(&e.method)) {
+            return false;
+        }
+        if self.content_types.is_empty() {
+            return true;
+        }
+        let Some((_, value)) = e.headers.0.iter().find(|(k, _)| k.eq_ignore_ascii_case("content-type")) else {
+            return false;
+        };
+        let Ok(value) = std::str::from_utf8(value) else { return false };
+        let media = value.split(';').next().unwrap_or("").trim();
+        self.content_types.iter().any(|t| match t.strip_suffix("/*") {
+            Some(major) => media.split_once('/').is_some_and(|(m, _)| m.eq_ignore_ascii_case(major)),
+            None => media.eq_ignore_ascii_case(t),
+        })
+    }
+}
+
+#[test]
+fn test_body_policy() {
+    use crate::Headers;
+
+    let mut headers = Headers::default();
+    headers.push("Content-Type", b"application/json; charset=utf-8");
+    let entry = |method: &str| RequestEntry {
+        method: method.into(),
+        body: Some(bytes::Bytes::from_static(b"{\"a\": 1}")),
+        headers: headers.clone(),
+        ..RequestEntry::sample()
+    };
+
+    let policy = BodyPolicy { max_len: Some(4), content_types: vec!["application/*".into()], ..Default::default() };
+    let mut e = entry("POST");
+    policy.apply(&mut e);
+    assert_eq!(e.body.as_deref(), Some(&b"{\"a\""[..]));
+    assert_eq!(e.body_len, 8);
+
+    let policy = BodyPolicy { content_types: vec!["text/plain".into()], ..Default::default() };
+    let mut e = entry("POST");
+    policy.apply(&mut e);
+    assert_eq!((e.body, e.body_len), (None, 8));
+
+    let policy = BodyPolicy { methods: vec!["put".into()], ..Default::default() };
+    let mut e = entry("PUT");
+    policy.apply(&mut e);
+    assert!(e.body.is_some());
+
+    let policy = BodyPolicy { hash_only: true, ..Default::default() };
+    let mut e = entry("POST");
+    policy.apply(&mut e);
+    assert_eq!(e.body, None);
+    assert_eq!(e.body_hash, Some(*blake3::hash(b"{\"a\": 1}").as_bytes()));
+
+    let policy = BodyPolicy { hash_only: true, methods: vec!["PUT".into()], ..Default::default() };
+    let mut e = entry("POST");
+    policy.apply(&mut e);
+    assert_eq!((e.body, e.body_hash, e.body_len), (None, None, 8));
+}
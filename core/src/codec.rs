@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+/// How a column is compressed. Picked per field with `#[clog(codec = "...")]`
+/// and recorded in the block in front of each field.
+///
+/// With `Pco` numbers are compressed with pco and byte data (strings, bodies) with brotli.
+/// The other codecs compress both as raw bytes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    Pco,
+    Brotli,
+    /// ruzstd only implements level 0 (stored) and its fastest level, which all other levels use
+    Zstd,
+    /// `level` is ignored
+    Lz4,
+}
+
+#[cfg(feature="encode")]
+pub(crate) fn compress_bytes(codec: Codec, level: u8, data: &[u8], writer: &mut impl std::io::Write) -> Result<usize, anyhow::Error> {
+    let written = match codec {
+        Codec::Pco | Codec::Brotli => {
+            use brotli::{enc::BrotliEncoderParams, BrotliCompress};
+            let params = BrotliEncoderParams {
+                quality: level.min(11) as i32,
+                ..Default::default()
+            };
+            BrotliCompress(&mut &data[..], writer, &params)?
+        }
+        Codec::Zstd => {
+            use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+            let level = match level {
+                0 => CompressionLevel::Uncompressed,
+                _ => CompressionLevel::Fastest,
+            };
+            let out = compress_to_vec(data, level);
+            writer.write_all(&out)?;
+            out.len()
+        }
+        Codec::Lz4 => {
+            let out = lz4_flex::compress_prepend_size(data);
+            writer.write_all(&out)?;
+            out.len()
+        }
+    };
+    Ok(written)
+}
+
+pub(crate) fn decompress_bytes(codec: Codec, mut input: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut buffer = vec![];
+    match codec {
+        Codec::Pco | Codec::Brotli => {
+            brotli_decompressor::BrotliDecompress(&mut input, &mut buffer)?;
+        }
+        Codec::Zstd => {
+            use std::io::Read;
+            let mut decoder = ruzstd::decoding::StreamingDecoder::new(input)?;
+            decoder.read_to_end(&mut buffer)?;
+        }
+        Codec::Lz4 => {
+            buffer = lz4_flex::decompress_size_prepended(input)?;
+        }
+    }
+    Ok(buffer)
+}
+
+#[cfg(feature="encode")]
+#[test]
+fn test_codecs() {
+    let data = b"GET /index.html\nGET /favicon.ico\nGET /index.html\nPOST /api/login".repeat(10);
+    for codec in [Codec::Pco, Codec::Brotli, Codec::Zstd, Codec::Lz4] {
+        let mut out = vec![];
+        let written = compress_bytes(codec, 5, &data, &mut out).unwrap();
+        assert_eq!(written, out.len());
+        assert!(written < data.len(), "{codec}");
+        assert_eq!(decompress_bytes(codec, &out).unwrap(), data);
+    }
+    let mut stored = vec![];
+    compress_bytes(Codec::Zstd, 0, &data, &mut stored).unwrap();
+    assert!(stored.len() > data.len());
+    assert_eq!(decompress_bytes(Codec::Zstd, &stored).unwrap(), data);
+    assert_eq!("lz4".parse(), Ok(Codec::Lz4));
+}
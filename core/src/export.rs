@@ -0,0 +1,323 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::net::IpAddr;
+
+use strum::{EnumString, IntoStaticStr};
+use time::OffsetDateTime;
+
+use crate::filter::{BlockFilter, Filter, FilterCtx};
+use crate::shema::{BatchEntry, Builder, Shema};
+use crate::{Headers, Protocol, RequestEntry, escape_bytes, headers_string};
+
+impl From<&BatchEntry<'_>> for RequestEntry {
+    fn from(e: &BatchEntry<'_>) -> Self {
+        let fp = u128::from_le_bytes(e.tls_fp);
+        RequestEntry {
+            status: e.status,
+            method: e.method.into(),
+            uri: e.uri.into(),
+            ip: e.ip.to_canonical(),
+            port: e.port,
+            time: e.time,
+            body: e.body.map(bytes::Bytes::copy_from_slice),
+            headers: Headers(e.headers.iter().map(|&(k, v)| (k.into(), bytes::Bytes::copy_from_slice(v))).collect()),
+            host: e.host.into(),
+            proto: Protocol::from_repr(e.proto).unwrap_or(Protocol::Unknown),
+            location: e.location.map(Into::into),
+            tls_fingerprint: (fp != 0).then_some(fp),
+            time_us: e.time_us,
+            ttfb: e.ttfb,
+            duration: e.duration,
+            bytes_in: e.bytes_in,
+            bytes_out: e.bytes_out,
+            body_len: e.body_len,
+            body_hash: (e.body_hash != [0; 32]).then_some(e.body_hash),
+            // older blocks have neither
+            source: (!e.source.is_empty()).then(|| e.source.into()),
+            seq: (!e.source.is_empty()).then_some(e.seq),
+        }
+    }
+}
+
+/// column of a CSV export
+#[derive(Copy, Clone, Debug, PartialEq, EnumString, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum Column {
+    Row,
+    Time,
+    Status,
+    Method,
+    Uri,
+    Host,
+    Ip,
+    Port,
+    Proto,
+    Location,
+    Ua,
+    Referer,
+    Ttfb,
+    Duration,
+    BytesIn,
+    BytesOut,
+    BodyLen,
+    BodyHash,
+    TlsFp,
+    Headers,
+    Body,
+    Source,
+    Seq,
+}
+impl Column {
+    pub const DEFAULT: &[Column] = &[
+        Column::Time, Column::Status, Column::Method, Column::Host, Column::Uri, Column::Ip,
+        Column::Ua, Column::Referer, Column::Duration, Column::BytesOut,
+    ];
+}
+
+pub enum Format {
+    /// one `RequestEntry` as JSON per line
+    Json,
+    /// a header line, then one line per row
+    Csv(Vec<Column>),
+    /// Apache/nginx "combined" format.
+    /// the HTTP version is not recorded, so requests are written as HTTP/1.1
+    Combined,
+}
+
+/// Rows to export. `from` and `until` are unix timestamps in seconds, `until` is exclusive.
+#[derive(Default)]
+pub struct Selection {
+    pub filter: Option<Filter>,
+    pub from: Option<u64>,
+    pub until: Option<u64>,
+}
+
+/// Writes the selected rows of blocks as text.
+pub struct Exporter<W: Write> {
+    out: W,
+    format: Format,
+    selection: Selection,
+    ctx: FilterCtx,
+    started: bool,
+    line: String,
+}
+impl<W: Write> Exporter<W> {
+    pub fn new(out: W, format: Format, selection: Selection) -> Self {
+        Exporter { out, format, selection, ctx: FilterCtx::new(), started: false, line: String::new() }
+    }
+    /// write the selected rows of the block starting at row `start`, returns the number of rows written.
+    /// fails on the first row that can't be decoded.
+    pub fn write_block(&mut self, start: u64, builder: &Builder) -> io::Result<usize> {
+        if !self.started {
+            self.started = true;
+            if let Format::Csv(ref columns) = self.format {
+                let names: Vec<&str> = columns.iter().map(|&c| c.into()).collect();
+                writeln!(self.out, "{}", names.join(","))?;
+            }
+        }
+        let filter = self.selection.filter.as_ref().map(|f| BlockFilter::new(f, builder));
+        let mut n = 0;
+        for (idx, e) in builder.iter().enumerate() {
+            let e = e.map_err(|field| io::Error::other(format!("row {}: invalid {field}", start + idx as u64)))?;
+            if self.selection.from.is_some_and(|t| e.time < t) || self.selection.until.is_some_and(|t| e.time >= t) {
+                continue;
+            }
+            if let Some(ref filter) = filter && !filter.matches(&self.ctx, idx) {
+                continue;
+            }
+            self.line.clear();
+            match self.format {
+                Format::Json => {
+                    let json = serde_json::to_string(&RequestEntry::from(&e)).map_err(io::Error::other)?;
+                    self.line.push_str(&json);
+                }
+                Format::Csv(ref columns) => csv_row(&mut self.line, start + idx as u64, &e, columns),
+                Format::Combined => combined(&mut self.line, &e),
+            }
+            self.line.push('\n');
+            self.out.write_all(self.line.as_bytes())?;
+            n += 1;
+        }
+        Ok(n)
+    }
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn ip(e: &BatchEntry) -> IpAddr {
+    e.ip.to_canonical()
+}
+/// the header, or the legacy column for blocks that stored it separately
+fn header<'a>(e: &BatchEntry<'a>, name: &str, legacy: Option<&'a str>) -> Option<Cow<'a, str>> {
+    match e.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)) {
+        Some((_, v)) => Some(String::from_utf8_lossy(v)),
+        None => legacy.map(Cow::Borrowed),
+    }
+}
+fn hex(out: &mut String, bytes: &[u8]) {
+    for b in bytes {
+        out.push_str(&format!("{b:02x}"));
+    }
+}
+fn datetime(e: &BatchEntry) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(e.time as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
+
+fn csv_row(out: &mut String, row: u64, e: &BatchEntry, columns: &[Column]) {
+    let mut field = String::new();
+    for (i, column) in columns.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        field.clear();
+        match column {
+            Column::Row => field.push_str(&row.to_string()),
+            Column::Time => {
+                let t = datetime(e);
+                field.push_str(&format!(
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+                    t.year(), t.month() as u8, t.day(), t.hour(), t.minute(), t.second(), e.time_us
+                ));
+            }
+            Column::Status => field.push_str(&e.status.to_string()),
+            Column::Method => field.push_str(e.method),
+            Column::Uri => field.push_str(e.uri),
+            Column::Host => field.push_str(e.host),
+            Column::Ip => field.push_str(&ip(e).to_string()),
+            Column::Port => field.push_str(&e.port.to_string()),
+            Column::Proto => field.push_str(match Protocol::from_repr(e.proto) {
+                Some(Protocol::Http) => "http",
+                Some(Protocol::Https) => "https",
+                _ => "",
+            }),
+            Column::Location => field.push_str(e.location.unwrap_or_default()),
+            Column::Ua => field.push_str(&header(e, "user-agent", e.ua).unwrap_or_default()),
+            Column::Referer => field.push_str(&header(e, "referer", e.referer).unwrap_or_default()),
+            Column::Ttfb => field.push_str(&e.ttfb.to_string()),
+            Column::Duration => field.push_str(&e.duration.to_string()),
+            Column::BytesIn => field.push_str(&e.bytes_in.to_string()),
+            Column::BytesOut => field.push_str(&e.bytes_out.to_string()),
+            Column::BodyLen => field.push_str(&e.body_len.to_string()),
+            Column::BodyHash => if e.body_hash != [0; 32] {
+                hex(&mut field, &e.body_hash);
+            }
+            Column::TlsFp => if e.tls_fp != [0; 16] {
+                hex(&mut field, &e.tls_fp);
+            }
+            Column::Headers => field.push_str(&headers_string(e.headers.iter().copied())),
+            Column::Body => if let Some(body) = e.body {
+                escape_bytes(&mut field, body);
+            }
+            Column::Source => field.push_str(e.source),
+            Column::Seq => if !e.source.is_empty() {
+                field.push_str(&e.seq.to_string());
+            }
+        }
+        csv_field(out, &field);
+    }
+}
+fn csv_field(out: &mut String, field: &str) {
+    if field.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// `ip - - [10/Oct/2000:13:55:36 +0000] "GET /x HTTP/1.1" 200 2326 "referer" "user agent"`
+fn combined(out: &mut String, e: &BatchEntry) {
+    let t = datetime(e);
+    out.push_str(&format!(
+        "{} - - [{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000] \"",
+        ip(e), t.day(), MONTHS[t.month() as usize - 1], t.year(), t.hour(), t.minute(), t.second()
+    ));
+    clf_escape(out, e.method.as_bytes());
+    out.push(' ');
+    clf_escape(out, e.uri.as_bytes());
+    out.push_str(" HTTP/1.1\" ");
+    out.push_str(&e.status.to_string());
+    out.push(' ');
+    match e.bytes_out {
+        0 => out.push('-'),
+        n => out.push_str(&n.to_string()),
+    }
+    for (name, legacy) in [("referer", e.referer), ("user-agent", e.ua)] {
+        out.push_str(" \"");
+        match header(e, name, legacy) {
+            Some(v) => clf_escape(out, v.as_bytes()),
+            None => out.push('-'),
+        }
+        out.push('"');
+    }
+}
+/// escapes `"`, `\` and non-printable bytes as `\xNN`, like nginx
+fn clf_escape(out: &mut String, bytes: &[u8]) {
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' | 0..0x20 | 0x7f.. => out.push_str(&format!("\\x{b:02X}")),
+            b => out.push(b as char),
+        }
+    }
+}
+
+#[test]
+fn test_export() {
+    let mut builder = Builder::default();
+    for (i, uri) in ["/", "/a?q=\"x\",y"].into_iter().enumerate() {
+        builder.add(BatchEntry {
+            status: 200 + i as u16,
+            method: "GET",
+            uri,
+            ua: None,
+            referer: None,
+            ip: "10.0.0.1".parse::<std::net::Ipv4Addr>().unwrap().to_ipv6_mapped(),
+            port: 443,
+            time: 1_700_000_000 + i as u64,
+            body: None,
+            headers: vec![("User-Agent", b"curl/8")],
+            host: "qdat.net",
+            proto: Protocol::Https as u16,
+            location: None,
+            tls_fp: [0; 16],
+            time_us: 42,
+            ttfb: 1,
+            duration: 2,
+            bytes_in: 3,
+            bytes_out: 512 * i as u64,
+            body_len: 0,
+            body_hash: [0; 32],
+            source: "edge-1",
+            seq: 7 + i as u64,
+        });
+    }
+    let export = |format, selection| {
+        let mut exporter = Exporter::new(vec![], format, selection);
+        exporter.write_block(10, &builder).unwrap();
+        String::from_utf8(exporter.finish().unwrap()).unwrap()
+    };
+
+    let json = export(Format::Json, Selection::default());
+    let entries: Vec<RequestEntry> = json.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].uri, "/a?q=\"x\",y");
+    assert_eq!(entries[1].ip, "10.0.0.1".parse::<IpAddr>().unwrap());
+    assert_eq!((entries[1].source.as_deref(), entries[1].seq), (Some("edge-1"), Some(8)));
+
+    let csv = export(Format::Csv(vec![Column::Row, Column::Time, Column::Uri, Column::Ua]), Selection::default());
+    assert_eq!(csv, "row,time,uri,ua\n\
+        10,2023-11-14T22:13:20.000042Z,/,curl/8\n\
+        11,2023-11-14T22:13:21.000042Z,\"/a?q=\"\"x\"\",y\",curl/8\n");
+
+    let selection = Selection { from: Some(1_700_000_001), ..Default::default() };
+    assert_eq!(export(Format::Combined, selection),
+        "10.0.0.1 - - [14/Nov/2023:22:13:21 +0000] \"GET /a?q=\\x22x\\x22,y HTTP/1.1\" 201 512 \"-\" \"curl/8\"\n");
+
+    let selection = Selection { filter: Some(Filter::parse("status 200").unwrap()), ..Default::default() };
+    assert_eq!(export(Format::Combined, selection).lines().count(), 1);
+}
@@ -1,4 +1,5 @@
 use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::net::{Ipv6Addr};
 use std::num::ParseIntError;
 
@@ -8,7 +9,8 @@ use regex::Regex;
 use serde::{Deserialize, Deserializer};
 use time::{Date, OffsetDateTime, PrimitiveDateTime, Time};
 use crate::Protocol;
-use crate::shema::BatchEntry;
+use crate::shema::{BatchEntry, Builder, Shema};
+use crate::types::SortedStrings;
 
 lalrpop_mod!(grammar);
 
@@ -57,6 +59,23 @@ impl<T: PartialOrd + Copy> NumberFilter<T> {
     }
 }
 impl StringFilter {
+    /// which symbols of `dict` match, indexed by symbol.
+    /// equality and prefix filters use binary search, the others test each string once.
+    pub fn matching_symbols(&self, dict: &SortedStrings) -> Vec<bool> {
+        let mut out = vec![false; dict.len()];
+        match self {
+            Self::Equals(t) => if let Some(sym) = dict.symbol(t) {
+                out[sym as usize] = true;
+            }
+            Self::Prefix(t) => for sym in dict.prefix(t) {
+                out[sym as usize] = true;
+            }
+            _ => for (sym, s) in dict.iter() {
+                out[sym as usize] = self.matches(s);
+            }
+        }
+        out
+    }
     pub fn matches(&self, s: &str) -> bool {
         match self {
             Self::Equals(t) => s == t,
@@ -197,8 +216,8 @@ impl HeaderFilter {
     pub fn new(header: &str, filter: StringFilter) -> Self {
         HeaderFilter { header: header.to_ascii_lowercase(), filter }
     }
-    pub fn matches(&self, headers: &[(&str, &str)]) -> bool {
-        headers.iter().any(|&(key, val)| key == self.header && self.filter.matches(val))
+    pub fn matches(&self, headers: &[(&str, &[u8])]) -> bool {
+        headers.iter().any(|&(key, val)| key.eq_ignore_ascii_case(&self.header) && self.filter.matches(&String::from_utf8_lossy(val)))
     }
 }
 
@@ -215,6 +234,12 @@ pub enum FieldFilter {
     Header(HeaderFilter),
     Location(StringFilter),
     Fingerprint(FingerprintFilter),
+    Duration(NumberFilter<u32>),
+    Ttfb(NumberFilter<u32>),
+    BytesIn(NumberFilter<u64>),
+    BytesOut(NumberFilter<u64>),
+    Source(StringFilter),
+    Seq(NumberFilter<u64>),
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -247,6 +272,12 @@ impl Filter {
                 FieldFilter::Header(f) => f.matches(&entry.headers),
                 FieldFilter::Location(f) => f.matches_opt(entry.location.as_deref()),
                 FieldFilter::Fingerprint(f) => f.matches(entry.tls_fp),
+                FieldFilter::Duration(n) => n.matches(entry.duration),
+                FieldFilter::Ttfb(n) => n.matches(entry.ttfb),
+                FieldFilter::BytesIn(n) => n.matches(entry.bytes_in),
+                FieldFilter::BytesOut(n) => n.matches(entry.bytes_out),
+                FieldFilter::Source(f) => f.matches(entry.source),
+                FieldFilter::Seq(n) => n.matches(entry.seq),
             }
             Filter::Combination(c) => match c {
                 Combinations::Not(f) => !f.matches(ctx, entry),
@@ -261,6 +292,64 @@ impl Filter {
     }
 }
 
+/// A filter prepared for one block.
+/// uri and host filters are resolved against the block dictionaries once,
+/// so rows are compared by symbol and only decoded when other fields are filtered.
+pub struct BlockFilter<'a> {
+    builder: &'a Builder,
+    node: Node<'a>,
+}
+enum Node<'a> {
+    Uri(Vec<bool>),
+    Host(Vec<bool>),
+    Entry(&'a Filter),
+    Not(Box<Node<'a>>),
+    And(Vec<Node<'a>>),
+    Or(Vec<Node<'a>>),
+    Xor(Vec<Node<'a>>),
+}
+impl<'a> Node<'a> {
+    fn new(filter: &'a Filter, builder: &Builder) -> Self {
+        let all = |v: &'a [Filter]| v.iter().map(|f| Node::new(f, builder)).collect();
+        match filter {
+            Filter::Field(FieldFilter::Uri(f)) => Node::Uri(f.matching_symbols(builder.uris())),
+            Filter::Field(FieldFilter::Host(f)) => Node::Host(f.matching_symbols(builder.hosts())),
+            Filter::Field(_) => Node::Entry(filter),
+            Filter::Combination(Combinations::Not(f)) => Node::Not(Box::new(Node::new(f, builder))),
+            Filter::Combination(Combinations::And(v)) => Node::And(all(v)),
+            Filter::Combination(Combinations::Or(v)) => Node::Or(all(v)),
+            Filter::Combination(Combinations::Xor(v)) => Node::Xor(all(v)),
+        }
+    }
+    fn matches(&self, ctx: &FilterCtx, builder: &'a Builder, idx: usize, entry: &OnceCell<Option<BatchEntry<'a>>>) -> bool {
+        let symbol = |set: &[bool], sym: u32| set.get(sym as usize).copied().unwrap_or(false);
+        match self {
+            Node::Uri(set) => builder.row_symbols(idx).is_some_and(|(uri, _)| symbol(set, uri)),
+            Node::Host(set) => builder.row_symbols(idx).is_some_and(|(_, host)| symbol(set, host)),
+            Node::Entry(f) => match entry.get_or_init(|| builder.get(idx)) {
+                Some(e) => f.matches(ctx, e),
+                None => false,
+            }
+            Node::Not(n) => !n.matches(ctx, builder, idx, entry),
+            Node::And(v) => v.iter().all(|n| n.matches(ctx, builder, idx, entry)),
+            Node::Or(v) => v.iter().any(|n| n.matches(ctx, builder, idx, entry)),
+            Node::Xor(v) => v.iter().fold(false, |b, n| b ^ n.matches(ctx, builder, idx, entry)),
+        }
+    }
+}
+impl<'a> BlockFilter<'a> {
+    pub fn new(filter: &'a Filter, builder: &'a Builder) -> Self {
+        BlockFilter { builder, node: Node::new(filter, builder) }
+    }
+    pub fn matches(&self, ctx: &FilterCtx, idx: usize) -> bool {
+        self.node.matches(ctx, self.builder, idx, &OnceCell::new())
+    }
+    /// indices of the matching rows
+    pub fn rows<'c>(&'c self, ctx: &'c FilterCtx) -> impl Iterator<Item = usize> + 'c {
+        (0 .. self.builder.len()).filter(move |&idx| self.matches(ctx, idx))
+    }
+}
+
 fn deser_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error> where D: Deserializer<'de> {
     let s: Cow<str> = Cow::deserialize(deserializer)?;
     Regex::new(&s).map_err(serde::de::Error::custom)
@@ -334,6 +423,7 @@ pub enum FilterParseError {
     HexString,
     HexStringLen { found: usize, expected: usize },
     IpV4,
+    Overflow,
 }
 impl std::fmt::Display for FilterParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -344,6 +434,7 @@ impl std::fmt::Display for FilterParseError {
             Self::HexString => write!(f, "invalid hex string"),
             Self::HexStringLen { found, expected } => write!(f, "invalid hex string length: {found}. expected {expected}."),
             Self::IpV4 => write!(f, "invalid IPv4"),
+            Self::Overflow => write!(f, "number too large"),
         }
     }
 }
@@ -366,8 +457,47 @@ fn test_filter_parser() {
         Filter::parse("fp 0123456789abcdef0123456789abcdef"),
         Ok(Filter::Field(FieldFilter::Fingerprint(FingerprintFilter([0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]))))
     );
+    assert_eq!(Filter::parse("duration > 250 ms"), Ok(Filter::Field(FieldFilter::Duration(NumberFilter::Range(250_001, u32::MAX)))));
+    assert_eq!(Filter::parse("ttfb 10 ms .. 2 s"), Ok(Filter::Field(FieldFilter::Ttfb(NumberFilter::Range(10_000, 2_000_000)))));
+    assert_eq!(Filter::parse("duration < 800"), Ok(Filter::Field(FieldFilter::Duration(NumberFilter::Range(0, 800)))));
+    assert_eq!(Filter::parse("bytes_out > 1000000"), Ok(Filter::Field(FieldFilter::BytesOut(NumberFilter::Range(1_000_001, u64::MAX)))));
+    assert_eq!(Filter::parse("source edge-3 & seq >= 10"), Ok(Filter::Combination(Combinations::And(vec![
+        Filter::Field(FieldFilter::Source(StringFilter::Equals("edge-3".into()))),
+        Filter::Field(FieldFilter::Seq(NumberFilter::Range(10, u64::MAX))),
+    ]))));
 
 }
+#[cfg(feature="encode")]
+#[test]
+fn test_block_filter() {
+    use crate::RequestEntry;
+
+    let entries: Vec<RequestEntry> = serde_json::from_str(r#"[
+        {"status": 200, "method": "GET", "uri": "/api/users", "ip": "10.0.0.1", "port": 1000, "host": "a.example", "proto": "Https", "tls_fingerprint": null},
+        {"status": 404, "method": "GET", "uri": "/api/login", "ip": "10.0.0.2", "port": 1001, "host": "b.example", "proto": "Https", "tls_fingerprint": null},
+        {"status": 200, "method": "POST", "uri": "/static/app.js", "ip": "10.0.0.1", "port": 1002, "host": "a.example", "proto": "Https", "tls_fingerprint": null}
+    ]"#).unwrap();
+    let mut builder = Builder::default();
+    for e in entries.iter() {
+        builder.add(e.into());
+    }
+
+    let ctx = FilterCtx::new();
+    for (s, rows) in [
+        ("uri /api/*", vec![0, 1]),
+        ("uri /api/* & status 200", vec![0]),
+        ("host a.example & (!uri /static/*)", vec![0]),
+        ("uri *api*", vec![0, 1]),
+    ] {
+        let filter = Filter::parse(s).unwrap();
+        let block = BlockFilter::new(&filter, &builder);
+        assert_eq!(block.rows(&ctx).collect::<Vec<_>>(), rows, "{s}");
+        for (i, e) in builder.iter().enumerate() {
+            assert_eq!(filter.matches(&ctx, &e.unwrap()), rows.contains(&i), "{s}");
+        }
+    }
+}
+
 #[test]
 fn test_lit_parser() {
     use grammar::{LitParser, SimpleLitParser, StrParser};
@@ -0,0 +1,525 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Error, bail};
+use serde::Deserialize;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+use crate::{Protocol, RequestEntry};
+
+/// nginx's predefined `combined` format, also written by Apache with `LogFormat "..." combined`
+pub const COMBINED: &str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent""#;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Ip,
+    /// `10/Oct/2000:13:55:36 -0700`
+    TimeLocal,
+    /// `2000-10-10T13:55:36-07:00`
+    TimeIso,
+    /// time since the epoch, in 1/n seconds
+    Epoch(u32),
+    /// `GET /index.html HTTP/1.1`
+    Request,
+    Method,
+    /// path and query
+    Uri,
+    Path,
+    Query,
+    Status,
+    /// response body only
+    BodyBytes,
+    /// whole response
+    BytesOut,
+    BytesIn,
+    /// duration in seconds
+    Seconds,
+    /// duration in microseconds
+    Micros,
+    Header(String),
+    Host,
+    Port,
+    Scheme,
+    Skip,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    Literal(String),
+    Field(Field),
+}
+
+/// How access log lines are read into `RequestEntry`s.
+///
+/// Variables that have no column are skipped, `$http_*` / `%{..}i` headers are kept lowercase.
+/// Lines without an address or time are rejected.
+#[derive(Clone, Debug)]
+pub struct LogFormat(Kind);
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Pattern(Vec<Item>),
+    Caddy,
+    Json,
+}
+
+impl LogFormat {
+    /// Caddy's JSON access log, lines from other loggers are skipped
+    pub fn caddy() -> Self {
+        LogFormat(Kind::Caddy)
+    }
+    /// one `RequestEntry` as JSON per line, as written by `export::Format::Json`
+    pub fn json() -> Self {
+        LogFormat(Kind::Json)
+    }
+    pub fn combined() -> Self {
+        Self::nginx(COMBINED).unwrap()
+    }
+    /// an nginx `log_format` string, e.g. `$remote_addr [$time_local] "$request" $status $request_time`
+    pub fn nginx(format: &str) -> Result<Self, Error> {
+        let mut items = vec![];
+        let mut literal = String::new();
+        let mut rest = format;
+        while let Some(pos) = rest.find('$') {
+            literal.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let name = match rest.strip_prefix('{') {
+                Some(braced) => {
+                    let end = braced.find('}').context("unterminated ${")?;
+                    rest = &braced[end + 1..];
+                    &braced[..end]
+                }
+                None => {
+                    let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                    let name = &rest[..end];
+                    rest = &rest[end..];
+                    name
+                }
+            };
+            if name.is_empty() {
+                literal.push('$');
+                continue;
+            }
+            push_field(&mut items, &mut literal, nginx_field(name));
+        }
+        literal.push_str(rest);
+        Self::finish(items, literal)
+    }
+    /// an Apache `LogFormat` string, e.g. `%h %l %u %t \"%r\" %>s %b %D`
+    pub fn apache(format: &str) -> Result<Self, Error> {
+        let mut items = vec![];
+        let mut literal = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                // as copied from the quoted argument of the directive
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some(c) => literal.push(c),
+                    None => literal.push('\\'),
+                },
+                '%' => {
+                    while chars.next_if(|&c| c == '<' || c == '>').is_some() {}
+                    let mut arg = None;
+                    if chars.next_if_eq(&'{').is_some() {
+                        arg = Some(chars.by_ref().take_while(|&c| c != '}').collect::<String>());
+                    }
+                    let directive = chars.next().context("format ends with %")?;
+                    if directive == '%' {
+                        literal.push('%');
+                        continue;
+                    }
+                    push_field(&mut items, &mut literal, apache_field(directive, arg.as_deref())?);
+                }
+                c => literal.push(c),
+            }
+        }
+        Self::finish(items, literal)
+    }
+    fn finish(mut items: Vec<Item>, literal: String) -> Result<Self, Error> {
+        if !literal.is_empty() {
+            items.push(Item::Literal(literal));
+        }
+        let has = |f: &dyn Fn(&Field) -> bool| items.iter().any(|i| matches!(i, Item::Field(field) if f(field)));
+        if !has(&|f| *f == Field::Ip) {
+            bail!("the format has no client address");
+        }
+        if !has(&|f| matches!(f, Field::TimeLocal | Field::TimeIso | Field::Epoch(_))) {
+            bail!("the format has no time");
+        }
+        Ok(LogFormat(Kind::Pattern(items)))
+    }
+
+    /// the entry of one line, `None` for lines that hold no request
+    pub fn parse(&self, line: &str) -> Result<Option<RequestEntry>, Error> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        match &self.0 {
+            Kind::Pattern(items) => parse_pattern(items, line).map(Some),
+            Kind::Caddy => parse_caddy(line),
+            Kind::Json => Ok(Some(serde_json::from_str(line)?)),
+        }
+    }
+}
+
+fn push_field(items: &mut Vec<Item>, literal: &mut String, field: Field) {
+    if !literal.is_empty() {
+        items.push(Item::Literal(std::mem::take(literal)));
+    }
+    items.push(Item::Field(field));
+}
+
+fn nginx_field(name: &str) -> Field {
+    if let Some(header) = name.strip_prefix("http_") {
+        return Field::Header(header.replace('_', "-"));
+    }
+    match name {
+        "remote_addr" | "realip_remote_addr" => Field::Ip,
+        "time_local" => Field::TimeLocal,
+        "time_iso8601" => Field::TimeIso,
+        "msec" => Field::Epoch(1),
+        "request" => Field::Request,
+        "request_method" => Field::Method,
+        "request_uri" => Field::Uri,
+        "uri" | "document_uri" => Field::Path,
+        "args" | "query_string" => Field::Query,
+        "status" => Field::Status,
+        "body_bytes_sent" => Field::BodyBytes,
+        "bytes_sent" => Field::BytesOut,
+        "request_length" => Field::BytesIn,
+        "request_time" => Field::Seconds,
+        "host" | "server_name" => Field::Host,
+        "remote_port" => Field::Port,
+        "scheme" => Field::Scheme,
+        _ => Field::Skip,
+    }
+}
+
+fn apache_field(directive: char, arg: Option<&str>) -> Result<Field, Error> {
+    Ok(match (directive, arg) {
+        ('h' | 'a', _) => Field::Ip,
+        ('t', None) => Field::TimeLocal,
+        ('t', Some("sec")) => Field::Epoch(1),
+        ('t', Some("msec")) => Field::Epoch(1_000),
+        ('t', Some("usec")) => Field::Epoch(1_000_000),
+        ('t', Some(arg)) => bail!("unsupported time format %{{{arg}}}t"),
+        ('r', _) => Field::Request,
+        ('m', _) => Field::Method,
+        ('U', _) => Field::Path,
+        ('q', _) => Field::Query,
+        ('s', _) => Field::Status,
+        ('b' | 'B', _) => Field::BodyBytes,
+        ('O', _) => Field::BytesOut,
+        ('I', _) => Field::BytesIn,
+        ('D', _) => Field::Micros,
+        ('T', None | Some("s")) => Field::Seconds,
+        ('i', Some(name)) => Field::Header(name.to_ascii_lowercase()),
+        ('v' | 'V', _) => Field::Host,
+        ('p', Some("remote")) => Field::Port,
+        _ => Field::Skip,
+    })
+}
+
+/// position of `pat` in `s`, skipping backslash escaped characters
+fn find_unescaped(s: &str, pat: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            i += 2;
+            continue;
+        }
+        if bytes[i..].starts_with(pat.as_bytes()) {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+/// undo the escaping of nginx (`\xNN`) and Apache (`\"`, `\\`, `\n`, `\xNN`)
+fn unescape(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let hex = bytes.get(i + 2..i + 4).and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (bytes[i + 1], hex) {
+            (b'x', Some(b)) => {
+                out.push(b);
+                i += 4;
+            }
+            (c, _) => {
+                out.push(match c {
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    c => c,
+                });
+                i += 2;
+            }
+        }
+    }
+    out
+}
+fn unescape_str(s: &str) -> String {
+    match s.contains('\\') {
+        true => String::from_utf8_lossy(&unescape(s)).into_owned(),
+        false => s.into(),
+    }
+}
+
+fn parse_pattern(items: &[Item], line: &str) -> Result<RequestEntry, Error> {
+    let mut e = RequestEntry::default();
+    let (mut path, mut query) = (None, None);
+    let (mut body_bytes, mut bytes_out) = (None, None);
+
+    let mut rest = line;
+    let mut iter = items.iter().peekable();
+    while let Some(item) = iter.next() {
+        let field = match item {
+            Item::Literal(l) => {
+                rest = rest.strip_prefix(l.as_str()).with_context(|| format!("expected {l:?} before {rest:?}"))?;
+                continue;
+            }
+            Item::Field(field) => field,
+        };
+        let end = match iter.peek() {
+            Some(Item::Literal(l)) => find_unescaped(rest, l).with_context(|| format!("expected {l:?} after {rest:?}"))?,
+            Some(Item::Field(_)) => rest.find(' ').unwrap_or(rest.len()),
+            None => rest.len(),
+        };
+        let value = &rest[..end];
+        rest = &rest[end..];
+        if value == "-" || value.is_empty() {
+            continue;
+        }
+        let number = || value.parse::<u64>().with_context(|| format!("invalid number {value:?}"));
+        let seconds = || value.parse::<f64>().with_context(|| format!("invalid number {value:?}"));
+        match field {
+            Field::Ip => e.ip = value.parse().with_context(|| format!("invalid address {value:?}"))?,
+            Field::TimeLocal => set_time(&mut e, parse_time_local(value).with_context(|| format!("invalid time {value:?}"))?),
+            Field::TimeIso => set_time(&mut e, parse_time_iso(value).with_context(|| format!("invalid time {value:?}"))?),
+            Field::Epoch(unit) => {
+                let us = (seconds()? * 1e6 / *unit as f64) as u64;
+                (e.time, e.time_us) = (us / 1_000_000, (us % 1_000_000) as u32);
+            }
+            Field::Request => {
+                let request = unescape_str(value);
+                let mut parts = request.splitn(3, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some(method), Some(uri)) => {
+                        e.method = method.into();
+                        e.uri = uri.into();
+                    }
+                    // garbage sent by scanners
+                    _ => e.uri = request,
+                }
+            }
+            Field::Method => e.method = value.into(),
+            Field::Uri => e.uri = unescape_str(value),
+            Field::Path => path = Some(unescape_str(value)),
+            Field::Query => query = Some(unescape_str(value.strip_prefix('?').unwrap_or(value))),
+            Field::Status => e.status = value.parse().with_context(|| format!("invalid status {value:?}"))?,
+            Field::BodyBytes => body_bytes = Some(number()?),
+            Field::BytesOut => bytes_out = Some(number()?),
+            Field::BytesIn => e.bytes_in = number()?,
+            Field::Seconds => e.duration = (seconds()? * 1e6) as u32,
+            Field::Micros => e.duration = number()?.try_into().unwrap_or(u32::MAX),
+            Field::Header(name) => e.headers.push(name, &unescape(value)),
+            Field::Host => e.host = value.into(),
+            Field::Port => e.port = value.parse().with_context(|| format!("invalid port {value:?}"))?,
+            Field::Scheme => e.proto = match value {
+                "https" => Protocol::Https,
+                "http" => Protocol::Http,
+                _ => Protocol::Unknown,
+            },
+            Field::Skip => {}
+        }
+    }
+
+    if e.uri.is_empty() && let Some(path) = path {
+        e.uri = match query {
+            Some(query) if !query.is_empty() => format!("{path}?{query}"),
+            _ => path,
+        };
+    }
+    // without the full size, the body is the best we have
+    e.bytes_out = bytes_out.or(body_bytes).unwrap_or(0);
+    if e.host.is_empty() && let Some((_, host)) = e.headers.0.iter().find(|(k, _)| k == "host") {
+        e.host = String::from_utf8_lossy(host).as_ref().into();
+    }
+    Ok(e)
+}
+
+fn set_time(e: &mut RequestEntry, t: OffsetDateTime) {
+    e.time = t.unix_timestamp().max(0) as u64;
+    e.time_us = t.microsecond();
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+fn parse_time_local(s: &str) -> Option<OffsetDateTime> {
+    // 10/Oct/2000:13:55:36 -0700
+    let (date, rest) = s.split_once(':')?;
+    let mut date = date.split('/');
+    let day = date.next()?.parse().ok()?;
+    let month = date.next()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as u8 + 1;
+    let year = date.next()?.parse().ok()?;
+    let (time, zone) = rest.split_once(' ')?;
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    Some(PrimitiveDateTime::new(date, parse_hms(time)?).assume_offset(parse_offset(zone)?))
+}
+
+fn parse_time_iso(s: &str) -> Option<OffsetDateTime> {
+    // 2000-10-10T13:55:36-07:00, 2000-10-10T20:55:36.123Z
+    let (date, rest) = s.split_once('T')?;
+    let mut date = date.split('-');
+    let year = date.next()?.parse().ok()?;
+    let month: u8 = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    let zone_at = rest.find(['Z', '+', '-'])?;
+    let (time, zone) = rest.split_at(zone_at);
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut t = PrimitiveDateTime::new(date, parse_hms(time)?);
+    if !fraction.is_empty() {
+        let digits = fraction.len().min(6);
+        let us: u32 = fraction[..digits].parse().ok()?;
+        t = t.replace_microsecond(us * 10u32.pow(6 - digits as u32)).ok()?;
+    }
+    let offset = match zone {
+        "Z" => UtcOffset::UTC,
+        zone => parse_offset(&zone.replace(':', ""))?,
+    };
+    Some(t.assume_offset(offset))
+}
+
+fn parse_hms(s: &str) -> Option<Time> {
+    let mut parts = s.split(':').map(|p| p.parse::<u8>().ok());
+    Time::from_hms(parts.next()??, parts.next()??, parts.next()??).ok()
+}
+
+/// `-0700`
+fn parse_offset(s: &str) -> Option<UtcOffset> {
+    let sign = match s.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let hours: i8 = s.get(1..3)?.parse().ok()?;
+    let minutes: i8 = s.get(3..5)?.parse().ok()?;
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+#[derive(Deserialize)]
+struct CaddyLine {
+    #[serde(default)]
+    logger: String,
+    ts: f64,
+    request: Option<CaddyRequest>,
+    #[serde(default)]
+    bytes_read: u64,
+    #[serde(default)]
+    duration: f64,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    status: u16,
+}
+#[derive(Deserialize)]
+struct CaddyRequest {
+    remote_ip: String,
+    #[serde(default)]
+    remote_port: String,
+    #[serde(default)]
+    client_ip: String,
+    method: String,
+    host: String,
+    uri: String,
+    #[serde(default)]
+    headers: BTreeMap<String, Vec<String>>,
+    tls: Option<serde::de::IgnoredAny>,
+}
+
+fn parse_caddy(line: &str) -> Result<Option<RequestEntry>, Error> {
+    let line: CaddyLine = serde_json::from_str(line)?;
+    let Some(request) = line.request else { return Ok(None) };
+    if !line.logger.starts_with("http.log.access") {
+        return Ok(None);
+    }
+    let ip = match request.client_ip.is_empty() {
+        true => &request.remote_ip,
+        false => &request.client_ip,
+    };
+    let mut e = RequestEntry {
+        ip: ip.parse().with_context(|| format!("invalid address {ip:?}"))?,
+        port: request.remote_port.parse().unwrap_or(0),
+        ..Default::default()
+    };
+    let us = (line.ts * 1e6) as u64;
+    (e.time, e.time_us) = (us / 1_000_000, (us % 1_000_000) as u32);
+    e.status = line.status;
+    e.method = request.method.as_str().into();
+    e.uri = request.uri;
+    e.host = request.host.as_str().into();
+    e.proto = match request.tls {
+        Some(_) => Protocol::Https,
+        None => Protocol::Http,
+    };
+    for (name, values) in &request.headers {
+        for value in values {
+            e.headers.push(&name.to_ascii_lowercase(), value.as_bytes());
+        }
+    }
+    e.duration = (line.duration * 1e6) as u32;
+    e.bytes_in = line.bytes_read;
+    e.bytes_out = line.size;
+    Ok(Some(e))
+}
+
+#[test]
+fn test_log_formats() {
+    let combined = LogFormat::combined();
+    let e = combined.parse(r#"203.0.113.9 - - [10/Oct/2000:13:55:36 -0700] "GET /a?b=1 HTTP/1.1" 200 2326 "https://qdat.net/x\x22y" "curl/8""#)
+        .unwrap().unwrap();
+    assert_eq!(e.ip, "203.0.113.9".parse::<std::net::IpAddr>().unwrap());
+    assert_eq!(e.time, 971211336);
+    assert_eq!((&*e.method, &*e.uri, e.status, e.bytes_out), ("GET", "/a?b=1", 200, 2326));
+    assert_eq!(e.headers.pairs(), [("referer", &b"https://qdat.net/x\"y"[..]), ("user-agent", b"curl/8")]);
+    assert!(combined.parse("garbage").is_err());
+    assert!(combined.parse("\n").unwrap().is_none());
+
+    let nginx = LogFormat::nginx("$remote_addr $remote_port $host [$time_iso8601] $request_method $uri?$args $status $bytes_sent $request_time").unwrap();
+    let e = nginx.parse("2001:db8::1 443 qdat.net [2024-01-30T09:00:15+01:00] POST /api?x=1 201 512 0.250").unwrap().unwrap();
+    assert!(e.ip.is_ipv6());
+    assert_eq!((e.port, &*e.host, e.time, e.duration), (443, "qdat.net", 1706601615, 250_000));
+    assert_eq!((&*e.uri, e.bytes_out), ("/api?x=1", 512));
+
+    let apache = LogFormat::apache(r#"%h %l %u %{msec}t \"%r\" %>s %b %D \"%{User-Agent}i\""#).unwrap();
+    let e = apache.parse(r#"10.1.2.3 - bob 1706601615123 "GET / HTTP/1.1" 304 - 1500 "a \"quoted\" agent""#).unwrap().unwrap();
+    assert_eq!((e.time, e.time_us, e.status, e.bytes_out, e.duration), (1706601615, 123_000, 304, 0, 1500));
+    assert_eq!(e.headers.pairs(), [("user-agent", &b"a \"quoted\" agent"[..])]);
+    assert!(LogFormat::apache("%l %u").is_err());
+
+    let caddy = r#"{"level":"info","ts":1706601615.5,"logger":"http.log.access.log0","msg":"handled request",
+        "request":{"remote_ip":"127.0.0.1","remote_port":"41342","client_ip":"198.51.100.7","proto":"HTTP/2.0","method":"GET",
+        "host":"qdat.net","uri":"/","headers":{"User-Agent":["Mozilla/5.0"]},"tls":{"resumed":false}},
+        "bytes_read":0,"user_id":"","duration":0.0012,"size":1024,"status":200,"resp_headers":{}}"#.replace('\n', "");
+    let e = LogFormat::caddy().parse(&caddy).unwrap().unwrap();
+    assert_eq!(e.ip, "198.51.100.7".parse::<std::net::IpAddr>().unwrap());
+    assert_eq!((e.time, e.time_us, e.port, e.duration, e.bytes_out), (1706601615, 500_000, 41342, 1200, 1024));
+    assert!(matches!(e.proto, Protocol::Https));
+    assert!(LogFormat::caddy().parse(r#"{"level":"info","ts":1.0,"logger":"tls","msg":"x"}"#).unwrap().is_none());
+
+    let json = serde_json::to_string(&e).unwrap();
+    assert_eq!(LogFormat::json().parse(&json).unwrap().unwrap().uri, "/");
+}
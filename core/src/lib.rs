@@ -1,21 +1,69 @@
 use std::ops::Deref;
 use std::{io, net::IpAddr, usize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use better_io::BetterBufRead;
 use bytes::{BufMut, Bytes, BytesMut};
 use http::header::{ACCEPT, REFERER};
 use istring::SmallString;
 use pco::wrapped::{FileCompressor, FileDecompressor};
+use pco::{DeltaSpec, ModeSpec};
 use anyhow::{Error};
 use serde::{Deserialize, Serialize};
 use slice::SliceTrait;
+use codec::Codec;
 use strum::FromRepr;
 
+extern crate self as clog_core;
+
 pub mod util;
 pub mod shema;
 pub mod types;
 pub mod filter;
-mod slice;
+pub mod aggregate;
+pub mod slice;
+pub mod codec;
+#[cfg(feature="encode")]
+pub mod body;
+#[cfg(feature="encode")]
+pub mod privacy;
+#[cfg(feature="encode")]
+pub mod export;
+#[cfg(feature="encode")]
+pub mod import;
+
+// paths used by the code generated in clog_derive
+#[doc(hidden)]
+pub mod __private {
+    pub use std::alloc::Layout;
+    pub use anyhow::Error;
+    pub use bytes::BytesMut;
+    pub use pco::wrapped::{FileCompressor, FileDecompressor};
+    pub use serde;
+    pub use clog_derive::SliceTrait;
+    pub use crate::slice::{Owned, SliceTrait};
+    pub use crate::shema::{decode, encode, Shema};
+    pub use crate::{ColumnInfo, DataBuilder, EncodeOptions, Input, Options};
+    pub use crate::codec::Codec;
+    #[cfg(feature="encode")]
+    pub use crate::DataBuilderEncode;
+    pub use crate::__cfg_encode as cfg_encode;
+}
+
+// the derived code is compiled in the downstream crate, so it can't check our features itself
+#[cfg(feature="encode")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_encode {
+    ($($t:tt)*) => { $($t)* };
+}
+#[cfg(not(feature="encode"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cfg_encode {
+    ($($t:tt)*) => {};
+}
 
 #[cfg(all(target_feature="aes", target_feature="sse2"))]
 pub type BuildHasher = gxhash::GxBuildHasher;
@@ -50,10 +98,83 @@ pub struct RequestEntry {
     #[serde(default)]
     pub location: Option<SmallString>,
     pub tls_fingerprint: Option<u128>,
+    /// microseconds within `time`
+    #[serde(default)]
+    pub time_us: u32,
+    /// time to first byte in microseconds, 0 if unknown
+    #[serde(default)]
+    pub ttfb: u32,
+    /// total request duration in microseconds, 0 if unknown
+    #[serde(default)]
+    pub duration: u32,
+    /// size of the request received, including headers
+    #[serde(default)]
+    pub bytes_in: u64,
+    /// size of the response sent, including headers
+    #[serde(default)]
+    pub bytes_out: u64,
+    /// length of the body as received, before `BodyPolicy` or the sender dropped or truncated it
+    #[serde(default)]
+    pub body_len: u32,
+    /// BLAKE3 hash of the body when only the hash is kept
+    #[serde(default)]
+    pub body_hash: Option<[u8; 32]>,
+    /// node or instance that produced the entry
+    #[serde(default)]
+    pub source: Option<SmallString>,
+    /// position of the entry among the entries of `source`, together they identify it.
+    /// the collector uses its row id when it is missing.
+    #[serde(default)]
+    pub seq: Option<u64>,
+}
+#[cfg(feature="encode")]
+impl Default for RequestEntry {
+    fn default() -> Self {
+        RequestEntry {
+            status: 0,
+            method: "".into(),
+            uri: String::new(),
+            ip: IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+            port: 0,
+            time: 0,
+            body: None,
+            headers: Headers::default(),
+            host: "".into(),
+            proto: Protocol::Unknown,
+            location: None,
+            tls_fingerprint: None,
+            time_us: 0,
+            ttfb: 0,
+            duration: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            body_len: 0,
+            body_hash: None,
+            source: None,
+            seq: None,
+        }
+    }
+}
+#[cfg(all(feature="encode", any(test, feature="test-util")))]
+impl RequestEntry {
+    /// a GET / from 10.0.0.1:443 to qdat.net over https, for tests
+    pub fn sample() -> Self {
+        RequestEntry {
+            status: 200,
+            method: "GET".into(),
+            uri: "/".into(),
+            ip: "10.0.0.1".parse().unwrap(),
+            port: 443,
+            host: "qdat.net".into(),
+            proto: Protocol::Https,
+            ..Default::default()
+        }
+    }
 }
 
 #[cfg(feature="encode")]
-#[derive(Copy, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Copy, Clone, serde::Serialize, serde::Deserialize, FromRepr)]
+#[repr(u16)]
 pub enum Protocol {
     Unknown = 0,
     Http = 1,
@@ -61,10 +182,39 @@ pub enum Protocol {
 }
 
 
-#[derive(serde::Serialize, serde::Deserialize, Default)]
-pub struct Headers(pub String);
+/// Request headers as received: in order, including duplicates, with raw values.
+/// Names keep the casing they were pushed with; `From<&HeaderMap>` gives lowercase names.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Headers(pub Vec<(String, Bytes)>);
+
+impl Headers {
+    pub fn push(&mut self, name: &str, value: &[u8]) {
+        self.0.push((name.into(), Bytes::copy_from_slice(value)));
+    }
+    pub fn pairs(&self) -> Vec<(&str, &[u8])> {
+        self.0.iter().map(|(k, v)| (k.as_str(), &v[..])).collect()
+    }
+}
+
+/// Appends `bytes` to `out`, escaping `\\`, `\n`, `\r` and bytes that are not UTF-8 as `\xNN`.
+pub fn escape_bytes(out: &mut String, bytes: &[u8]) {
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                c => out.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\x{b:02x}"));
+        }
+    }
+}
 
-pub fn headers_string<'a>(pairs: impl Iterator<Item=(&'a str, &'a str)>) -> String {
+/// One `name:value` line per header, values escaped with `escape_bytes`.
+pub fn headers_string<'a>(pairs: impl Iterator<Item=(&'a str, &'a [u8])>) -> String {
     let mut out = String::new();
     for (i, (k, v)) in pairs.enumerate() {
         if i > 0 {
@@ -72,22 +222,57 @@ pub fn headers_string<'a>(pairs: impl Iterator<Item=(&'a str, &'a str)>) -> Stri
         }
         out.push_str(k);
         out.push(':');
-        out.push_str(v);
+        escape_bytes(&mut out, v);
     }
     out
 }
 
 impl<'a> From<&'a http::HeaderMap> for Headers {
     fn from(map: &'a http::HeaderMap) -> Self {
-        let pairs = map.iter()
-            .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?)));
+        let mut headers = Headers::default();
+        for (k, v) in map.iter() {
+            headers.push(k.as_str(), v.as_bytes());
+        }
+        headers
+    }
+}
 
-        Headers(headers_string(pairs))
+struct HeaderValue<'a>(&'a [u8]);
+impl Serialize for HeaderValue<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(self.0) {
+            Ok(s) if serializer.is_human_readable() => serializer.serialize_str(s),
+            _ => serializer.serialize_bytes(self.0),
+        }
     }
 }
-impl Headers {
-    pub fn split(&self) -> Vec<(&str, &str)> {
-        self.0.split("\n").filter_map(|s| s.split_once(":")).collect()
+impl Serialize for Headers {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter().map(|(k, v)| (k, HeaderValue(v))))
+    }
+}
+impl<'de> Deserialize<'de> for Headers {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // older producers sent a single "name:value" string joined with '\n'
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Joined(String),
+            Pairs(Vec<(String, Bytes)>),
+        }
+        if !deserializer.is_human_readable() {
+            return Vec::deserialize(deserializer).map(Headers);
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Pairs(pairs) => Headers(pairs),
+            Repr::Joined(s) => {
+                let mut headers = Headers::default();
+                for (k, v) in s.split('\n').filter_map(|s| s.split_once(':')) {
+                    headers.push(k, v.as_bytes());
+                }
+                headers
+            }
+        })
     }
 }
 
@@ -96,11 +281,24 @@ fn test_headers() {
     let mut m = http::HeaderMap::new();
     m.insert(REFERER, "https://qdat.net/foo".parse().unwrap());
     m.insert(ACCEPT, "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8".parse().unwrap());
+    m.append(ACCEPT, http::HeaderValue::from_bytes(b"a\xff:b").unwrap());
 
     let h = Headers::from(&m);
-    for (k, v) in h.split() {
-        assert_eq!(m.get(k).unwrap().to_str().unwrap(), v);
+    let pairs = h.pairs();
+    assert_eq!(pairs.len(), 3);
+    for ((k, v), (k2, v2)) in pairs.iter().zip(m.iter()) {
+        assert_eq!((*k, *v), (k2.as_str(), v2.as_bytes()));
     }
+
+    let json = serde_json::to_string(&h).unwrap();
+    assert_eq!(serde_json::from_str::<Headers>(&json).unwrap(), h);
+    let bin = postcard::to_stdvec(&h).unwrap();
+    assert_eq!(postcard::from_bytes::<Headers>(&bin).unwrap(), h);
+
+    let old: Headers = serde_json::from_str(r#""Host:a\nUser-Agent:x:y""#).unwrap();
+    assert_eq!(old.pairs(), [("Host", &b"a"[..]), ("User-Agent", b"x:y")]);
+
+    assert_eq!(headers_string(h.pairs().into_iter().skip(2)), "accept:a\\xff:b");
 }
 
 #[derive(Serialize, Deserialize)]
@@ -141,11 +339,16 @@ pub trait Pos {
 #[derive(Clone)]
 pub struct Input<'a> {
     data: &'a [u8],
-    pos: usize
+    pos: usize,
+    owner: Option<&'a Bytes>,
 }
 impl<'a> Input<'a> {
     pub fn new(data: &'a [u8]) -> Self {
-        Input { data, pos: 0 }
+        Input { data, pos: 0, owner: None }
+    }
+    /// like `new`, but `take_bytes` can return slices of `data` without copying.
+    pub fn from_bytes(data: &'a Bytes) -> Self {
+        Input { data, pos: 0, owner: Some(data) }
     }
     #[inline(always)]
     pub fn advance(&mut self, n: usize) {
@@ -158,6 +361,14 @@ impl<'a> Input<'a> {
         self.pos += n;
         Ok(out)
     }
+    /// take `n` bytes, borrowing them from the owner if there is one
+    pub fn take_bytes(&mut self, n: usize) -> Result<Bytes, Error> {
+        let data = self.take_n(n)?;
+        Ok(match self.owner {
+            Some(owner) => owner.slice_ref(data),
+            None => Bytes::copy_from_slice(data),
+        })
+    }
     pub fn pos(&self) -> usize {
         self.pos
     }
@@ -187,6 +398,16 @@ impl<'a> BetterBufRead for Input<'a> {
     }
 }
 
+/// how a column was stored in a block
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ColumnInfo {
+    pub codec: Codec,
+    /// shema version of the block
+    pub version: u32,
+    /// string sets are separated by newlines instead of length-prefixed, see `types::Legacy`
+    pub newline_strings: bool,
+}
+
 pub trait DataBuilder: Default + Sized {
     type CompressedItem;
     type Item<'a>;
@@ -196,18 +417,107 @@ pub trait DataBuilder: Default + Sized {
     type Data: SliceTrait;
 
     fn add<'a>(&mut self, item: Self::Item<'a>) -> Self::CompressedItem;
-    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, data: Input<'r>, size: Self::Size) -> Result<(Self, Input<'r>), Error>;
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, data: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error>;
     fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>>;
 }
 
 #[cfg(feature="encode")]
 pub trait DataBuilderEncode: DataBuilder {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &Options) -> Result<(Self::Size, W), Error>;
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error>;
 }
 
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Options {
     pub brotli_level: u8,
-    pub dict: &'static [u8]
+    pub dict: &'static [u8],
+    /// use this codec for all columns instead of the ones chosen in the shema
+    pub codec: Option<Codec>,
+    /// compression level for all columns instead of the ones chosen in the shema
+    pub level: Option<u8>,
+    /// try several pco delta and mode specs on a sample of each column and keep the smallest,
+    /// until this much time has been spent on the block. `None` uses fixed specs.
+    pub auto_specs: Option<Duration>,
+}
+
+/// `Options` of the column being encoded, with the state of the block
+#[doc(hidden)]
+#[derive(Default, Clone)]
+pub struct EncodeOptions {
+    options: Options,
+    column: &'static str,
+    deadline: Option<Instant>,
+    stats: Option<Arc<Mutex<BlockStats>>>,
+}
+impl Deref for EncodeOptions {
+    type Target = Options;
+    fn deref(&self) -> &Options {
+        &self.options
+    }
+}
+impl EncodeOptions {
+    /// start encoding a block, optionally collecting stats
+    pub(crate) fn block(options: &Options, stats: Option<Arc<Mutex<BlockStats>>>) -> EncodeOptions {
+        EncodeOptions {
+            options: options.clone(),
+            column: "",
+            deadline: options.auto_specs.map(|budget| Instant::now() + budget),
+            stats,
+        }
+    }
+    /// options for the column `name` that defaults to `codec` and `level`
+    pub fn column(&self, name: &'static str, codec: Codec, level: Option<u8>) -> EncodeOptions {
+        EncodeOptions {
+            options: Options {
+                codec: Some(self.codec.unwrap_or(codec)),
+                level: self.level.or(level),
+                ..self.options.clone()
+            },
+            column: name,
+            ..self.clone()
+        }
+    }
+    pub(crate) fn out_of_time(&self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+    pub(crate) fn record(&self, chunk: impl FnOnce(&'static str) -> ChunkStats) {
+        if let Some(ref stats) = self.stats {
+            stats.lock().unwrap().chunks.push(chunk(self.column));
+        }
+    }
+}
+
+/// how the columns of a block were encoded
+#[derive(Debug, Default, Clone)]
+pub struct BlockStats {
+    pub chunks: Vec<ChunkStats>,
+}
+/// one compressed slice of numbers. a column can consist of several.
+#[derive(Debug, Clone)]
+pub struct ChunkStats {
+    pub column: &'static str,
+    pub codec: Codec,
+    /// the pco specs used, if `codec` is pco
+    pub specs: Option<(DeltaSpec, ModeSpec)>,
+    pub len: usize,
+    pub bytes: usize,
+}
+
+impl Options {
+    /// fast encoding for blocks that are still being filled
+    pub fn live() -> Options {
+        Options {
+            codec: Some(Codec::Lz4),
+            ..Default::default()
+        }
+    }
+    /// best compression with the codecs chosen in the shema,
+    /// spending up to 100ms per block on choosing pco specs
+    pub fn archive() -> Options {
+        Options {
+            brotli_level: 11,
+            auto_specs: Some(Duration::from_millis(100)),
+            ..Default::default()
+        }
+    }
 }
@@ -0,0 +1,209 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use serde::Deserialize;
+
+use crate::RequestEntry;
+
+/// replaces masked header values and query parameters
+pub const MASK: &str = "redacted";
+
+/// Redaction applied to each entry at ingest, usually read from a TOML file:
+///
+/// ```toml
+/// drop_headers = ["cookie", "set-cookie"]
+/// mask_headers = ["authorization"]
+/// query_params = ["token", "password"]
+///
+/// [ip]
+/// mode = "truncate"
+/// v4 = 24
+/// v6 = 48
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyPolicy {
+    /// headers removed from the entry
+    pub drop_headers: Vec<String>,
+    /// headers whose value is replaced by `MASK`
+    pub mask_headers: Vec<String>,
+    /// query parameters whose value is replaced by `MASK`, in the uri, the location and the referer header
+    pub query_params: Vec<String>,
+    pub ip: IpPolicy,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase", deny_unknown_fields)]
+pub enum IpPolicy {
+    #[default]
+    Keep,
+    /// keep only the first `v4` / `v6` bits
+    Truncate {
+        #[serde(default = "default_v4_prefix")]
+        v4: u8,
+        #[serde(default = "default_v6_prefix")]
+        v6: u8,
+    },
+    /// replace the address with a keyed BLAKE3 hash of it, of the same family
+    Hash { key: HashKey },
+}
+
+/// BLAKE3 key of `IpPolicy::Hash`, derived from the configured string when the policy is loaded
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "String")]
+pub struct HashKey([u8; 32]);
+impl From<&str> for HashKey {
+    fn from(key: &str) -> Self {
+        HashKey(blake3::derive_key("clog ip pseudonym", key.as_bytes()))
+    }
+}
+impl From<String> for HashKey {
+    fn from(key: String) -> Self {
+        key.as_str().into()
+    }
+}
+fn default_v4_prefix() -> u8 { 24 }
+fn default_v6_prefix() -> u8 { 48 }
+
+impl PrivacyPolicy {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let policy = toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        Ok(policy)
+    }
+    pub fn apply(&self, e: &mut RequestEntry) {
+        let listed = |list: &[String], name: &str| list.iter().any(|h| h.eq_ignore_ascii_case(name));
+
+        e.headers.0.retain(|(k, _)| !listed(&self.drop_headers, k));
+        for (k, v) in e.headers.0.iter_mut() {
+            if listed(&self.mask_headers, k) {
+                *v = Bytes::from_static(MASK.as_bytes());
+            } else if k.eq_ignore_ascii_case("referer")
+                && let Some(scrubbed) = std::str::from_utf8(v).ok().and_then(|s| self.scrub_query(s))
+            {
+                *v = scrubbed.into();
+            }
+        }
+        if let Some(uri) = self.scrub_query(&e.uri) {
+            e.uri = uri;
+        }
+        if let Some(location) = e.location.as_deref().and_then(|l| self.scrub_query(l)) {
+            e.location = Some(location.into());
+        }
+        e.ip = self.ip.apply(e.ip);
+    }
+    /// `uri` with the listed query parameters masked, or `None` if there was nothing to mask
+    fn scrub_query(&self, uri: &str) -> Option<String> {
+        let (path, query) = uri.split_once('?')?;
+        let (query, fragment) = match query.split_once('#') {
+            Some((q, f)) => (q, Some(f)),
+            None => (query, None),
+        };
+        let mut changed = false;
+        let params: Vec<String> = query.split('&').map(|param| {
+            let name = param.split_once('=').map_or(param, |(name, _)| name);
+            if self.query_params.iter().any(|p| p.eq_ignore_ascii_case(name)) {
+                changed = true;
+                format!("{name}={MASK}")
+            } else {
+                param.into()
+            }
+        }).collect();
+        if !changed {
+            return None;
+        }
+        let mut out = format!("{path}?{}", params.join("&"));
+        if let Some(fragment) = fragment {
+            out.push('#');
+            out.push_str(fragment);
+        }
+        Some(out)
+    }
+}
+
+impl IpPolicy {
+    pub fn apply(&self, ip: IpAddr) -> IpAddr {
+        match *self {
+            IpPolicy::Keep => ip,
+            IpPolicy::Truncate { v4, v6 } => match ip {
+                IpAddr::V4(ip) => {
+                    let mask = u32::MAX.checked_shl(32 - v4.min(32) as u32).unwrap_or(0);
+                    IpAddr::V4(Ipv4Addr::from_bits(ip.to_bits() & mask))
+                }
+                IpAddr::V6(ip) => {
+                    let mask = u128::MAX.checked_shl(128 - v6.min(128) as u32).unwrap_or(0);
+                    IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & mask))
+                }
+            },
+            IpPolicy::Hash { key: HashKey(ref key) } => {
+                let octets = match ip {
+                    IpAddr::V4(ip) => ip.octets().to_vec(),
+                    IpAddr::V6(ip) => ip.octets().to_vec(),
+                };
+                let hash = blake3::keyed_hash(key, &octets);
+                let hash = hash.as_bytes();
+                match ip {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(hash[0], hash[1], hash[2], hash[3])),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&hash[..16]).unwrap())),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_privacy_policy() {
+    use crate::{Headers, Protocol};
+
+    let policy: PrivacyPolicy = toml::from_str(r#"
+        drop_headers = ["cookie"]
+        mask_headers = ["authorization"]
+        query_params = ["token"]
+        [ip]
+        mode = "truncate"
+    "#).unwrap();
+
+    let mut headers = Headers::default();
+    headers.push("Cookie", b"session=1");
+    headers.push("Authorization", b"Bearer abc");
+    headers.push("Referer", b"https://qdat.net/?token=abc");
+    headers.push("Accept", b"*/*");
+    let mut e = RequestEntry {
+        status: 200,
+        method: "GET".into(),
+        uri: "/login?user=a&Token=secret#top".into(),
+        location: Some("https://qdat.net/next?token=secret".into()),
+        ip: "192.168.17.42".parse().unwrap(),
+        port: 80,
+        headers,
+        host: "qdat.net".into(),
+        proto: Protocol::Http,
+        ..Default::default()
+    };
+    policy.apply(&mut e);
+
+    assert_eq!(e.uri, "/login?user=a&Token=redacted#top");
+    assert_eq!(e.location.as_deref(), Some("https://qdat.net/next?token=redacted"));
+    assert_eq!(e.ip, "192.168.17.0".parse::<IpAddr>().unwrap());
+    assert_eq!(e.headers.pairs(), [
+        ("Authorization", &b"redacted"[..]),
+        ("Referer", b"https://qdat.net/?token=redacted"),
+        ("Accept", b"*/*"),
+    ]);
+
+    let v6: IpAddr = "2001:db8:1234:5678::1".parse().unwrap();
+    let truncate = IpPolicy::Truncate { v4: 24, v6: 48 };
+    assert_eq!(truncate.apply(v6), "2001:db8:1234::".parse::<IpAddr>().unwrap());
+
+    let hash = IpPolicy::Hash { key: "k".into() };
+    assert_eq!(hash.apply(v6), hash.apply(v6));
+    assert_ne!(hash.apply(v6), v6);
+    assert!(hash.apply(e.ip).is_ipv4());
+    let loaded: IpPolicy = toml::from_str(r#"
+        mode = "hash"
+        key = "k"
+    "#).unwrap();
+    assert_eq!(loaded.apply(v6), hash.apply(v6));
+}
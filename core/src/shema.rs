@@ -1,21 +1,19 @@
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use serde::de::DeserializeOwned;
-use std::alloc::Layout;
 use std::ops::Range;
+use std::sync::{Arc, Mutex};
 use pco::wrapped::{FileCompressor, FileDecompressor};
 use anyhow::{bail, Error};
 use serde::{Serialize, Deserialize};
 
-use crate::types::{DataSeries, HashArray};
+use crate::types::{DataSeries, HashArray, Legacy, SortedStrings, Upgrade};
 use crate::util::WriteAdapter;
-use crate::{types::{HashIpv6, HashStrings, HashStringsOpt, NumberSeries, TimeSeries, StringMap}, DataBuilder, Options, RequestEntry,
+use crate::{types::{HashIpv6, HashStrings, HashStringsOpt, NumberSeries, TimeSeries, StringMap}, BlockStats, EncodeOptions, Options, RequestEntry,
     slice::{SliceTrait, Owned},
     Input
 };
-use crate as clog;
 
-#[cfg(feature="encode")]
-use crate::DataBuilderEncode;
+pub use clog_derive::Shema;
 
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -29,38 +27,91 @@ const V3: u32 = 3;
 const V4: u32 = 4;
 const V5: u32 = 5;
 const V6: u32 = 6;
-const SHEMA_VERSION: u32 = V6;
+const V7: u32 = 7;
+const V8: u32 = 8;
+const V9: u32 = 9;
+const V10: u32 = 10;
+const V11: u32 = 11;
+const V12: u32 = 12;
+pub const SHEMA_VERSION: u32 = V12;
+
+/// set in `Header::version` when fields are stored with their codec.
+/// older readers see a version that is too high and refuse the block.
+const FLAG_CODECS: u32 = 1 << 31;
 
 #[allow(unused)]
-#[derive(clog_derive::Shema)]
+#[derive(Shema)]
+#[clog(version = SHEMA_VERSION)]
 pub struct ShemaImpl {
     status: NumberSeries<u16>,
-    method: HashStrings,
-    uri: HashStrings,
+    method: Upgrade<Legacy<HashStrings>, HashStrings, V10>,
+    uri: Upgrade<Legacy<HashStrings>, SortedStrings, V9>,
     #[clog(max_version=V2)]
-    ua: HashStringsOpt,
+    ua: Legacy<HashStringsOpt>,
     #[clog(max_version=V2)]
-    referer: HashStringsOpt,
+    referer: Legacy<HashStringsOpt>,
     ip: HashIpv6,
     port: NumberSeries<u16>,
+    #[clog(time)]
     time: TimeSeries,
     #[clog(min_version=V2)]
     body: DataSeries,
     #[clog(min_version=V3)]
-    headers: StringMap,
+    headers: Upgrade<Legacy<StringMap>, StringMap, V10>,
     #[clog(min_version=V3)]
-    host: HashStrings,
+    host: Upgrade<Legacy<HashStrings>, SortedStrings, V9>,
     #[clog(min_version=V4)]
     proto: NumberSeries<u16>,
     #[clog(min_version=V5)]
-    location: HashStringsOpt,
+    location: Upgrade<Legacy<HashStringsOpt>, HashStringsOpt, V10>,
     #[clog(min_version=V6)]
     tls_fp: HashArray<16>,
+    #[clog(min_version=V7)]
+    time_us: NumberSeries<u32>,
+    #[clog(min_version=V7)]
+    ttfb: NumberSeries<u32>,
+    #[clog(min_version=V7)]
+    duration: NumberSeries<u32>,
+    #[clog(min_version=V8)]
+    bytes_in: NumberSeries<u64>,
+    #[clog(min_version=V8)]
+    bytes_out: NumberSeries<u64>,
+    #[clog(min_version=V11)]
+    body_len: NumberSeries<u32>,
+    #[clog(min_version=V11)]
+    body_hash: HashArray<32>,
+    #[clog(min_version=V12)]
+    source: HashStrings,
+    #[clog(min_version=V12)]
+    seq: NumberSeries<u64>,
 }
 
 pub type BatchEntry<'a> = ShemaImplItem<'a>;
 pub type Builder = ShemaImplBuilder;
 
+impl Builder {
+    /// dictionary of the uri column
+    pub fn uris(&self) -> &SortedStrings {
+        &self.uri
+    }
+    /// dictionary of the host column
+    pub fn hosts(&self) -> &SortedStrings {
+        &self.host
+    }
+    /// uri and host symbols of row `idx`
+    pub fn row_symbols(&self, idx: usize) -> Option<(u32, u32)> {
+        let row = self.soa.get(idx)?;
+        Some((row.uri, row.host))
+    }
+}
+
+impl BatchEntry<'_> {
+    /// microseconds since the unix epoch
+    pub fn timestamp_us(&self) -> u64 {
+        self.time * 1_000_000 + self.time_us as u64
+    }
+}
+
 pub fn decode<'a, T: DeserializeOwned>(mut input: Input<'a>) -> Result<(T, Input<'a>), Error> {
     let (val, rest) = postcard::take_from_bytes(&input)?;
     input.advance(input.len() - rest.len());
@@ -72,9 +123,14 @@ pub fn encode<T: Serialize, W: Extend<u8>>(val: T, writer: W) -> Result<W, Error
 }
 
 pub trait Shema: Sized {
-    type Item<'a>;
+    type Item<'a>: Serialize + Deserialize<'a>;
     type Fields: SliceTrait;
 
+    /// version written into new blocks. blocks with a higher version are rejected.
+    const VERSION: u32;
+    /// a field is marked `#[clog(time)]`, see `max_time`
+    const HAS_TIME: bool = false;
+
     fn with_capacity(n: usize) -> Self;
 
     fn add(&mut self, item: Self::Item<'_>);
@@ -84,9 +140,14 @@ pub trait Shema: Sized {
     fn fields(&self) -> &Owned<Self::Fields>;
 
     #[cfg(feature="encode")]
-    fn write(&self, f: &FileCompressor, writer: BytesMut, opt: &Options, version: u32) -> Result<BytesMut, Error>;
-    fn read<'a>(f: &FileDecompressor, data: Input<'a>, len: usize, version: u32) -> Result<(Self, Input<'a>), Error>;
+    fn write(&self, f: &FileCompressor, writer: BytesMut, opt: &EncodeOptions, version: u32) -> Result<BytesMut, Error>;
+    /// `codecs`: every field is preceded by the codec it was written with
+    fn read<'a>(f: &FileDecompressor, data: Input<'a>, len: usize, version: u32, codecs: bool) -> Result<(Self, Input<'a>), Error>;
     fn reserve(&mut self, additional: usize);
+    /// highest value of the field marked `#[clog(time)]`, in unix seconds
+    fn max_time(&self) -> Option<u64> {
+        None
+    }
 
     fn iter(&self) -> impl Iterator<Item=Result<Self::Item<'_>, &'static str>> + ExactSizeIterator {
         self.fields().iter().map(|i| self.decompress(i))
@@ -96,31 +157,52 @@ pub trait Shema: Sized {
     }
 
     #[cfg(feature="encode")]
-    fn write_to(&self, mut writer: BytesMut, opt: &Options) -> BytesMut {
+    fn write_to(&self, writer: BytesMut, opt: &Options) -> BytesMut {
+        self.write_block(writer, &EncodeOptions::block(opt, None))
+    }
+    /// like `write_to`, but also reports how each column was encoded
+    #[cfg(feature="encode")]
+    fn write_to_with_stats(&self, writer: BytesMut, opt: &Options) -> (BytesMut, BlockStats) {
+        let stats = Arc::new(Mutex::new(BlockStats::default()));
+        let writer = self.write_block(writer, &EncodeOptions::block(opt, Some(stats.clone())));
+        let stats = std::mem::take(&mut *stats.lock().unwrap());
+        (writer, stats)
+    }
+    #[cfg(feature="encode")]
+    #[doc(hidden)]
+    fn write_block(&self, mut writer: BytesMut, opt: &EncodeOptions) -> BytesMut {
         let f = FileCompressor::default();
         writer.reserve(10 * self.len() + 100);
 
         let header = Header {
-            version: SHEMA_VERSION,
+            version: Self::VERSION | FLAG_CODECS,
             len: self.len() as u32,
         };
         let writer = postcard::to_extend(&header, writer).unwrap();
         let writer = WriteAdapter(writer);
         let WriteAdapter(writer) = f.write_header(writer).unwrap();
-        let writer = self.write(&f, writer, opt, SHEMA_VERSION).unwrap();
+        let writer = self.write(&f, writer, opt, Self::VERSION).unwrap();
         writer
     }
     fn from_slice(data: &[u8]) -> Result<Self, Error> {
-        let input = Input::new(data);
+        Self::from_input(Input::new(data))
+    }
+    /// like `from_slice`, but fixed size tables are borrowed from `data` instead of copied.
+    fn from_bytes(data: &Bytes) -> Result<Self, Error> {
+        Self::from_input(Input::from_bytes(data))
+    }
+    fn from_input(input: Input) -> Result<Self, Error> {
         let (header, reader) = decode::<Header>(input)?;
         //println!("header: {header:?}");
-        if header.version > SHEMA_VERSION {
-            bail!("found version {} but compiled with version {}", header.version, SHEMA_VERSION);
+        let codecs = header.version & FLAG_CODECS != 0;
+        let version = header.version & !FLAG_CODECS;
+        if version > Self::VERSION {
+            bail!("found version {} but compiled with version {}", version, Self::VERSION);
         }
         //println!("after header reader at {}", reader.pos());
         let (f, reader) = FileDecompressor::new(reader)?;
         //println!("after decmpressor reader at {}", reader.pos());
-        let (builder, _reader) = Self::read(&f, reader, header.len as usize, header.version)?;
+        let (builder, _reader) = Self::read(&f, reader, header.len as usize, version, codecs)?;
         Ok(builder)
     }
     #[cfg(feature="encode")]
@@ -150,11 +232,20 @@ impl<'a> From<&'a RequestEntry> for BatchEntry<'a> {
             port: e.port,
             time: e.time,
             body: e.body.as_deref(),
-            headers: e.headers.split(),
+            headers: e.headers.pairs(),
             host: &e.host,
             proto: e.proto as u16,
             location: e.location.as_deref(),
             tls_fp: e.tls_fingerprint.unwrap_or(0).to_le_bytes(),
+            time_us: e.time_us,
+            ttfb: e.ttfb,
+            duration: e.duration,
+            bytes_in: e.bytes_in,
+            bytes_out: e.bytes_out,
+            body_len: e.body_len,
+            body_hash: e.body_hash.unwrap_or([0; 32]),
+            source: e.source.as_deref().unwrap_or_default(),
+            seq: e.seq.unwrap_or(0),
         }
     }
 }
@@ -89,6 +89,9 @@ impl<F: SliceTrait> Owned<F> {
     pub fn len(&self) -> usize {
         self.len
     }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 }
 impl<F: SliceTrait> Drop for Owned<F> {
     fn drop(&mut self) {
@@ -136,10 +139,22 @@ pub trait SliceTrait: Debug {
     type SliceUninit<'a>;
     type Elem;
     fn layout(capacity: usize) -> (Layout, Self);
+
+    /// # Safety
+    /// `raw` must point to an allocation made with the layout returned by `layout`,
+    /// holding at least `len` initialized elements.
     unsafe fn slice<'a>(&self, raw: *mut u8, len: usize) -> Self::Slice<'a>;
+    /// # Safety
+    /// see `slice`
     unsafe fn slice_mut<'a>(&self, raw: *mut u8, len: usize) -> Self::SliceMut<'a>;
+    /// # Safety
+    /// `raw` must point to an allocation made with the layout returned by `layout` for at least `len` elements.
     unsafe fn slice_uninit<'a>(&self, raw: *mut u8, len: usize) -> Self::SliceUninit<'a>;
+    /// # Safety
+    /// `idx` must be less than the number of initialized elements at `raw`.
     unsafe fn get(&self, raw: *mut u8, idx: usize) -> Self::Elem;
+    /// # Safety
+    /// `idx` must be less than the capacity of the allocation at `raw`.
     unsafe fn write(&self, raw: *mut u8, idx: usize, val: Self::Elem);
 
     fn copy_slice<'a, 'b>(from: Self::Slice<'a>, to: Self::SliceMut<'b>);
@@ -1,14 +1,19 @@
 use std::marker::PhantomData;
+use std::ops::{Bound, Deref, RangeBounds};
 use std::{io, net::Ipv6Addr};
 
+use std::collections::HashMap;
+use std::hash::{BuildHasher as _, Hash};
+use std::mem::size_of;
+use std::sync::OnceLock;
+
 use anyhow::{Context, Error};
-use better_io::BetterBufRead;
-use bytemuck::bytes_of_mut;
+use bytemuck::{bytes_of_mut, Pod};
+use bytes::Bytes;
 use indexmap::IndexSet;
-use itertools::intersperse;
 use pco::data_types::Number;
 use pco::ChunkConfig;
-use pco::{wrapped::{FileCompressor, FileDecompressor}, DeltaSpec};
+use pco::{wrapped::{FileCompressor, FileDecompressor}, DeltaSpec, ModeSpec};
 use string_interner::backend::StringBackend;
 use string_interner::symbol::SymbolU32;
 use string_interner::{StringInterner, Symbol};
@@ -18,7 +23,12 @@ use crate::slice::{Tuple1, Tuple2};
 use crate::DataBuilderEncode;
 
 use crate::Input;
-use crate::{DataBuilder, Options, Pos, BuildHasher};
+use crate::codec::{decompress_bytes, Codec};
+#[cfg(feature="encode")]
+use crate::codec::compress_bytes;
+use crate::{ColumnInfo, DataBuilder, EncodeOptions, Options, Pos, BuildHasher};
+#[cfg(feature="encode")]
+use crate::ChunkStats;
 
 
 #[derive(Clone)]
@@ -31,31 +41,79 @@ impl Default for HashStrings {
     }
 }
 
+// separator of the string sets written by `Legacy` columns
 const STR_SEP_1: char = '\n';
-const STR_SEP_1_STR: &str = "\n";
 
+/// each entry prefixed with its length
 #[cfg(feature="encode")]
-fn write_string_set_inner<'a, W: io::Write + Pos>(set: &StringInterner<StringBackend, BuildHasher>, mut writer: W, opt: &Options) -> Result<(u32, W), Error> {
-    let strings: String = intersperse(set.iter().map(|(_, s)| s), STR_SEP_1_STR).collect();
-    let len = compress_string(&mut writer, &strings, opt)?;
+fn write_byte_set<'a, W: io::Write + Pos>(items: impl Iterator<Item=&'a [u8]>, mut writer: W, opt: &EncodeOptions) -> Result<(u32, W), Error> {
+    let mut data = vec![];
+    for item in items {
+        data = postcard::to_extend(&(item.len() as u32), data)?;
+        data.extend_from_slice(item);
+    }
+    let len = compress_data(&mut writer, &data, opt)?;
     Ok((len as u32, writer))
 }
-fn read_string_set_inner<'a, 'r>(reader: Input<'r>, size: u32) -> Result<(StringInterner<StringBackend, BuildHasher>, Input<'r>), Error> {
-    let (strings, reader) = decompress_string(reader, size as usize)?;
+fn split_byte_set(mut data: &[u8]) -> impl Iterator<Item=Result<&[u8], Error>> {
+    std::iter::from_fn(move || {
+        if data.is_empty() {
+            return None;
+        }
+        let entry = postcard::take_from_bytes::<u32>(data).map_err(Error::from).and_then(|(len, rest)| {
+            let (item, rest) = rest.split_at_checked(len as usize).context("byte set entry")?;
+            data = rest;
+            Ok(item)
+        });
+        if entry.is_err() {
+            data = &[];
+        }
+        Some(entry)
+    })
+}
+
+#[cfg(feature="encode")]
+fn write_string_set_inner<'a, W: io::Write + Pos>(set: &StringInterner<StringBackend, BuildHasher>, writer: W, opt: &EncodeOptions) -> Result<(u32, W), Error> {
+    write_byte_set(set.iter().map(|(_, s)| s.as_bytes()), writer, opt)
+}
+fn read_string_set_inner<'a, 'r>(reader: Input<'r>, size: u32, col: ColumnInfo) -> Result<(StringInterner<StringBackend, BuildHasher>, Input<'r>), Error> {
     let mut set = StringInterner::with_hasher(BuildHasher::default());
-    set.extend(strings.split(STR_SEP_1));
+    if col.newline_strings {
+        let (strings, reader) = decompress_string(reader, size as usize, col.codec)?;
+        set.extend(strings.split(STR_SEP_1));
+        return Ok((set, reader));
+    }
+    let (data, reader) = decompress_data(reader, size as usize, col.codec)?;
+    for item in split_byte_set(&data) {
+        set.get_or_intern(std::str::from_utf8(item?)?);
+    }
+    Ok((set, reader))
+}
+type ByteSet = IndexSet<Box<[u8]>, BuildHasher>;
+
+fn read_byte_set_inner<'r>(reader: Input<'r>, size: u32, col: ColumnInfo) -> Result<(ByteSet, Input<'r>), Error> {
+    let mut set = IndexSet::with_hasher(BuildHasher::default());
+    if col.newline_strings {
+        let (strings, reader) = decompress_string(reader, size as usize, col.codec)?;
+        set.extend(strings.split(STR_SEP_1).map(|s| s.as_bytes().into()));
+        return Ok((set, reader));
+    }
+    let (data, reader) = decompress_data(reader, size as usize, col.codec)?;
+    for item in split_byte_set(&data) {
+        set.insert(item?.into());
+    }
     Ok((set, reader))
 }
 
 #[cfg(feature="encode")]
-fn write_string_set<'a, W: io::Write + Pos>(set: &StringInterner<StringBackend, BuildHasher>, f: &FileCompressor, slice: &'a [u32], writer: W, opt: &Options) -> Result<(u32, W), Error> {
+fn write_string_set<'a, W: io::Write + Pos>(set: &StringInterner<StringBackend, BuildHasher>, f: &FileCompressor, slice: &'a [u32], writer: W, opt: &EncodeOptions) -> Result<(u32, W), Error> {
     let (len, writer) = write_string_set_inner(set, writer, opt)?;
-    let writer = compress_slice(f, writer, slice, DeltaSpec::NoOp)?;
+    let writer = compress_slice(f, writer, slice, DeltaSpec::NoOp, opt)?;
     Ok((len as u32, writer))
 }
-fn read_string_set<'a, 'r>(f: &FileDecompressor, slice: &'a mut [u32], reader: Input<'r>, size: u32) -> Result<(StringInterner<StringBackend, BuildHasher>, Input<'r>), Error> {
-    let (set, reader) = read_string_set_inner(reader, size)?;
-    let reader = decompress_slice(f, reader, slice)?;
+fn read_string_set<'a, 'r>(f: &FileDecompressor, slice: &'a mut [u32], reader: Input<'r>, size: u32, col: ColumnInfo) -> Result<(StringInterner<StringBackend, BuildHasher>, Input<'r>), Error> {
+    let (set, reader) = read_string_set_inner(reader, size, col)?;
+    let reader = decompress_slice(f, reader, slice, col.codec)?;
     Ok((set, reader))
 }
 impl DataBuilder for HashStrings {
@@ -70,17 +128,21 @@ impl DataBuilder for HashStrings {
         let sym = self.set.get_or_intern(item);
         sym.to_usize() as u32
     }
-    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size) -> Result<(Self, Input<'r>), Error> {
-        let (set, reader) = read_string_set(f, slice, reader, size)?;
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let (set, reader) = read_string_set(f, slice, reader, size, col)?;
         Ok((HashStrings { set }, reader))
     }
     fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>> {
+        // columns added after a block was written are empty
+        if compressed == 0 && self.set.is_empty() {
+            return Some("");
+        }
         self.set.resolve(SymbolU32::try_from_usize(compressed as usize)?)
     }
 }
 #[cfg(feature="encode")]
 impl DataBuilderEncode for HashStrings {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &Options) -> Result<(Self::Size, W), Error> {
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
         write_string_set(&self.set, f, &slice, writer, opt)
     }
 }
@@ -88,14 +150,14 @@ impl DataBuilderEncode for HashStrings {
 #[derive(Clone)]
 pub struct StringMap {
     keys: StringInterner<StringBackend, BuildHasher>,
-    values: StringInterner<StringBackend, BuildHasher>,
+    values: ByteSet,
     entries: IndexSet<Vec<(u32, u32)>, BuildHasher>,
 }
 impl Default for StringMap {
     fn default() -> Self {
         StringMap {
             keys: StringInterner::with_hasher(BuildHasher::default()),
-            values: StringInterner::with_hasher(BuildHasher::default()),
+            values: IndexSet::with_hasher(BuildHasher::default()),
             entries: IndexSet::with_hasher(BuildHasher::default())
         }
     }
@@ -112,7 +174,7 @@ Encode as
 
 impl DataBuilder for StringMap {
     type CompressedItem = u32;
-    type Item<'a> = Vec<(&'a str, &'a str)>;
+    type Item<'a> = Vec<(&'a str, &'a [u8])>;
     type Slice<'a> = &'a [u32];
     type SliceMut<'a> = &'a mut [u32];
     type Size = (u32, u32, u32);
@@ -122,33 +184,36 @@ impl DataBuilder for StringMap {
         let mut entry = vec![];
         for (key, val) in item {
             let key_idx = self.keys.get_or_intern(key).to_usize() as u32;
-            let val_idx = self.values.get_or_intern(val).to_usize() as u32;
+            let val_idx = match self.values.get_index_of(val) {
+                Some(idx) => idx,
+                None => self.values.insert_full(val.into()).0,
+            } as u32;
             entry.push((key_idx, val_idx));
         }
         let (entry_idx, _) = self.entries.insert_full(entry);
         entry_idx as u32
     }
-    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size) -> Result<(Self, Input<'r>), Error> {
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
         let (keys_size, vals_size, n_entries) = size;
 
         // set of key strings
-        let (key_set, reader) = read_string_set_inner(reader, keys_size)?;
+        let (key_set, reader) = read_string_set_inner(reader, keys_size, col)?;
 
-        // set of value strings
-        let (val_set, reader) = read_string_set_inner(reader, vals_size)?;
+        // set of values, not necessarily UTF-8
+        let (val_set, reader) = read_byte_set_inner(reader, vals_size, col)?;
 
         let mut entries_len: Vec<u16> = vec![0; n_entries as usize];
 
         // length of entry vecs
-        let reader = decompress_slice(f, reader, &mut entries_len)?;
+        let reader = decompress_slice(f, reader, &mut entries_len, col.codec)?;
         let n_total: usize = entries_len.iter().map(|&n| n as usize).sum();
 
         let mut keys_idx: Vec<u32> = vec![0; n_total];
         // concatenated entry key indices
-        let reader = decompress_slice(f, reader, &mut keys_idx)?;
+        let reader = decompress_slice(f, reader, &mut keys_idx, col.codec)?;
         let mut val_idx: Vec<u32> = vec![0; n_total];
         // concatenated entry value indices
-        let reader = decompress_slice(f, reader, &mut val_idx)?;
+        let reader = decompress_slice(f, reader, &mut val_idx, col.codec)?;
 
         let mut iter = keys_idx.into_iter().zip(val_idx);
         let mut entries = IndexSet::with_capacity_and_hasher(entries_len.len(), BuildHasher::default());
@@ -157,7 +222,7 @@ impl DataBuilder for StringMap {
             entries.insert(pairs);
         }
 
-        let reader = decompress_slice(f, reader, slice)?;
+        let reader = decompress_slice(f, reader, slice, col.codec)?;
 
         Ok((StringMap { keys: key_set, values: val_set, entries }, reader))
     }
@@ -168,33 +233,33 @@ impl DataBuilder for StringMap {
         let entry = self.entries.get_index(compressed as usize)?;
         Some(entry.iter().filter_map(|&(key_idx, val_idx)| {
             let key = self.keys.resolve(SymbolU32::try_from_usize(key_idx as usize)?)?;
-            let val = self.values.resolve(SymbolU32::try_from_usize(val_idx as usize)?)?;
-            Some((key, val))
+            let val = self.values.get_index(val_idx as usize)?;
+            Some((key, &**val))
         }).collect())
     }
 }
 
 #[cfg(feature="encode")]
 impl DataBuilderEncode for StringMap {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &Options) -> Result<(Self::Size, W), Error> {
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
         // set of key strings
         let (keys_size, writer) = write_string_set_inner(&self.keys, writer, opt)?;
 
-        // set of value strings
-        let (vals_size, writer) = write_string_set_inner(&self.values, writer, opt)?;
+        // set of values
+        let (vals_size, writer) = write_byte_set(self.values.iter().map(|v| &**v), writer, opt)?;
 
         // length of entry vecs
         let entries_len: Vec<u16> = self.entries.iter().map(|v| v.len() as u16).collect();
-        let writer = compress_slice(f, writer, &entries_len, DeltaSpec::NoOp)?;
+        let writer = compress_slice(f, writer, &entries_len, DeltaSpec::NoOp, opt)?;
 
         let (keys_idx, vals_idx): (Vec<u32>, Vec<u32>) = self.entries.iter().flat_map(|v| v.iter().cloned()).unzip();
         // concatenated entry key indices
-        let writer = compress_slice(f, writer, &keys_idx, DeltaSpec::Auto)?;
+        let writer = compress_slice(f, writer, &keys_idx, DeltaSpec::Auto, opt)?;
 
         // concatenated entry value indices
-        let writer = compress_slice(f, writer, &vals_idx, DeltaSpec::Auto)?;
+        let writer = compress_slice(f, writer, &vals_idx, DeltaSpec::Auto, opt)?;
 
-        let writer = compress_slice(f, writer, slice, DeltaSpec::Auto)?;
+        let writer = compress_slice(f, writer, slice, DeltaSpec::Auto, opt)?;
 
         let n_entries = self.entries.len() as u32;
 
@@ -213,14 +278,14 @@ fn test_stringmap() {
 
     //println!("offset {}", writer.pos());
     let mut map = StringMap::default();
-    let entry = vec![("Foo", "bar"), ("baz", "0123 412")];
+    let entry = vec![("Foo", &b"bar"[..]), ("baz", b"0123\n412"), ("Foo", b"\xff:\x00")];
     let n = map.add(entry.clone());
-    let (size, writer) = map.write(&f, &[n], writer, &Options::default()).unwrap();
+    let (size, writer) = map.write(&f, &[n], writer, &EncodeOptions::default()).unwrap();
 
     let reader = Input::new(writer.as_slice());
     let (f, reader) = FileDecompressor::new(reader).unwrap();
     let mut slice = vec![0];
-    let (map2, _reader) = StringMap::read(&f, &mut slice, reader, size).unwrap();
+    let (map2, _reader) = StringMap::read(&f, &mut slice, reader, size, ColumnInfo::default()).unwrap();
 
     assert_eq!(map2.get(n).unwrap(), entry);
 }
@@ -229,6 +294,8 @@ fn test_stringmap() {
 pub struct DataSeries {
     data: Vec<u8>,
     offsets: Vec<u32>,
+    // hash of each stored item, so identical bodies are stored once per block
+    dedup: HashMap<u64, u32>,
 }
 impl DataBuilder for DataSeries {
     type CompressedItem = u32;
@@ -240,9 +307,15 @@ impl DataBuilder for DataSeries {
 
     fn add<'a>(&mut self, item: Self::Item<'a>) -> Self::CompressedItem {
         if let Some(data) = item {
+            let hash = BuildHasher::default().hash_one(data);
+            if let Some(&idx) = self.dedup.get(&hash) && self.get(idx) == Some(Some(data)) {
+                return idx;
+            }
             self.data.extend_from_slice(data);
             self.offsets.push(self.data.len() as u32);
-            self.offsets.len() as u32
+            let idx = self.offsets.len() as u32;
+            self.dedup.insert(hash, idx);
+            idx
         } else {
             0
         }
@@ -259,31 +332,62 @@ impl DataBuilder for DataSeries {
             Some(Some(self.data.get(start .. end)?))
         }
     }
-    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, (offsets_len, cdata_len): Self::Size) -> Result<(Self, Input<'r>), Error> {
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, (offsets_len, cdata_len): Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
         let mut offsets = vec![0; offsets_len as usize];
-        let mut reader = decompress_slice(f, reader, slice)?;
+        let mut reader = decompress_slice(f, reader, slice, col.codec)?;
         if offsets_len > 0 {
-            reader = decompress_slice(f, reader, &mut offsets)?;
+            reader = decompress_slice(f, reader, &mut offsets, col.codec)?;
         }
-        let (data, reader) = decompress_data(reader, cdata_len as usize)?;
-        Ok((DataSeries {
-            data, offsets
-        }, reader))
+        let (data, reader) = decompress_data(reader, cdata_len as usize, col.codec)?;
+        let mut series = DataSeries { data, offsets, dedup: HashMap::new() };
+        // blocks are added to after decoding, e.g. when they are merged
+        for idx in 1 ..= series.offsets.len() as u32 {
+            if let Some(Some(item)) = series.get(idx) {
+                let hash = BuildHasher::default().hash_one(item);
+                series.dedup.entry(hash).or_insert(idx);
+            }
+        }
+        Ok((series, reader))
     }
 }
 
 #[cfg(feature="encode")]
 impl DataBuilderEncode for DataSeries {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &Options) -> Result<(Self::Size, W), Error> {
-        let mut writer = compress_slice(f, writer, slice, DeltaSpec::TryLookback)?;
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
+        let mut writer = compress_slice(f, writer, slice, DeltaSpec::TryLookback, opt)?;
         if self.offsets.len() > 0 {
-            writer = compress_slice(f, writer, &self.offsets, DeltaSpec::TryConsecutive(2))?;
+            writer = compress_slice(f, writer, &self.offsets, DeltaSpec::TryConsecutive(2), opt)?;
         }
         let cdata_len = compress_data(&mut writer, &self.data, opt)? as u32;
         Ok((((self.offsets.len() as u32, cdata_len)), writer))
     }
 }
 
+#[cfg(feature="encode")]
+#[test]
+fn test_data_series_dedup() {
+    let mut series = DataSeries::default();
+    let a = series.add(Some(b"upload"));
+    let b = series.add(Some(b"other"));
+    assert_eq!(series.add(Some(b"upload")), a);
+    assert_eq!(series.add(None), 0);
+    assert_eq!(series.data.len(), 11);
+
+    let f = FileCompressor::default();
+    let writer = f.write_header(vec![]).unwrap();
+    let (size, writer) = series.write(&f, &[a, b, a], writer, &EncodeOptions::default()).unwrap();
+
+    let (f, reader) = FileDecompressor::new(Input::new(writer.as_slice())).unwrap();
+    let mut slice = vec![0; 3];
+    let (series2, _reader) = DataSeries::read(&f, &mut slice, reader, size, ColumnInfo::default()).unwrap();
+    assert_eq!(slice, [a, b, a]);
+    assert_eq!(series2.get(slice[2]), Some(Some(&b"upload"[..])));
+
+    let mut series2 = series2;
+    assert_eq!(series2.add(Some(b"other")), b);
+    assert_eq!(series2.data.len(), 11);
+}
+
 #[derive(Clone)]
 pub struct HashStringsOpt {
     set: StringInterner<StringBackend, BuildHasher>
@@ -310,8 +414,8 @@ impl DataBuilder for HashStringsOpt {
             }
         }
     }
-    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size) -> Result<(Self, Input<'r>), Error> {
-        let (set, reader) = read_string_set(f, slice, reader, size)?;
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let (set, reader) = read_string_set(f, slice, reader, size, col)?;
         Ok((HashStringsOpt { set }, reader))
     }
     fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>> {
@@ -324,30 +428,320 @@ impl DataBuilder for HashStringsOpt {
 #[cfg(feature="encode")]
 impl DataBuilderEncode for HashStringsOpt {
     #[cfg(feature="encode")]
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &Options) -> Result<(Self::Size, W), Error> {
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
         write_string_set(&self.set, f, &slice, writer, opt)
     }
 }
 
-fn copy_to(reader: &mut impl BetterBufRead, mut out: &mut [u8]) -> Result<(), Error> {
-    while out.len() > 0 {
-        let max = out.len().min(reader.capacity().unwrap_or(usize::MAX));
-        let buf = reader.fill_or_eof(max)?;
+/// Strings stored sorted and front-coded, so prefix and range queries are binary searches.
+/// Rows keep their symbol ids, the block stores the permutation from sorted order to symbols.
+#[derive(Clone)]
+pub struct SortedStrings {
+    set: StringInterner<StringBackend, BuildHasher>,
+    /// symbols in the order of their strings. cleared when a string is added, sorted again when needed.
+    sorted: OnceLock<Vec<u32>>,
+}
+impl Default for SortedStrings {
+    fn default() -> Self {
+        SortedStrings { set: StringInterner::with_hasher(BuildHasher::default()), sorted: OnceLock::new() }
+    }
+}
+impl From<HashStrings> for SortedStrings {
+    fn from(h: HashStrings) -> Self {
+        SortedStrings { set: h.set, sorted: OnceLock::new() }
+    }
+}
 
-        let n = buf.len().min(out.len());
-        let (head, tail) = out.split_at_mut(n);
-        head.copy_from_slice(&buf[..n]);
-        out = tail;
+/*
+Encode as
+ front-coded strings in sorted order: (shared prefix len, suffix len, suffix)
+ symbol[n_strings] in sorted order
+ symbol[n_rows]
+*/
 
-        reader.consume(n);
+impl SortedStrings {
+    fn resolve(&self, sym: u32) -> &str {
+        self.set.resolve(SymbolU32::try_from_usize(sym as usize).unwrap()).unwrap()
+    }
+    fn sorted(&self) -> &[u32] {
+        self.sorted.get_or_init(|| {
+            let mut sorted: Vec<u32> = (0 .. self.set.len() as u32).collect();
+            sorted.sort_unstable_by_key(|&sym| self.resolve(sym));
+            sorted
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+    /// symbol of `s`, if it is in the dictionary
+    pub fn symbol(&self, s: &str) -> Option<u32> {
+        Some(self.set.get(s)?.to_usize() as u32)
+    }
+    /// symbols of all strings starting with `prefix`, in sorted order
+    pub fn prefix(&self, prefix: &str) -> Vec<u32> {
+        let sorted = self.sorted();
+        let start = sorted.partition_point(|&sym| self.resolve(sym) < prefix);
+        let len = sorted[start..].partition_point(|&sym| self.resolve(sym).starts_with(prefix));
+        sorted[start .. start + len].to_vec()
+    }
+    /// symbols of all strings within `range`, in sorted order
+    pub fn range<'s>(&self, range: impl RangeBounds<&'s str>) -> Vec<u32> {
+        let sorted = self.sorted();
+        let start = match range.start_bound() {
+            Bound::Included(a) => sorted.partition_point(|&sym| self.resolve(sym) < *a),
+            Bound::Excluded(a) => sorted.partition_point(|&sym| self.resolve(sym) <= *a),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(b) => sorted.partition_point(|&sym| self.resolve(sym) <= *b),
+            Bound::Excluded(b) => sorted.partition_point(|&sym| self.resolve(sym) < *b),
+            Bound::Unbounded => sorted.len(),
+        };
+        sorted.get(start .. end).unwrap_or_default().to_vec()
+    }
+    /// all symbols with their strings, in symbol order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.set.iter().map(|(sym, s)| (sym.to_usize() as u32, s))
+    }
+}
+impl DataBuilder for SortedStrings {
+    type CompressedItem = u32;
+    type Item<'a> = &'a str;
+    type Slice<'a> = &'a [u32];
+    type SliceMut<'a> = &'a mut [u32];
+    type Size = u32;
+    type Data = Tuple1<u32>;
+
+    fn add<'a>(&mut self, item: Self::Item<'a>) -> Self::CompressedItem {
+        let len = self.set.len();
+        let sym = self.set.get_or_intern(item);
+        if self.set.len() != len {
+            self.sorted.take();
+        }
+        sym.to_usize() as u32
+    }
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let (data, reader) = decompress_data(reader, size as usize, col.codec)?;
+
+        let mut strings = vec![];
+        let mut current: Vec<u8> = vec![];
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            let ((shared, suffix_len), r) = postcard::take_from_bytes::<(u32, u32)>(rest)?;
+            let (suffix, r) = r.split_at_checked(suffix_len as usize).context("front-coded string")?;
+            current.truncate(shared as usize);
+            current.extend_from_slice(suffix);
+            strings.push(String::from_utf8(current.clone())?);
+            rest = r;
+        }
+
+        let mut sorted = vec![0; strings.len()];
+        let reader = decompress_slice(f, reader, &mut sorted, col.codec)?;
+        let reader = decompress_slice(f, reader, slice, col.codec)?;
+
+        // rank of each symbol
+        let mut ranks = vec![u32::MAX; strings.len()];
+        for (rank, &sym) in sorted.iter().enumerate() {
+            let r = ranks.get_mut(sym as usize).context("symbol out of range")?;
+            anyhow::ensure!(*r == u32::MAX, "duplicate symbol");
+            *r = rank as u32;
+        }
+        let mut set = StringInterner::with_capacity_and_hasher(strings.len(), BuildHasher::default());
+        set.extend(ranks.iter().map(|&rank| strings[rank as usize].as_str()));
+
+        Ok((SortedStrings { set, sorted: sorted.into() }, reader))
+    }
+    fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>> {
+        self.set.resolve(SymbolU32::try_from_usize(compressed as usize)?)
+    }
+}
+#[cfg(feature="encode")]
+impl DataBuilderEncode for SortedStrings {
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, mut writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
+        let sorted = self.sorted();
+
+        let mut data = vec![];
+        let mut prev = "";
+        for &sym in sorted.iter() {
+            let s = self.resolve(sym);
+            let shared = prev.bytes().zip(s.bytes()).take_while(|(a, b)| a == b).count();
+            let suffix = &s.as_bytes()[shared..];
+            data = postcard::to_extend(&(shared as u32, suffix.len() as u32), data)?;
+            data.extend_from_slice(suffix);
+            prev = s;
+        }
+        let len = compress_data(&mut writer, &data, opt)?;
+
+        let writer = compress_slice(f, writer, sorted, DeltaSpec::NoOp, opt)?;
+        let writer = compress_slice(f, writer, slice, DeltaSpec::NoOp, opt)?;
+        Ok((len as u32, writer))
     }
-    Ok(())
 }
 
+/// `T` as written before string sets were length-prefixed, when strings were separated by newlines.
+/// Only for reading old blocks, usually as the old type of an `Upgrade`.
+#[derive(Clone, Default)]
+pub struct Legacy<T>(pub T);
+impl<T: DataBuilder> DataBuilder for Legacy<T> {
+    type CompressedItem = T::CompressedItem;
+    type Item<'a> = T::Item<'a>;
+    type Slice<'a> = T::Slice<'a>;
+    type SliceMut<'a> = T::SliceMut<'a>;
+    type Size = T::Size;
+    type Data = T::Data;
+
+    fn add<'a>(&mut self, item: Self::Item<'a>) -> Self::CompressedItem {
+        self.0.add(item)
+    }
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let (inner, reader) = T::read(f, slice, reader, size, ColumnInfo { newline_strings: true, ..col })?;
+        Ok((Legacy(inner), reader))
+    }
+    fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>> {
+        self.0.get(compressed)
+    }
+}
+#[cfg(feature="encode")]
+impl<T: DataBuilderEncode> DataBuilderEncode for Legacy<T> {
+    fn write<'a, W: io::Write + Pos>(&self, _f: &FileCompressor, _slice: Self::Slice<'a>, _writer: W, _opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
+        anyhow::bail!("legacy columns can't be written")
+    }
+}
+impl From<Legacy<HashStrings>> for HashStrings {
+    fn from(l: Legacy<HashStrings>) -> Self {
+        l.0
+    }
+}
+impl From<Legacy<HashStringsOpt>> for HashStringsOpt {
+    fn from(l: Legacy<HashStringsOpt>) -> Self {
+        l.0
+    }
+}
+impl From<Legacy<StringMap>> for StringMap {
+    fn from(l: Legacy<StringMap>) -> Self {
+        l.0
+    }
+}
+impl From<Legacy<HashStrings>> for SortedStrings {
+    fn from(l: Legacy<HashStrings>) -> Self {
+        l.0.into()
+    }
+}
+
+/// Column stored as `New` from shema version `V` on, and as `Old` before.
+/// Blocks with the old format are converted when read.
+#[derive(Clone, Default)]
+pub struct Upgrade<Old, New, const V: u32> {
+    pub new: New,
+    _m: PhantomData<Old>,
+}
+impl<Old, New, const V: u32> Deref for Upgrade<Old, New, V> {
+    type Target = New;
+    fn deref(&self) -> &New {
+        &self.new
+    }
+}
+impl<Old, New, const V: u32> DataBuilder for Upgrade<Old, New, V>
+where
+    New: DataBuilder + From<Old>,
+    Old: Default + for<'a> DataBuilder<Size = New::Size, SliceMut<'a> = New::SliceMut<'a>>,
+{
+    type CompressedItem = New::CompressedItem;
+    type Item<'a> = New::Item<'a>;
+    type Slice<'a> = New::Slice<'a>;
+    type SliceMut<'a> = New::SliceMut<'a>;
+    type Size = New::Size;
+    type Data = New::Data;
+
+    fn add<'a>(&mut self, item: Self::Item<'a>) -> Self::CompressedItem {
+        self.new.add(item)
+    }
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let (new, reader) = if col.version >= V {
+            New::read(f, slice, reader, size, col)?
+        } else {
+            let (old, reader) = Old::read(f, slice, reader, size, col)?;
+            (old.into(), reader)
+        };
+        Ok((Upgrade { new, _m: PhantomData }, reader))
+    }
+    fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>> {
+        self.new.get(compressed)
+    }
+}
+#[cfg(feature="encode")]
+impl<Old, New, const V: u32> DataBuilderEncode for Upgrade<Old, New, V>
+where
+    New: DataBuilderEncode + From<Old>,
+    Old: Default + for<'a> DataBuilder<Size = New::Size, SliceMut<'a> = New::SliceMut<'a>>,
+{
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
+        self.new.write(f, slice, writer, opt)
+    }
+}
+
+/// A set of fixed size values, indexed in insertion order.
+/// Tables read from a block reference the block data until something is added.
+#[derive(Clone)]
+enum Table<T> {
+    Set(IndexSet<T, BuildHasher>),
+    Frozen(Bytes),
+}
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Table::Set(IndexSet::with_hasher(BuildHasher::default()))
+    }
+}
+impl<T: Pod + Hash + Eq> Table<T> {
+    fn read(reader: &mut Input, len: usize) -> Result<Self, Error> {
+        let data = reader.take_bytes(len * size_of::<T>())?;
+        Ok(Table::Frozen(data))
+    }
+    fn write<W: io::Write>(&self, writer: &mut W) -> Result<(), Error> {
+        match self {
+            Table::Set(set) => {
+                for val in set.iter() {
+                    writer.write_all(bytemuck::bytes_of(val))?;
+                }
+            }
+            Table::Frozen(data) => writer.write_all(data)?,
+        }
+        Ok(())
+    }
+    fn insert_full(&mut self, val: T) -> usize {
+        if let Table::Frozen(data) = self {
+            let set = data.chunks_exact(size_of::<T>()).map(bytemuck::pod_read_unaligned).collect();
+            *self = Table::Set(set);
+        }
+        match self {
+            Table::Set(set) => set.insert_full(val).0,
+            Table::Frozen(_) => unreachable!(),
+        }
+    }
+    fn get_index(&self, idx: usize) -> Option<T> {
+        match self {
+            Table::Set(set) => set.get_index(idx).copied(),
+            Table::Frozen(data) => {
+                let size = size_of::<T>();
+                let bytes = data.get(idx * size .. (idx + 1) * size)?;
+                Some(bytemuck::pod_read_unaligned(bytes))
+            }
+        }
+    }
+    fn len(&self) -> usize {
+        match self {
+            Table::Set(set) => set.len(),
+            Table::Frozen(data) => data.len() / size_of::<T>(),
+        }
+    }
+}
 
 #[derive(Default, Clone)]
 pub struct HashIpv6 {
-    prefixes: IndexSet<[u32; 3], BuildHasher>,
+    prefixes: Table<[u32; 3]>,
 }
 impl DataBuilder for HashIpv6 {
     type Item<'a> = Ipv6Addr;
@@ -365,19 +759,14 @@ impl DataBuilder for HashIpv6 {
             (bits >> 32) as u32
         ];
         let suffix = bits as u32;
-        let (prefix_idx, _) = self.prefixes.insert_full(prefix);
+        let prefix_idx = self.prefixes.insert_full(prefix);
         (prefix_idx as u32, suffix)
     }
-    fn read<'a, 'r>(f: &FileDecompressor, (prefixes, suffixes): Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size) -> Result<(Self, Input<'r>), Error> {
-        let reader = decompress_slice(f, reader, prefixes)?;
-        let mut reader = decompress_slice(f, reader, suffixes)?;
+    fn read<'a, 'r>(f: &FileDecompressor, (prefixes, suffixes): Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let reader = decompress_slice(f, reader, prefixes, col.codec)?;
+        let mut reader = decompress_slice(f, reader, suffixes, col.codec)?;
 
-        let mut prefixes = IndexSet::with_capacity_and_hasher(size as usize, BuildHasher::default());
-        for _ in 0 .. size {
-            let mut val = [0; 3];
-            copy_to(&mut reader, bytes_of_mut(&mut val))?;
-            prefixes.insert(val);
-        }
+        let prefixes = Table::read(&mut reader, size as usize)?;
 
         Ok((HashIpv6 { prefixes }, reader))
     }
@@ -389,22 +778,20 @@ impl DataBuilder for HashIpv6 {
 }
 #[cfg(feature="encode")]
 impl DataBuilderEncode for HashIpv6 {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, (prefixes, suffixes): Self::Slice<'a>, writer: W, _opt: &Options) -> Result<(Self::Size, W), Error> {
-        let writer = compress_slice(f, writer, prefixes, DeltaSpec::TryLookback)?;
-        let mut writer = compress_slice(f, writer, suffixes, DeltaSpec::TryLookback)?;
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, (prefixes, suffixes): Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
+        let writer = compress_slice(f, writer, prefixes, DeltaSpec::TryLookback, opt)?;
+        let mut writer = compress_slice(f, writer, suffixes, DeltaSpec::TryLookback, opt)?;
 
-        for i in self.prefixes.iter() {
-            writer.write_all(bytemuck::bytes_of(i))?;
-        }
+        self.prefixes.write(&mut writer)?;
         Ok((self.prefixes.len() as u32, writer))
     }
 }
 
 #[derive(Default, Clone)]
 pub struct HashArray<const N: usize> {
-    values: IndexSet<[u8; N], BuildHasher>,
+    values: Table<[u8; N]>,
 }
-impl<const N: usize> DataBuilder for HashArray<N> {
+impl<const N: usize> DataBuilder for HashArray<N> where [u8; N]: Pod {
     type Item<'a> = [u8; N];
     type CompressedItem = u32;
     type Slice<'a> = &'a [u32];
@@ -413,34 +800,26 @@ impl<const N: usize> DataBuilder for HashArray<N> {
     type Data = Tuple1<u32>;
 
     fn add<'a>(&mut self, item: Self::Item<'a>) -> Self::CompressedItem {
-        let (idx, _) = self.values.insert_full(item);
-        idx as u32
+        self.values.insert_full(item) as u32
     }
-    fn read<'a, 'r>(f: &FileDecompressor, idxs: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size) -> Result<(Self, Input<'r>), Error> {
-        let mut reader = decompress_slice(f, reader, idxs)?;
+    fn read<'a, 'r>(f: &FileDecompressor, idxs: Self::SliceMut<'a>, reader: Input<'r>, size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let mut reader = decompress_slice(f, reader, idxs, col.codec)?;
 
-        let mut values = IndexSet::with_capacity_and_hasher(size as usize, BuildHasher::default());
-        for _ in 0 .. size {
-            let mut val = [0; N];
-            copy_to(&mut reader, &mut val)?;
-            values.insert(val);
-        }
+        let values = Table::read(&mut reader, size as usize)?;
 
         Ok((HashArray { values }, reader))
     }
     fn get<'a>(&'a self, idx: Self::CompressedItem) -> Option<Self::Item<'a>> {
-        let value = self.values.get_index(idx as usize).cloned().unwrap_or([0; N]);
+        let value = self.values.get_index(idx as usize).unwrap_or([0; N]);
         Some(value)
     }
 }
 #[cfg(feature="encode")]
-impl<const N: usize> DataBuilderEncode for HashArray<N> {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, idxs: Self::Slice<'a>, writer: W, _opt: &Options) -> Result<(Self::Size, W), Error> {
-        let mut writer = compress_slice(f, writer, idxs, DeltaSpec::TryLookback)?;
+impl<const N: usize> DataBuilderEncode for HashArray<N> where [u8; N]: Pod {
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, idxs: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
+        let mut writer = compress_slice(f, writer, idxs, DeltaSpec::TryLookback, opt)?;
 
-        for i in self.values.iter() {
-            writer.write_all(&*i)?;
-        }
+        self.values.write(&mut writer)?;
         Ok((self.values.len() as u32, writer))
     }
 }
@@ -454,7 +833,7 @@ impl<N> Default for NumberSeries<N> {
         NumberSeries { _m: PhantomData }
     }
 }
-impl<N: Number> DataBuilder for NumberSeries<N> {
+impl<N: Number + Pod> DataBuilder for NumberSeries<N> {
     type Item<'a> = N;
     type CompressedItem = N;
     type Slice<'a> = &'a [N];
@@ -465,8 +844,8 @@ impl<N: Number> DataBuilder for NumberSeries<N> {
     fn add<'a>(&mut self, item: Self::Item<'a>) -> Self::CompressedItem {
         item
     }
-    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, _size: Self::Size) -> Result<(Self, Input<'r>), Error> {
-        let reader = decompress_slice(f, reader, slice)?;
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, reader: Input<'r>, _size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
+        let reader = decompress_slice(f, reader, slice, col.codec)?;
         Ok((NumberSeries { _m: PhantomData }, reader))
     }
     fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>> {
@@ -474,9 +853,9 @@ impl<N: Number> DataBuilder for NumberSeries<N> {
     }
 }
 #[cfg(feature="encode")]
-impl<N: Number> DataBuilderEncode for NumberSeries<N> {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, _opt: &Options) -> Result<(Self::Size, W), Error> {
-        let writer = compress_slice(f, writer, slice, DeltaSpec::Auto)?;
+impl<N: Number + Pod> DataBuilderEncode for NumberSeries<N> {
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
+        let writer = compress_slice(f, writer, slice, DeltaSpec::Auto, opt)?;
         Ok(((), writer))
     }
 }
@@ -499,92 +878,254 @@ impl DataBuilder for TimeSeries {
         }
         item.wrapping_sub(self.offset) as u32
     }
-    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, mut reader: Input<'r>, _size: Self::Size) -> Result<(Self, Input<'r>), Error> {
+    fn read<'a, 'r>(f: &FileDecompressor, slice: Self::SliceMut<'a>, mut reader: Input<'r>, _size: Self::Size, col: ColumnInfo) -> Result<(Self, Input<'r>), Error> {
         let mut offset = 0;
         let dest_bytes = bytes_of_mut(&mut offset);
         let bytes = reader.take_n(dest_bytes.len())?;
         dest_bytes.copy_from_slice(bytes);
-        let reader = decompress_slice(f, reader, slice)?;
+        let reader = decompress_slice(f, reader, slice, col.codec)?;
         Ok((TimeSeries { offset}, reader))
     }
     fn get<'a>(&'a self, compressed: Self::CompressedItem) -> Option<Self::Item<'a>> {
-        Some(self.offset.wrapping_add(compressed as u64))
+        // rows older than the first one are stored as negative offsets
+        Some(self.offset.wrapping_add(compressed as i32 as u64))
     }
 }
 
 #[cfg(feature="encode")]
 impl DataBuilderEncode for TimeSeries {
-    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, mut writer: W, _opt: &Options) -> Result<(Self::Size, W), Error> {
+    fn write<'a, W: io::Write + Pos>(&self, f: &FileCompressor, slice: Self::Slice<'a>, mut writer: W, opt: &EncodeOptions) -> Result<(Self::Size, W), Error> {
         writer.write_all(bytemuck::bytes_of(&self.offset))?;
-        let writer = compress_slice(f, writer, slice, DeltaSpec::TryConsecutive(1))?;
+        let writer = compress_slice(f, writer, slice, DeltaSpec::TryConsecutive(1), opt)?;
         Ok(((), writer))
     }
 }
 
 #[cfg(feature="encode")]
-pub fn compress_string<W: io::Write + Pos>(writer: &mut W, strings: &str, opt: &Options) -> Result<usize, Error> {
-    compress_data(writer, strings.as_bytes(), opt)
+#[test]
+fn test_timeseries_older_rows() {
+    let f = FileCompressor::default();
+    let writer = f.write_header(vec![]).unwrap();
+
+    let mut series = TimeSeries::default();
+    let times = [1_700_000_100, 1_700_000_000, 1_700_000_200];
+    let slice: Vec<u32> = times.iter().map(|&t| series.add(t)).collect();
+    let (size, writer) = series.write(&f, &slice, writer, &EncodeOptions::default()).unwrap();
+
+    let reader = Input::new(writer.as_slice());
+    let (f, reader) = FileDecompressor::new(reader).unwrap();
+    let mut slice2 = vec![0; times.len()];
+    let (series2, _reader) = TimeSeries::read(&f, &mut slice2, reader, size, ColumnInfo::default()).unwrap();
+
+    for (&t, &c) in times.iter().zip(&slice2) {
+        assert_eq!(series2.get(c), Some(t));
+    }
+}
+
+#[cfg(feature="encode")]
+#[test]
+fn test_sorted_strings() {
+    let uris = ["/api/users", "/", "/api/login", "/static/app.js", "/api/users", "/apiary"];
+
+    let mut dict = SortedStrings::default();
+    let slice: Vec<u32> = uris.iter().map(|u| dict.add(u)).collect();
+    let f = FileCompressor::default();
+    let (size, writer) = dict.write(&f, &slice, f.write_header(vec![]).unwrap(), &EncodeOptions::default()).unwrap();
+
+    let reader = Input::new(&writer);
+    let (f, reader) = FileDecompressor::new(reader).unwrap();
+    let mut slice2 = vec![0; slice.len()];
+    let (dict2, _) = SortedStrings::read(&f, &mut slice2, reader, size, ColumnInfo::default()).unwrap();
+    assert_eq!(slice, slice2);
+    for (&u, &c) in uris.iter().zip(&slice2) {
+        assert_eq!(dict2.get(c), Some(u));
+    }
+
+    let strings = |syms: Vec<u32>| syms.into_iter().map(|s| dict2.get(s).unwrap()).collect::<Vec<_>>();
+    assert_eq!(strings(dict2.prefix("/api/")), ["/api/login", "/api/users"]);
+    assert_eq!(strings(dict2.prefix("/api")), ["/api/login", "/api/users", "/apiary"]);
+    assert_eq!(strings(dict2.range("/api/z" .. "/static")), ["/apiary"]);
+    assert_eq!(strings(dict2.range(..="/")), ["/"]);
+    assert!(dict2.prefix("/x").is_empty());
+
+    // the order is sorted again after strings were added
+    assert_eq!(dict.prefix("/api/").len(), 2);
+    dict.add("/api/admin");
+    let syms = dict.prefix("/api/");
+    assert_eq!(syms.iter().map(|&s| dict.get(s).unwrap()).collect::<Vec<_>>(), ["/api/admin", "/api/login", "/api/users"]);
+
+    // blocks from before the upgrade
+    let mut old = HashStrings::default();
+    let slice: Vec<u32> = uris.iter().map(|u| old.add(u)).collect();
+    let f = FileCompressor::default();
+    let (size, writer) = old.write(&f, &slice, f.write_header(vec![]).unwrap(), &EncodeOptions::default()).unwrap();
+    let (f, reader) = FileDecompressor::new(Input::new(&writer)).unwrap();
+    let col = ColumnInfo { version: 1, ..Default::default() };
+    let (up, _) = Upgrade::<HashStrings, SortedStrings, 2>::read(&f, &mut slice2, reader, size, col).unwrap();
+    assert_eq!(up.get(slice2[3]), Some("/static/app.js"));
+    assert_eq!(up.prefix("/static").len(), 1);
 }
+
 #[cfg(feature="encode")]
-pub fn compress_data<W: io::Write + Pos>(writer: &mut W, mut data: &[u8], opt: &Options) -> Result<usize, Error> {
-    use brotli::{enc::BrotliEncoderParams, BrotliCompress};
+#[test]
+fn test_string_sets() {
+    let uris = ["/a\nHost: evil", "/b", "", "/c\n"];
+    let mut strings = HashStrings::default();
+    let slice: Vec<u32> = uris.iter().map(|u| strings.add(u)).collect();
+    let f = FileCompressor::default();
+    let (size, writer) = strings.write(&f, &slice, f.write_header(vec![]).unwrap(), &EncodeOptions::default()).unwrap();
+    let (f, reader) = FileDecompressor::new(Input::new(&writer)).unwrap();
+    let mut slice2 = vec![0; slice.len()];
+    let (strings2, _) = HashStrings::read(&f, &mut slice2, reader, size, ColumnInfo::default()).unwrap();
+    for (&u, &c) in uris.iter().zip(&slice2) {
+        assert_eq!(strings2.get(c), Some(u));
+    }
 
-    // println!("write Brotli strings at {}", writer.pos());
+    // blocks from before length prefixes
+    let f = FileCompressor::default();
+    let mut writer = f.write_header(vec![]).unwrap();
+    let size = compress_string(&mut writer, "/x\n/y", &EncodeOptions::default()).unwrap() as u32;
+    let writer = compress_slice(&f, writer, &[1u32, 0], DeltaSpec::NoOp, &EncodeOptions::default()).unwrap();
+    let (f, reader) = FileDecompressor::new(Input::new(&writer)).unwrap();
+    let mut slice2 = vec![0; 2];
+    let (old, _) = Legacy::<HashStrings>::read(&f, &mut slice2, reader, size, ColumnInfo::default()).unwrap();
+    assert_eq!(old.get(slice2[0]), Some("/y"));
+    assert_eq!(old.get(slice2[1]), Some("/x"));
+}
+
+#[cfg(feature="encode")]
+#[test]
+fn test_hash_ipv6_borrowed() {
+    let f = FileCompressor::default();
+    let writer = f.write_header(vec![]).unwrap();
 
-    let mut params = BrotliEncoderParams::default();
-    params.quality = opt.brotli_level as i32;
+    let mut ips = HashIpv6::default();
+    let addrs: Vec<Ipv6Addr> = ["::ffff:10.0.0.1", "2001:db8::1", "::ffff:10.0.0.2"].iter().map(|s| s.parse().unwrap()).collect();
+    let (prefixes, suffixes): (Vec<u32>, Vec<u32>) = addrs.iter().map(|&ip| ips.add(ip)).unzip();
+    let (size, writer) = ips.write(&f, (&prefixes, &suffixes), writer, &EncodeOptions::default()).unwrap();
+
+    let data = Bytes::from(writer);
+    let reader = Input::from_bytes(&data);
+    let (f, reader) = FileDecompressor::new(reader).unwrap();
+    let (mut p2, mut s2) = (vec![0; addrs.len()], vec![0; addrs.len()]);
+    let (mut ips2, _reader) = HashIpv6::read(&f, (&mut p2, &mut s2), reader, size, ColumnInfo::default()).unwrap();
+    assert!(matches!(ips2.prefixes, Table::Frozen(ref b) if data.as_ptr_range().contains(&b.as_ptr())));
+
+    for (&ip, c) in addrs.iter().zip(p2.into_iter().zip(s2)) {
+        assert_eq!(ips2.get(c), Some(ip));
+    }
+    let c = ips2.add("2001:db8::2".parse().unwrap());
+    assert_eq!(c.0, 1);
+    assert_eq!(ips2.prefixes.len(), 2);
+}
 
-    let written = BrotliCompress(
-        &mut data,
-        writer,
-        &params
-    ).unwrap();
-    Ok(written)
+#[cfg(feature="encode")]
+pub fn compress_string<W: io::Write + Pos>(writer: &mut W, strings: &str, opt: &Options) -> Result<usize, Error> {
+    compress_data(writer, strings.as_bytes(), opt)
+}
+#[cfg(feature="encode")]
+pub fn compress_data<W: io::Write + Pos>(writer: &mut W, data: &[u8], opt: &Options) -> Result<usize, Error> {
+    let codec = opt.codec.unwrap_or_default();
+    compress_bytes(codec, opt.level.unwrap_or(opt.brotli_level), data, writer)
 }
 
-fn decompress_string(reader: Input, len: usize) -> Result<(String, Input), Error> {
-    let (buffer, rest) = decompress_data(reader, len)?;
+fn decompress_string(reader: Input, len: usize, codec: Codec) -> Result<(String, Input), Error> {
+    let (buffer, rest) = decompress_data(reader, len, codec)?;
     let buffer = String::from_utf8(buffer)?;
     Ok((buffer, rest))
 }
-fn decompress_data(mut reader:Input, len: usize) -> Result<(Vec<u8>, Input), Error> {
-    use brotli_decompressor::BrotliDecompress;
-
-    // println!("read Brotli strings at {}", reader.pos());
-
-    let mut input = reader.take_n(len)?;
-    let mut buffer: Vec<u8> = vec![];
-    BrotliDecompress(
-        &mut input,
-        &mut buffer,
-    )?;
+fn decompress_data(mut reader:Input, len: usize, codec: Codec) -> Result<(Vec<u8>, Input), Error> {
+    let input = reader.take_n(len)?;
+    let buffer = decompress_bytes(codec, input)?;
     Ok((buffer, reader))
 }
 
-fn compress_slice<'a, T: Number, W: io::Write + Pos>(f: &FileCompressor, writer: W, slice: &'a [T], delta_spec: DeltaSpec) -> Result<W, Error> {
+#[cfg(feature="encode")]
+fn compress_slice<'a, T: Number + Pod, W: io::Write + Pos>(f: &FileCompressor, mut writer: W, slice: &'a [T], delta_spec: DeltaSpec, opt: &EncodeOptions) -> Result<W, Error> {
     // println!("write [{}] at {}", type_name::<T>(), writer.pos());
     if slice.len() == 0 {
         return Ok(writer);
     }
 
+    let codec = opt.codec.unwrap_or_default();
+    if codec != Codec::Pco {
+        // other codecs don't know about numbers and need the compressed length up front
+        let mut data = vec![];
+        compress_bytes(codec, opt.level.unwrap_or(opt.brotli_level), bytemuck::cast_slice(slice), &mut data)?;
+        writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        writer.write_all(&data)?;
+        opt.record(|column| ChunkStats { column, codec, specs: None, len: slice.len(), bytes: data.len() + 4 });
+        return Ok(writer);
+    }
+
+    let level = opt.level.map_or(8, |l| l.min(12) as usize);
+    let (delta_spec, mode_spec) = match opt.auto_specs {
+        Some(_) => choose_specs(f, slice, delta_spec, level, opt)?,
+        None => (delta_spec, ModeSpec::Classic),
+    };
     let config = ChunkConfig::default()
-        .with_compression_level(8)
-        .with_delta_spec(delta_spec)
-        .with_mode_spec(pco::ModeSpec::Classic)
+        .with_compression_level(level)
+        .with_delta_spec(delta_spec.clone())
+        .with_mode_spec(mode_spec.clone())
         .with_paging_spec(pco::PagingSpec::EqualPagesUpTo(slice.len()));
 
+    let start = writer.pos();
     let mut time = f.chunk_compressor(slice, &config)?;
     let writer = time.write_meta(writer)?;
     let writer = time.write_page(0, writer)?;
+    opt.record(|column| ChunkStats { column, codec, specs: Some((delta_spec, mode_spec)), len: slice.len(), bytes: writer.pos() - start });
     Ok(writer)
 }
 
-fn decompress_slice<'a, 'r, T: Number>(f: &FileDecompressor, reader: Input<'r>, slice: &'a mut [T]) -> Result<Input<'r>, Error> {
+/// number of values the specs are tried on
+#[cfg(feature="encode")]
+const SPEC_SAMPLE_LEN: usize = 4096;
+
+/// compress a sample of `slice` with different specs and return the ones giving the smallest output.
+/// `default` is tried first, the remaining candidates only while there is time left.
+#[cfg(feature="encode")]
+fn choose_specs<T: Number>(f: &FileCompressor, slice: &[T], default: DeltaSpec, level: usize, opt: &EncodeOptions) -> Result<(DeltaSpec, ModeSpec), Error> {
+    let sample = &slice[.. slice.len().min(SPEC_SAMPLE_LEN)];
+    let deltas = [default, DeltaSpec::NoOp, DeltaSpec::TryConsecutive(1), DeltaSpec::TryLookback, DeltaSpec::Auto];
+
+    let mut best: Option<(usize, DeltaSpec, ModeSpec)> = None;
+    for delta in deltas {
+        for mode in [ModeSpec::Classic, ModeSpec::Auto] {
+            if best.is_some() && opt.out_of_time() {
+                break;
+            }
+            let config = ChunkConfig::default()
+                .with_compression_level(level)
+                .with_delta_spec(delta.clone())
+                .with_mode_spec(mode.clone())
+                .with_paging_spec(pco::PagingSpec::EqualPagesUpTo(sample.len()));
+
+            let mut c = f.chunk_compressor(sample, &config)?;
+            let out = c.write_page(0, c.write_meta(vec![])?)?;
+            if best.as_ref().is_none_or(|&(size, _, _)| out.len() < size) {
+                best = Some((out.len(), delta.clone(), mode));
+            }
+        }
+    }
+    let (_, delta, mode) = best.unwrap();
+    Ok((delta, mode))
+}
+
+fn decompress_slice<'a, 'r, T: Number + Pod>(f: &FileDecompressor, mut reader: Input<'r>, slice: &'a mut [T], codec: Codec) -> Result<Input<'r>, Error> {
     // println!("read [{}; {}] at {}", std::any::type_name::<T>(), slice.len(), reader.pos());
     if slice.len() == 0 {
         return Ok(reader);
     }
 
+    if codec != Codec::Pco {
+        let len = u32::from_le_bytes(reader.take_n(4)?.try_into().unwrap());
+        let data = decompress_bytes(codec, reader.take_n(len as usize)?)?;
+        let dest: &mut [u8] = bytemuck::cast_slice_mut(slice);
+        anyhow::ensure!(data.len() == dest.len(), "column has {} bytes, expected {}", data.len(), dest.len());
+        dest.copy_from_slice(&data);
+        return Ok(reader);
+    }
+
     let (mut decompressor, reader) = f.chunk_decompressor(reader).context("chunk header")?;
     let mut page = decompressor.page_decompressor(reader, slice.len()).context("page")?;
     let progress = page.read(slice).context("decompress")?;
@@ -0,0 +1,62 @@
+use clog_core::{
+    shema::Shema,
+    types::{HashStrings, NumberSeries, TimeSeries},
+    Options,
+};
+
+const EVENT_V2: u32 = 2;
+
+#[allow(unused)]
+#[derive(Shema)]
+#[clog(version = EVENT_V2)]
+pub struct Event {
+    time: TimeSeries,
+    #[clog(codec = "lz4")]
+    level: NumberSeries<u16>,
+    #[clog(codec = "zstd")]
+    message: HashStrings,
+    #[clog(min_version = EVENT_V2, codec = "brotli", level = 11)]
+    target: HashStrings,
+}
+
+#[test]
+fn custom_shema() {
+    let mut builder = EventBuilder::default();
+    builder.add(EventItem { time: 1_700_000_000, level: 3, message: "started", target: "app" });
+    builder.add(EventItem { time: 1_700_000_005, level: 1, message: "disk full", target: "storage" });
+
+    for opt in [Options::default(), Options::live(), Options::archive()] {
+        let data = builder.to_vec(&opt);
+        let read = EventBuilder::from_slice(&data).unwrap();
+        assert_eq!(read.len(), 2);
+
+        let e = read.get(1).unwrap();
+        assert_eq!(e.time, 1_700_000_005);
+        assert_eq!(e.level, 1);
+        assert_eq!(e.message, "disk full");
+        assert_eq!(e.target, "storage");
+    }
+    assert_eq!(EventBuilder::VERSION, EVENT_V2);
+}
+
+#[test]
+fn auto_specs() {
+    use std::time::Duration;
+
+    let mut builder = EventBuilder::default();
+    for i in 0 .. 10_000u64 {
+        builder.add(EventItem { time: 1_700_000_000 + i / 7, level: [200, 200, 404, 304][i as usize % 4], message: "request", target: "api" });
+    }
+    let opt = Options { auto_specs: Some(Duration::from_secs(10)), ..Default::default() };
+    let (data, stats) = builder.write_to_with_stats(Default::default(), &opt);
+
+    let columns: Vec<_> = stats.chunks.iter().map(|c| c.column).collect();
+    assert_eq!(columns, ["time", "level", "message", "target"]);
+    assert!(stats.chunks.iter().all(|c| c.len == 10_000));
+    assert!(stats.chunks[0].specs.is_some());
+    assert!(stats.chunks[1].specs.is_none(), "level uses lz4");
+
+    let read = EventBuilder::from_slice(&data).unwrap();
+    let e = read.get(9_999).unwrap();
+    assert_eq!((e.time, e.level), (1_700_000_000 + 9_999 / 7, 304));
+}
@@ -94,7 +94,7 @@ fn test_compression() {
     let strings: String = uris.into_iter().collect();
 
     let dict = b"https://artisan-ma.net/img /api/img width? context shop 2000 1000 600 400 www";
-    println!("brotli  5: {}",        test_dict(&strings, &Options { brotli_level: 5, dict: b"" }));
-    println!("brotli  5 + dict: {}", test_dict(&strings, &Options { brotli_level: 5, dict }));
-    println!("brotli 11 + dict: {}", test_dict(&strings, &Options { brotli_level: 11, dict  }));
+    println!("brotli  5: {}",        test_dict(&strings, &Options { brotli_level: 5, dict: b"", ..Default::default() }));
+    println!("brotli  5 + dict: {}", test_dict(&strings, &Options { brotli_level: 5, dict, ..Default::default() }));
+    println!("brotli 11 + dict: {}", test_dict(&strings, &Options { brotli_level: 11, dict, ..Default::default() }));
 }
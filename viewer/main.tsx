@@ -11,13 +11,16 @@ type BatchEntry = {
     referer: string | null,
     ip: string,
     port: number,
-    time: string
+    time: string,
+    ttfb: number,
+    duration: number
 };
 
 function init_ws(update: (c: Client, start: bigint, end: bigint) => void): Client {
     //let ws = new WebSocket("ws://127.0.0.1:8080/api/ws");
     let ws = new WebSocket("ws://127.0.0.1:3000/ws");
     let client = new Client(ws);
+    client.set_partition(new URLSearchParams(location.search).get("partition") ?? undefined);
 
     function handle_range(range: PacketRange) {
         update(client, range.start, range.end);
@@ -73,6 +76,7 @@ function App() {
                 <td>{e.uri}</td>
                 <td>{e.ip}</td>
                 <td>{e.port}</td>
+                <td>{e.duration ? `${(e.duration / 1000).toFixed(1)} ms` : ""}</td>
                 <td>{e.ua}</td>
             </tr>
         }
@@ -154,6 +158,7 @@ function Table(p: JSX.HTMLAttributes<HTMLDivElement> & { list: Accessor<JSXEleme
                 <th>URI</th>
                 <th>IP</th>
                 <th>Port</th>
+                <th>Duration</th>
                 <th>User Agent</th>
             </tr>
         </thead>
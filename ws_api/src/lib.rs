@@ -5,7 +5,8 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
 pub enum ClientMessage {
-    SubScribeWithBacklog { backlog: usize },
+    /// `partition` names one of the `Partitions` of the server, if it has them
+    SubScribeWithBacklog { backlog: usize, partition: Option<String> },
     FetchRange { start: u64, end: u64 },
 }
 
@@ -13,7 +14,9 @@ pub enum ClientMessage {
 pub enum ServerMessage {
     NotAttached,
     Detached,
-    Error { msg: String }
+    Error { msg: String },
+    /// rows `start..end` were deleted by the retention policy
+    RangeUnavailable { start: u64, end: u64 },
 }
 impl ServerMessage {
     pub fn encode(&self) -> Bytes {
@@ -3,10 +3,13 @@ use std::{path::{Path, PathBuf}, sync::Arc, time::Duration};
 use anyhow::Error;
 use axum::{extract::{Request, State, WebSocketUpgrade}, response::IntoResponse, routing::get, Router};
 use clog_collector::{init_log, LogCollector, LogOptions};
-use clog_core::RequestEntry;
+use clog_core::{import::LogFormat, privacy::PrivacyPolicy, RequestEntry};
 use tokio::{spawn, time::sleep, signal};
 use tower_http::services::ServeDir;
 use clog_ws_server::handle_ws;
+#[cfg(unix)]
+use clog_ws_server::ingest::serve_unix;
+use clog_ws_server::ingest::{ingest_router, serve_syslog_udp};
 
 struct App {
     log: LogCollector
@@ -22,9 +25,16 @@ async fn ws_handler(
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
+    let privacy_path = Path::new("privacy.toml");
+    let privacy = match privacy_path.exists() {
+        true => PrivacyPolicy::load(privacy_path)?,
+        false => PrivacyPolicy::default(),
+    };
     let (collector, log_tx) = init_log(LogOptions {
         data_dir: Some(PathBuf::from("blocks")),
-        read_old: true
+        read_old: true,
+        privacy,
+        ..Default::default()
     }).await?;
     let state = Arc::new(App { log: collector.clone() });
     /*
@@ -41,6 +51,22 @@ async fn main() -> Result<(), Error> {
     });
      */
 
+    // ingest is opt-in, as anyone who can reach it can add entries
+    if let Ok(addr) = std::env::var("CLOG_INGEST_ADDR") {
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        let router = ingest_router(log_tx.clone());
+        spawn(async move { axum::serve(listener, router).await });
+    }
+    #[cfg(unix)]
+    if let Ok(path) = std::env::var("CLOG_INGEST_SOCK") {
+        let _ = std::fs::remove_file(&path);
+        spawn(serve_unix(tokio::net::UnixListener::bind(&path)?, log_tx.clone()));
+    }
+    if let Ok(addr) = std::env::var("CLOG_SYSLOG_UDP") {
+        let syslog = tokio::net::UdpSocket::bind(&addr).await?;
+        spawn(serve_syslog_udp(syslog, Arc::new(LogFormat::combined()), log_tx.clone()));
+    }
+
     let routes = Router::new()
         .route("/ws", get(ws_handler))
         .fallback(file_and_error_handler)
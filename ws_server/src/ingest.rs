@@ -0,0 +1,293 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Error, bail};
+use axum::{
+    Router,
+    body::Bytes,
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use clog_core::{RequestEntry, import::LogFormat};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    spawn,
+    sync::mpsc::Sender,
+};
+
+/// largest entry accepted on the unix socket, and largest syslog message
+const MAX_FRAME: usize = 16 << 20;
+/// rejected NDJSON lines reported in the response
+const MAX_ERRORS: usize = 10;
+
+/// `POST /ingest`, taking NDJSON `RequestEntry`s (`application/x-ndjson`)
+/// or a postcard encoded `Vec<RequestEntry>` (`application/postcard`).
+///
+/// The response is sent once all entries were handed to the collector, so a full queue slows down the senders.
+/// It reports `{"accepted": n, "rejected": m, "errors": [..]}`, with status 422 if any NDJSON line was rejected.
+pub fn ingest_router<S: Clone + Send + Sync + 'static>(tx: Sender<RequestEntry>) -> Router<S> {
+    Router::new().route("/ingest", post(move |headers: HeaderMap, body: Bytes| ingest_http(tx.clone(), headers, body)))
+}
+
+async fn ingest_http(tx: Sender<RequestEntry>, headers: HeaderMap, body: Bytes) -> Response {
+    let content_type = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let mut errors = vec![];
+    let mut rejected = 0;
+    let entries = match content_type.split(';').next().unwrap_or("").trim() {
+        "" | "application/x-ndjson" | "application/jsonl" | "application/json" => {
+            let mut entries = vec![];
+            for (n, line) in body.split(|&b| b == b'\n').enumerate() {
+                if line.trim_ascii().is_empty() {
+                    continue;
+                }
+                match serde_json::from_slice::<RequestEntry>(line) {
+                    Ok(e) => entries.push(e),
+                    Err(e) => {
+                        rejected += 1;
+                        if errors.len() < MAX_ERRORS {
+                            errors.push(serde_json::json!({ "line": n + 1, "error": e.to_string() }));
+                        }
+                    }
+                }
+            }
+            entries
+        }
+        "application/postcard" | "application/octet-stream" => match postcard::from_bytes::<Vec<RequestEntry>>(&body) {
+            Ok(entries) => entries,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("invalid batch: {e}")).into_response(),
+        },
+        other => return (StatusCode::UNSUPPORTED_MEDIA_TYPE, format!("unsupported content type {other:?}")).into_response(),
+    };
+
+    let accepted = entries.len();
+    for e in entries {
+        if tx.send(e).await.is_err() {
+            return (StatusCode::SERVICE_UNAVAILABLE, "the collector stopped").into_response();
+        }
+    }
+    let status = match rejected {
+        0 => StatusCode::OK,
+        _ => StatusCode::UNPROCESSABLE_ENTITY,
+    };
+    let body = serde_json::json!({ "accepted": accepted, "rejected": rejected, "errors": errors });
+    (status, [(CONTENT_TYPE, "application/json")], body.to_string()).into_response()
+}
+
+/// Accept connections on a unix socket, each a stream of u32 LE length prefixed, postcard encoded `RequestEntry`s.
+///
+/// Whenever the frames received so far were handed to the collector, the number of entries received on the connection
+/// is sent back as u64 LE. Frames are not read while the collector is busy.
+#[cfg(unix)]
+pub async fn serve_unix(listener: tokio::net::UnixListener, tx: Sender<RequestEntry>) -> Result<(), Error> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let tx = tx.clone();
+        spawn(async move {
+            if let Err(e) = unix_client(stream, tx).await {
+                eprintln!("ingest: {e:#}");
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn unix_client(stream: tokio::net::UnixStream, tx: Sender<RequestEntry>) -> Result<(), Error> {
+    use tokio::io::AsyncWriteExt;
+
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+    let mut frame = vec![];
+    let mut count = 0u64;
+    loop {
+        let len = match reader.read_u32_le().await {
+            Ok(len) => len as usize,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if len > MAX_FRAME {
+            bail!("frame of {len} bytes");
+        }
+        frame.resize(len, 0);
+        reader.read_exact(&mut frame).await?;
+        let e = postcard::from_bytes(&frame).with_context(|| format!("frame {count}"))?;
+        if tx.send(e).await.is_err() {
+            return Ok(());
+        }
+        count += 1;
+        if reader.buffer().is_empty() {
+            write.write_all(&count.to_le_bytes()).await?;
+        }
+    }
+}
+
+/// the MSG of an RFC 5424 syslog message
+pub fn syslog_message(data: &[u8]) -> Result<&[u8], Error> {
+    let rest = data.strip_prefix(b"<").context("missing priority")?;
+    let end = rest.iter().position(|&b| b == b'>').context("missing priority")?;
+    let mut rest = &rest[end + 1..];
+    // VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID
+    for _ in 0..6 {
+        let end = rest.iter().position(|&b| b == b' ').context("truncated header")?;
+        rest = &rest[end + 1..];
+    }
+    // STRUCTURED-DATA
+    match rest.first() {
+        Some(b'-') => rest = &rest[1..],
+        Some(b'[') => {
+            let mut i = 0;
+            let mut in_element = false;
+            while let Some(&b) = rest.get(i) {
+                match b {
+                    b'\\' if in_element => i += 1,
+                    b'[' if !in_element => in_element = true,
+                    b']' if in_element => in_element = false,
+                    _ if !in_element => break,
+                    _ => {}
+                }
+                i += 1;
+            }
+            rest = &rest[i..];
+        }
+        _ => bail!("missing structured data"),
+    }
+    let msg = rest.strip_prefix(b" ").unwrap_or(rest);
+    Ok(msg.strip_prefix(b"\xef\xbb\xbf").unwrap_or(msg))
+}
+
+/// parse and hand over one message, returns false once the collector stopped
+async fn syslog_entry(data: &[u8], format: &LogFormat, tx: &Sender<RequestEntry>) -> bool {
+    let entry = syslog_message(data).and_then(|msg| format.parse(&String::from_utf8_lossy(msg)));
+    match entry {
+        Ok(Some(e)) => tx.send(e).await.is_ok(),
+        Ok(None) => true,
+        Err(e) => {
+            eprintln!("syslog: {e:#}");
+            true
+        }
+    }
+}
+
+/// Receive RFC 5424 syslog messages over UDP, their MSG is read with `format`.
+/// UDP has no acknowledgements, datagrams arriving while the collector is busy may be dropped by the kernel.
+pub async fn serve_syslog_udp(socket: UdpSocket, format: Arc<LogFormat>, tx: Sender<RequestEntry>) -> Result<(), Error> {
+    let mut buf = vec![0; 64 << 10];
+    loop {
+        let (n, _) = socket.recv_from(&mut buf).await?;
+        if !syslog_entry(&buf[..n], &format, &tx).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Receive RFC 5424 syslog messages over TCP, framed by octet counting or newlines (RFC 6587).
+/// The protocol has no acknowledgements, a busy collector stops the reads instead.
+pub async fn serve_syslog_tcp(listener: TcpListener, format: Arc<LogFormat>, tx: Sender<RequestEntry>) -> Result<(), Error> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let (format, tx) = (format.clone(), tx.clone());
+        spawn(async move {
+            if let Err(e) = syslog_tcp_client(stream, &format, tx).await {
+                eprintln!("syslog: {e:#}");
+            }
+        });
+    }
+}
+
+async fn syslog_tcp_client(stream: TcpStream, format: &LogFormat, tx: Sender<RequestEntry>) -> Result<(), Error> {
+    let mut reader = BufReader::new(stream);
+    let mut frame = vec![];
+    loop {
+        frame.clear();
+        let Some(&first) = reader.fill_buf().await?.first() else { return Ok(()) };
+        if first.is_ascii_digit() {
+            let mut len = vec![];
+            (&mut reader).take(12).read_until(b' ', &mut len).await?;
+            if len.pop() != Some(b' ') {
+                bail!("message length not followed by a space");
+            }
+            let len: usize = std::str::from_utf8(&len)?.parse()?;
+            if len > MAX_FRAME {
+                bail!("message of {len} bytes");
+            }
+            frame.resize(len, 0);
+            reader.read_exact(&mut frame).await?;
+        } else {
+            (&mut reader).take(MAX_FRAME as u64).read_until(b'\n', &mut frame).await?;
+            while frame.last().is_some_and(|&b| b == b'\n' || b == b'\r' || b == 0) {
+                frame.pop();
+            }
+            if frame.is_empty() {
+                continue;
+            }
+        }
+        if !syslog_entry(&frame, format, &tx).await {
+            return Ok(());
+        }
+    }
+}
+
+#[test]
+fn test_syslog_message() {
+    let msg = syslog_message(b"<165>1 2024-01-30T09:00:00.003Z web1 nginx - ID47 [ex@32473 a=\"x\\]y\"][b@1 c=\"d\"] \xef\xbb\xbfhello").unwrap();
+    assert_eq!(msg, b"hello");
+    assert_eq!(syslog_message(b"<13>1 - - - - - - hi").unwrap(), b"hi");
+    assert_eq!(syslog_message(b"<13>1 - - - - - -").unwrap(), b"");
+    assert!(syslog_message(b"Jan 30 09:00:00 web1 nginx: hi").is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_ingest() {
+    use axum::{body::Body, http::Request};
+    use tokio::{io::AsyncWriteExt, net::UnixStream, sync::mpsc::channel};
+    use tower::util::ServiceExt;
+
+    let line = |uri: &str| format!("10.0.0.1 - - [30/Jan/2024:09:00:00 +0000] \"GET {uri} HTTP/1.1\" 200 5 \"-\" \"-\"");
+    let entry = |uri: &str| LogFormat::combined().parse(&line(uri)).unwrap().unwrap();
+    let (tx, mut rx) = channel::<RequestEntry>(16);
+    let mut next = async || rx.recv().await.unwrap().uri;
+
+    let router: Router = ingest_router(tx.clone());
+    let body = format!("{}\nnot json\n", serde_json::to_string(&entry("/a")).unwrap());
+    let request = Request::post("/ingest").header(CONTENT_TYPE, "application/x-ndjson").body(Body::from(body)).unwrap();
+    let response = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(next().await, "/a");
+
+    let batch = postcard::to_stdvec(&vec![entry("/b"), entry("/c")]).unwrap();
+    let request = Request::post("/ingest").header(CONTENT_TYPE, "application/postcard").body(Body::from(batch)).unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!((next().await, next().await), ("/b".into(), "/c".into()));
+
+    let path = std::env::temp_dir().join(format!("clog-ingest-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    spawn(serve_unix(tokio::net::UnixListener::bind(&path).unwrap(), tx.clone()));
+    let mut stream = UnixStream::connect(&path).await.unwrap();
+    for uri in ["/d", "/e"] {
+        let frame = postcard::to_stdvec(&entry(uri)).unwrap();
+        stream.write_all(&(frame.len() as u32).to_le_bytes()).await.unwrap();
+        stream.write_all(&frame).await.unwrap();
+    }
+    assert_eq!((next().await, next().await), ("/d".into(), "/e".into()));
+    let mut acked = 0;
+    while acked < 2 {
+        acked = stream.read_u64_le().await.unwrap();
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    spawn(serve_syslog_tcp(listener, Arc::new(LogFormat::combined()), tx));
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let msg = format!("<165>1 - web1 nginx - - - {}", line("/f"));
+    stream.write_all(format!("{} {msg}", msg.len()).as_bytes()).await.unwrap();
+    stream.write_all(format!("<165>1 - web1 nginx - - - {}\n", line("/g")).as_bytes()).await.unwrap();
+    assert_eq!((next().await, next().await), ("/f".into(), "/g".into()));
+
+    // a length without end closes the connection instead of being buffered
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(&[b'1'; 4096]).await.unwrap();
+    assert_eq!(stream.read(&mut [0; 16]).await.unwrap(), 0);
+}
@@ -1,14 +1,44 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use axum::{body::Bytes, extract::ws::{Message, WebSocket}};
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, WebSocketUpgrade, ws::{Message, WebSocket}},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::get,
+};
 use clog_core::PacketType;
 use clog_ws_api::{ClientMessage, ServerMessage};
 
-use clog_collector::{ClientHandle, LogCollector};
+use clog_collector::{ClientHandle, LogCollector, Partitions, Replicas};
 use tokio::{select, sync::broadcast, time::{interval, sleep, Interval}};
 
+pub mod ingest;
+
+enum Logs {
+    One(LogCollector),
+    Partitioned {
+        partitions: Arc<Partitions>,
+        allowed: Box<dyn Fn(&str) -> bool + Send + Sync>,
+    },
+}
+impl Logs {
+    async fn get(&self, partition: Option<&str>) -> Result<LogCollector, String> {
+        match (self, partition) {
+            (Logs::One(log), None) => Ok(log.clone()),
+            (Logs::One(_), Some(_)) => Err("no partitions".into()),
+            (Logs::Partitioned { .. }, None) => Err("partition required".into()),
+            (Logs::Partitioned { partitions, allowed }, Some(name)) => match allowed(name) {
+                true => partitions.collector(name).await.map_err(|e| e.to_string()),
+                false => Err(format!("no partition {name:?}")),
+            },
+        }
+    }
+}
+
 struct ClientState {
-    log: LogCollector,
+    log: Logs,
     handle: Option<ClientHandle>,
     ws: WebSocket,
     ping_timer: Interval,
@@ -35,8 +65,14 @@ impl ClientState {
 
                         }
                     },
-                    ClientMessage::SubScribeWithBacklog { backlog } => {
-                        self.handle = self.log.attach_with_backlog(backlog).await.ok();
+                    ClientMessage::SubScribeWithBacklog { backlog, partition } => {
+                        match self.log.get(partition.as_deref()).await {
+                            Ok(log) => self.handle = log.attach_with_backlog(backlog).await.ok(),
+                            Err(msg) => {
+                                self.handle = None;
+                                self.send_msg(ServerMessage::Error { msg }).await;
+                            }
+                        }
                     }
                 }
             }
@@ -78,6 +114,19 @@ impl ClientState {
 }
 
 pub async fn handle_ws(ws: WebSocket, log: LogCollector) {
+    run_ws(ws, Logs::One(log)).await
+}
+
+/// `handle_ws` for the partitions `allowed` returns true for, e.g. those of the tenant that opened the socket
+pub async fn handle_partitioned_ws(
+    ws: WebSocket,
+    partitions: Arc<Partitions>,
+    allowed: impl Fn(&str) -> bool + Send + Sync + 'static,
+) {
+    run_ws(ws, Logs::Partitioned { partitions, allowed: Box::new(allowed) }).await
+}
+
+async fn run_ws(ws: WebSocket, log: Logs) {
     let ping_timer = interval(Duration::from_secs(10));
     let mut state = ClientState { handle: None, ws, log, ping_timer, last_pong: 0, last_ping: 0, closed: false };
 
@@ -115,3 +164,19 @@ pub async fn handle_ws(ws: WebSocket, log: LogCollector) {
         }
     }
 }
+
+/// `GET /sources` lists the sources forwarding to `replicas` as JSON, `/ws/{source}` is `handle_ws` for one of them.
+pub fn replicas_router<S: Clone + Send + Sync + 'static>(replicas: Arc<Replicas>) -> Router<S> {
+    let list = replicas.clone();
+    Router::new()
+        .route("/sources", get(async move || match list.sources().await {
+            Ok(names) => Json(names).into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }))
+        .route("/ws/{source}", get(async move |Path(source): Path<String>, ws: WebSocketUpgrade| {
+            match replicas.collector(&source).await {
+                Ok(log) => ws.on_upgrade(move |ws| handle_ws(ws, log)),
+                Err(e) => (StatusCode::NOT_FOUND, e.to_string()).into_response(),
+            }
+        }))
+}